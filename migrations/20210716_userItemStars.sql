@@ -0,0 +1,8 @@
+CREATE TABLE user_item_stars (
+    item_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    starred_on INTEGER NOT NULL,
+    FOREIGN KEY (item_id) REFERENCES items (id),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    PRIMARY KEY (item_id, user_id)
+);
@@ -0,0 +1,16 @@
+ALTER TABLE users
+ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users
+ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE users
+SET is_admin = TRUE
+WHERE id = 1;
+CREATE TABLE invites (
+    code TEXT NOT NULL PRIMARY KEY,
+    created_by INTEGER NOT NULL,
+    created_on INTEGER NOT NULL,
+    used_by INTEGER,
+    used_on INTEGER,
+    FOREIGN KEY (created_by) REFERENCES users (id),
+    FOREIGN KEY (used_by) REFERENCES users (id)
+);
@@ -0,0 +1,13 @@
+CREATE TABLE sessions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    token_hash TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    created_on INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    user_agent TEXT,
+    ip TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    unique (token_hash)
+);
+CREATE INDEX IF NOT EXISTS sessions_user_idx ON sessions (user_id);
+CREATE INDEX IF NOT EXISTS sessions_last_seen_idx ON sessions (last_seen);
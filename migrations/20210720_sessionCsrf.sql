@@ -0,0 +1,4 @@
+ALTER TABLE sessions
+ADD COLUMN csrf_token TEXT NOT NULL DEFAULT '';
+UPDATE sessions
+SET csrf_token = lower(hex(randomblob(16)));
@@ -0,0 +1,3 @@
+ALTER TABLE users
+ADD COLUMN fever_api_key TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS users__fever_api_key_udx ON users (fever_api_key);
@@ -0,0 +1,13 @@
+CREATE TABLE output_feeds (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    token TEXT NOT NULL,
+    title TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    value TEXT,
+    format TEXT NOT NULL DEFAULT 'rss',
+    created_on INTEGER NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    unique (token)
+);
+CREATE INDEX IF NOT EXISTS output_feeds_user_idx ON output_feeds (user_id);
@@ -0,0 +1,32 @@
+CREATE TABLE filter_rules (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    field TEXT NOT NULL,
+    match_kind TEXT NOT NULL,
+    pattern TEXT NOT NULL,
+    scope TEXT NOT NULL DEFAULT 'all',
+    scope_value TEXT,
+    action TEXT NOT NULL,
+    action_value TEXT,
+    created_on INTEGER NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+CREATE INDEX IF NOT EXISTS filter_rules_user_idx ON filter_rules (user_id);
+CREATE TABLE user_item_tags (
+    item_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    tagged_on INTEGER NOT NULL,
+    FOREIGN KEY (item_id) REFERENCES items (id),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    PRIMARY KEY (item_id, user_id, tag)
+);
+CREATE INDEX IF NOT EXISTS user_item_tags_tag_idx ON user_item_tags (user_id, tag);
+CREATE TABLE user_item_hides (
+    item_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    hidden_on INTEGER NOT NULL,
+    FOREIGN KEY (item_id) REFERENCES items (id),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    PRIMARY KEY (item_id, user_id)
+);
@@ -0,0 +1,21 @@
+CREATE TABLE user_item_notes (
+    item_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    note TEXT NOT NULL,
+    updated_on INTEGER NOT NULL,
+    FOREIGN KEY (item_id) REFERENCES items (id),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    PRIMARY KEY (item_id, user_id)
+);
+CREATE TABLE user_item_highlights (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    item_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    quote TEXT NOT NULL,
+    start_offset INTEGER NOT NULL,
+    end_offset INTEGER NOT NULL,
+    created_on INTEGER NOT NULL,
+    FOREIGN KEY (item_id) REFERENCES items (id),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+CREATE INDEX IF NOT EXISTS user_item_highlights_item_idx ON user_item_highlights (user_id, item_id);
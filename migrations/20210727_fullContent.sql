@@ -0,0 +1,2 @@
+ALTER TABLE user_subscription_metas
+ADD COLUMN fetch_full_content BOOLEAN NOT NULL DEFAULT FALSE;
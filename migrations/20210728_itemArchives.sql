@@ -0,0 +1,11 @@
+CREATE TABLE item_archives (
+    item_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    html TEXT NOT NULL,
+    archived_on INTEGER NOT NULL,
+    FOREIGN KEY (item_id) REFERENCES items (id),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    PRIMARY KEY (item_id, user_id)
+);
+ALTER TABLE users
+ADD COLUMN archive_starred BOOLEAN NOT NULL DEFAULT FALSE;
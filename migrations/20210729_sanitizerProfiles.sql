@@ -0,0 +1,2 @@
+ALTER TABLE user_subscription_metas
+ADD COLUMN sanitizer_profile TEXT NOT NULL DEFAULT 'strict';
@@ -0,0 +1,7 @@
+CREATE TABLE subscription_icons (
+    subscription_id INTEGER PRIMARY KEY NOT NULL,
+    content_type TEXT,
+    data BLOB,
+    fetched_on INTEGER NOT NULL,
+    FOREIGN KEY (subscription_id) REFERENCES subscriptions (id)
+);
@@ -0,0 +1,11 @@
+CREATE TABLE pruned_items (
+    subscription_id INTEGER NOT NULL,
+    title_hash TEXT NOT NULL,
+    pruned_on INTEGER NOT NULL,
+    FOREIGN KEY (subscription_id) REFERENCES subscriptions (id),
+    PRIMARY KEY (subscription_id, title_hash)
+);
+ALTER TABLE subscriptions
+ADD COLUMN retention_max_age_days INTEGER;
+ALTER TABLE subscriptions
+ADD COLUMN retention_max_items INTEGER;
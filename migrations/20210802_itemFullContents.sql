@@ -0,0 +1,2 @@
+ALTER TABLE items
+ADD COLUMN full_contents TEXT;
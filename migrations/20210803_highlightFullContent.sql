@@ -0,0 +1,2 @@
+ALTER TABLE user_item_highlights
+ADD COLUMN full_content BOOLEAN NOT NULL DEFAULT FALSE;
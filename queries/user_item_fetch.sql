@@ -0,0 +1,29 @@
+SELECT i.id,
+    i.subscription_id,
+    i.contents,
+    i.title,
+    i.pub_date,
+    i.link,
+    i.author,
+    i.description,
+    i.comments,
+    EXISTS (
+        SELECT 1
+        FROM user_item_reads r
+        WHERE r.user_id = $1
+            AND r.item_id = i.id
+    ) AS "is_read!: bool",
+    EXISTS (
+        SELECT 1
+        FROM user_item_stars s
+        WHERE s.user_id = $1
+            AND s.item_id = i.id
+    ) AS "is_starred!: bool"
+FROM items i
+WHERE i.id = $2
+    AND EXISTS (
+        SELECT 1
+        from user_subscription_metas m
+        WHERE m.user_id = $1
+            AND m.subscription_id = i.subscription_id
+    );
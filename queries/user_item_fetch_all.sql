@@ -6,7 +6,19 @@ SELECT id,
     i.link,
     i.author,
     i.description,
-    i.comments
+    i.comments,
+    EXISTS (
+        SELECT 1
+        FROM user_item_reads r
+        WHERE r.user_id = $1
+            AND r.item_id = i.id
+    ) AS "is_read!: bool",
+    EXISTS (
+        SELECT 1
+        FROM user_item_stars s
+        WHERE s.user_id = $1
+            AND s.item_id = i.id
+    ) AS "is_starred!: bool"
 FROM items i
 WHERE (
         $4 IS NULL
@@ -31,4 +43,22 @@ WHERE (
                 OR m.title = $3
             )
     )
-ORDER BY i.pub_date desc;
\ No newline at end of file
+    AND (
+        $7 IS NULL
+        OR EXISTS (
+            SELECT 1
+            FROM user_item_tags t
+            WHERE t.user_id = $1
+                AND t.item_id = i.id
+                AND t.tag = $7
+        )
+    )
+    AND NOT EXISTS (
+        SELECT 1
+        FROM user_item_hides h
+        WHERE h.user_id = $1
+            AND h.item_id = i.id
+    )
+ORDER BY i.pub_date desc,
+    i.id desc
+LIMIT $5 OFFSET $6;
@@ -0,0 +1,99 @@
+SELECT i.id,
+    i.subscription_id,
+    i.contents,
+    i.title,
+    i.pub_date,
+    i.link,
+    i.author,
+    i.description,
+    i.comments,
+    EXISTS (
+        SELECT 1
+        FROM user_item_reads r
+        WHERE r.user_id = $1
+            AND r.item_id = i.id
+    ) AS "is_read!: bool",
+    EXISTS (
+        SELECT 1
+        FROM user_item_stars s
+        WHERE s.user_id = $1
+            AND s.item_id = i.id
+    ) AS "is_starred!: bool"
+FROM items i
+WHERE EXISTS (
+        SELECT 1
+        from user_subscription_metas m
+        WHERE m.user_id = $1
+            AND m.subscription_id = i.subscription_id
+            AND (
+                $2 IS NULL
+                OR m.subscription_id = $2
+            )
+            AND (
+                $3 IS NULL
+                OR m.category = $3
+            )
+    )
+    AND (
+        NOT $4
+        OR EXISTS (
+            SELECT 1
+            FROM user_item_stars s
+            WHERE s.user_id = $1
+                AND s.item_id = i.id
+        )
+    )
+    AND (
+        NOT $5
+        OR NOT EXISTS (
+            SELECT 1
+            FROM user_item_reads r
+            WHERE r.user_id = $1
+                AND r.item_id = i.id
+        )
+    )
+    AND (
+        $6 IS NULL
+        OR i.pub_date > $6
+    )
+    AND (
+        $7 IS NULL
+        OR i.pub_date < $7
+    )
+    AND (
+        $11 IS NULL
+        OR i.title LIKE '%' || $11 || '%'
+        OR i.description LIKE '%' || $11 || '%'
+        OR EXISTS (
+            SELECT 1
+            FROM user_item_tags t
+            WHERE t.user_id = $1
+                AND t.item_id = i.id
+                AND t.tag LIKE '%' || $11 || '%'
+        )
+    )
+    AND (
+        $12 IS NULL
+        OR EXISTS (
+            SELECT 1
+            FROM user_item_tags t
+            WHERE t.user_id = $1
+                AND t.item_id = i.id
+                AND t.tag = $12
+        )
+    )
+    AND NOT EXISTS (
+        SELECT 1
+        FROM user_item_hides h
+        WHERE h.user_id = $1
+            AND h.item_id = i.id
+    )
+ORDER BY CASE
+        WHEN $8 THEN i.pub_date
+    END ASC,
+    CASE
+        WHEN $8 THEN i.id
+    END ASC,
+    i.pub_date DESC,
+    i.id DESC
+LIMIT $9 OFFSET $10;
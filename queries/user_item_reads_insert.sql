@@ -16,6 +16,29 @@ WHERE pub_date <= $2
             )
             AND (
                 $5 IS NULL
-                OR m.category = $4
+                OR m.title = $5
             )
+            AND (
+                $6 IS NULL
+                OR m.category = $6
+            )
+    )
+    AND (
+        $7 IS NULL
+        OR EXISTS (
+            SELECT 1
+            FROM user_item_tags t
+            WHERE t.user_id = $1
+                AND t.item_id = i.id
+                AND t.tag = $7
+        )
+    )
+    AND (
+        NOT $8
+        OR EXISTS (
+            SELECT 1
+            FROM user_item_stars s
+            WHERE s.user_id = $1
+                AND s.item_id = i.id
+        )
     )
\ No newline at end of file
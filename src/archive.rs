@@ -0,0 +1,245 @@
+use std::collections::{HashMap, HashSet};
+
+use ammonia::{Builder, UrlRelative};
+use chrono::Utc;
+use color_eyre::{eyre::eyre, Result};
+use html5ever::{parse_document, tendril::TendrilSink};
+use markup5ever_rcdom::{Handle, RcDom};
+use sqlx::SqlitePool;
+use tokio::task::spawn_blocking;
+use tracing::{info, instrument, warn};
+use url::Url;
+
+use crate::{
+    dto::ItemArchive,
+    image_proxy::{ImageProxy, IMAGE_TYPES},
+    readability::{
+        self, attribute, descendants, remove_attribute, serialize_children, set_attribute, tag_name,
+    },
+};
+
+const MAX_IMAGE_SIZE: usize = 2 * 1024 * 1024;
+const MAX_IMAGES: usize = 40;
+/// Images stop being inlined once the archive gets this big, each image is fetched with what
+/// is left as its limit
+const MAX_ARCHIVE_SIZE: usize = 20 * 1024 * 1024;
+/// Starred items archived on each poll, the rest wait for the next polls
+const STARRED_PER_PASS: i64 = 5;
+
+/// A self-contained snapshot of the page: the article when one is found or else the whole
+/// page, sanitized, with the images inlined as data urls
+#[instrument]
+pub async fn archive_page(link: &str) -> Result<String> {
+    let (page, base) = readability::fetch_page(link).await?;
+    // Big pages take a while to read, off the workers serving the requests
+    let (sanitized, sources) = spawn_blocking(move || {
+        let article = readability::extract(&page, &base).unwrap_or(page);
+        let sanitized = sanitize(&article, &base);
+        let sources = image_sources(&sanitized);
+        (sanitized, sources)
+    })
+    .await?;
+    let mut images = HashMap::new();
+    let mut size = sanitized.len();
+    for source in sources.into_iter().take(MAX_IMAGES) {
+        // The data urls are base64, 4 bytes for every 3 bytes of the image
+        let max_size = (MAX_ARCHIVE_SIZE.saturating_sub(size) / 4 * 3).min(MAX_IMAGE_SIZE);
+        if max_size == 0 {
+            warn!("Archive of {} is full, leaving the other images out", link);
+            break;
+        }
+        match inline_image(&source, max_size).await {
+            Ok(data_url) if size + data_url.len() <= MAX_ARCHIVE_SIZE => {
+                size += data_url.len();
+                images.insert(source, data_url);
+            }
+            Ok(_) => warn!("Archive of {} has no room for the image {}", link, source),
+            Err(e) => warn!("Could not inline the image {}: {:?}", source, e),
+        }
+    }
+    Ok(spawn_blocking(move || replace_image_sources(&sanitized, &images)).await?)
+}
+
+/// Archives the stars of the users who asked for it. When the page can not be fetched the
+/// content from the feed is archived instead, so a dead link is not retried forever.
+#[instrument(skip(pool))]
+pub async fn archive_starred(pool: &SqlitePool) -> Result<()> {
+    for missing in ItemArchive::fetch_missing_starred(STARRED_PER_PASS, pool).await? {
+        let html = match archive_page(&missing.link).await {
+            Ok(html) => html,
+            Err(e) => {
+                warn!("Archiving the feed content of {}: {:?}", missing.link, e);
+                let content = missing
+                    .contents
+                    .as_deref()
+                    .or_else(|| missing.description.as_deref())
+                    .unwrap_or_default();
+                match Url::parse(&missing.link) {
+                    Ok(base) => sanitize(content, &base),
+                    Err(_) => ammonia::clean(content),
+                }
+            }
+        };
+        info!(item_id = missing.item_id, "Archived starred item");
+        ItemArchive::save(
+            &missing.user_id,
+            missing.item_id,
+            &html,
+            Utc::now().timestamp(),
+            pool,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Relative urls are made absolute so the links of the snapshot keep working
+fn sanitize(html: &str, base: &Url) -> String {
+    Builder::default()
+        .url_relative(UrlRelative::RewriteWithBase(base.clone()))
+        .clean(html)
+        .to_string()
+}
+
+/// The images to inline, each once in the order of the page
+fn image_sources(html: &str) -> Vec<String> {
+    let dom = parse_document(RcDom::default(), Default::default()).one(html);
+    let mut seen = HashSet::new();
+    descendants(&dom.document)
+        .iter()
+        .filter(|x| tag_name(x).as_deref() == Some("img"))
+        .filter_map(|x| attribute(x, "src"))
+        .filter(|x| x.starts_with("http://") || x.starts_with("https://"))
+        .filter(|x| seen.insert(x.clone()))
+        .collect()
+}
+
+/// Fetched like the pages, so the images can not point to our own network either
+async fn inline_image(source: &str, max_size: usize) -> Result<String> {
+    let (image, _, content_type) = readability::fetch(source, max_size).await?;
+    let content_type = content_type.unwrap_or_default();
+    if !IMAGE_TYPES.contains(&content_type.as_str()) {
+        return Err(eyre!("{} is not an image but {:?}", source, content_type));
+    }
+    Ok(format!(
+        "data:{};base64,{}",
+        content_type,
+        base64::encode(&image)
+    ))
+}
+
+fn replace_image_sources(html: &str, images: &HashMap<String, String>) -> String {
+    rewrite_images(html, |image| {
+        if let Some(data_url) = attribute(image, "src").and_then(|x| images.get(&x)) {
+            set_attribute(image, "src", data_url);
+        }
+    })
+}
+
+/// The images that did not fit in the archive are loaded through the image proxy, so reading
+/// an archive does not reach out to the origin. Done when showing the archive, the proxy signs
+/// with a secret that can change.
+pub fn proxy_images(html: &str, image_proxy: &ImageProxy) -> String {
+    rewrite_images(html, |image| {
+        if let Some(src) = attribute(image, "src") {
+            if !src.starts_with("data:image/") {
+                match image_proxy.proxied_url(&src) {
+                    Some(proxied) => set_attribute(image, "src", &proxied),
+                    None => remove_attribute(image, "src"),
+                }
+            }
+        }
+        if let Some(srcset) = attribute(image, "srcset") {
+            match image_proxy.proxied_srcset(&srcset) {
+                Some(proxied) => set_attribute(image, "srcset", &proxied),
+                None => remove_attribute(image, "srcset"),
+            }
+        }
+    })
+}
+
+fn rewrite_images(html: &str, rewrite: impl Fn(&Handle)) -> String {
+    let dom = parse_document(RcDom::default(), Default::default()).one(html);
+    let elements = descendants(&dom.document);
+    for image in elements
+        .iter()
+        .filter(|x| tag_name(x).as_deref() == Some("img"))
+    {
+        rewrite(image);
+    }
+    elements
+        .iter()
+        .find(|x| tag_name(x).as_deref() == Some("body"))
+        .and_then(serialize_children)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let base = Url::parse("https://example.com/posts/1").unwrap();
+        let sanitized = sanitize(
+            r#"<p onclick="evil()">Hi <img src="/a.png"><script>evil()</script></p>"#,
+            &base,
+        );
+        assert_eq!(
+            sanitized,
+            r#"<p>Hi <img src="https://example.com/a.png"></p>"#
+        );
+        assert_eq!(image_sources(&sanitized), vec!["https://example.com/a.png"]);
+    }
+
+    #[test]
+    fn test_image_sources() {
+        assert_eq!(
+            image_sources(
+                r#"<p><img src="https://example.com/a.png"><img src="data:image/png;base64,AAAA"><img src="https://example.com/b.png"><img src="https://example.com/a.png"></p>"#
+            ),
+            vec!["https://example.com/a.png", "https://example.com/b.png"]
+        );
+    }
+
+    #[test]
+    fn test_replace_image_sources() {
+        let mut images = HashMap::new();
+        images.insert(
+            "https://example.com/a.png".to_string(),
+            "data:image/png;base64,AAAA".to_string(),
+        );
+        assert_eq!(
+            replace_image_sources(
+                r#"<p><img src="https://example.com/a.png"><img src="https://example.com/b.png"></p>"#,
+                &images
+            ),
+            r#"<p><img src="data:image/png;base64,AAAA"><img src="https://example.com/b.png"></p>"#
+        );
+    }
+
+    #[test]
+    fn test_proxy_images() {
+        let image_proxy = ImageProxy::new(&crate::settings::ImageProxySettings {
+            secret: Some("secret".to_string()),
+            cache_dir: std::env::temp_dir()
+                .join("rss-archive-test")
+                .to_string_lossy()
+                .to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let html = proxy_images(
+            r#"<p><img src="data:image/png;base64,AAAA"><img src="https://example.com/b.png" srcset="https://example.com/b2.png 2x"><img src="javascript:evil()"></p>"#,
+            &image_proxy,
+        );
+        assert!(html.contains(r#"<img src="data:image/png;base64,AAAA">"#));
+        assert!(
+            html.contains(r#"src="/proxy/image?url=https%3A%2F%2Fexample.com%2Fb.png&amp;sig="#)
+        );
+        assert!(html
+            .contains(r#"srcset="/proxy/image?url=https%3A%2F%2Fexample.com%2Fb2.png&amp;sig="#));
+        assert!(!html.contains("https://example.com"));
+        assert!(!html.contains("javascript"));
+    }
+}
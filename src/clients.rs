@@ -1,18 +1,43 @@
 use color_eyre::eyre::Result;
 use sqlx::sqlite::SqlitePool;
 
-use crate::settings::Settings;
+use crate::{image_proxy::ImageProxy, sanitizer::Sanitizer, settings::Settings};
 
 #[derive(Clone, Debug)]
 pub struct Clients {
     pub pool: SqlitePool,
     pub settings: Settings,
+    pub image_proxy: ImageProxy,
+    pub sanitizer: Sanitizer,
 }
 
 impl Clients {
     pub async fn new(settings: Settings) -> Result<Clients> {
+        let image_proxy = ImageProxy::new(&settings.image_proxy)?;
         Ok(Clients {
             pool: SqlitePool::connect(&settings.db_name).await?,
+            sanitizer: Sanitizer::new(&settings.sanitizer, image_proxy.clone()),
+            image_proxy,
+            settings,
+        })
+    }
+}
+
+#[cfg(test)]
+impl Clients {
+    /// A fresh database with the migrations applied, for the tests going through the queries
+    pub async fn in_memory(settings: Settings) -> Result<Clients> {
+        // Every connection to `:memory:` opens its own database
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+        sqlx::migrate!("./migrations").run(&pool).await?;
+        let image_proxy = ImageProxy::new(&settings.image_proxy)?;
+        Ok(Clients {
+            pool,
+            sanitizer: Sanitizer::new(&settings.sanitizer, image_proxy.clone()),
+            image_proxy,
             settings,
         })
     }
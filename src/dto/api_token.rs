@@ -0,0 +1,148 @@
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Executor, Sqlite};
+use tracing::instrument;
+
+use super::UserId;
+
+/// What a token is allowed to do, write includes read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ApiScope {
+    Read,
+    Write,
+}
+
+impl ApiScope {
+    pub fn allows(&self, needed: ApiScope) -> bool {
+        *self == ApiScope::Write || needed == ApiScope::Read
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Write => "write",
+        }
+    }
+}
+
+/// A personal token for scripts and apps, only the hash of the token is stored
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: UserId,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub scope: ApiScope,
+    pub created_on: i64,
+    pub last_used: Option<i64>,
+}
+
+impl ApiToken {
+    #[instrument(skip(executor, token_hash))]
+    pub async fn insert<'a>(
+        user_id: &UserId,
+        name: &str,
+        token_hash: &str,
+        scope: ApiScope,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            r#"INSERT INTO api_tokens (user_id, name, token_hash, scope, created_on)
+            VALUES ($1, $2, $3, $4, $5)"#,
+            user_id,
+            name,
+            token_hash,
+            scope,
+            now
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor, token_hash))]
+    pub async fn fetch<'a>(
+        token_hash: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, user_id as 'user_id:UserId', name, token_hash, scope as 'scope:ApiScope', created_on, last_used
+            FROM api_tokens
+            WHERE token_hash = $1"#,
+            token_hash
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, user_id as 'user_id:UserId', name, token_hash, scope as 'scope:ApiScope', created_on, last_used
+            FROM api_tokens
+            WHERE user_id = $1
+            ORDER BY created_on DESC"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn touch<'a>(
+        id: i64,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(r#"UPDATE api_tokens SET last_used = $1 WHERE id = $2"#, now, id)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns false if the user has no such token
+    #[instrument(skip(executor))]
+    pub async fn delete<'a>(
+        user_id: &UserId,
+        id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<bool> {
+        let deleted = query!(
+            r#"DELETE FROM api_tokens WHERE id = $1 AND user_id = $2"#,
+            id,
+            user_id
+        )
+        .execute(executor)
+        .await?
+        .rows_affected();
+        Ok(deleted > 0)
+    }
+    /// Drops the tokens of the user with that name, for the clients that get a new token on
+    /// every login
+    #[instrument(skip(executor))]
+    pub async fn delete_named<'a>(
+        user_id: &UserId,
+        name: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            r#"DELETE FROM api_tokens WHERE user_id = $1 AND name = $2"#,
+            user_id,
+            name
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
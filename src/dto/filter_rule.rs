@@ -0,0 +1,150 @@
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Executor, Sqlite};
+use tracing::instrument;
+
+use super::UserId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RuleField {
+    Title,
+    Author,
+    Description,
+    Link,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RuleMatch {
+    /// Case insensitive substring
+    Keyword,
+    Regex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RuleScope {
+    All,
+    Category,
+    Subscription,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Read,
+    Star,
+    Tag,
+    Hide,
+}
+
+/// A rule a user set up to act on items as they come in, see `crate::rules`
+#[derive(Debug, Clone, Serialize)]
+pub struct FilterRule {
+    pub id: i64,
+    pub user_id: UserId,
+    pub field: RuleField,
+    pub match_kind: RuleMatch,
+    pub pattern: String,
+    pub scope: RuleScope,
+    /// The category or the subscription id, depending on the scope
+    pub scope_value: Option<String>,
+    pub action: RuleAction,
+    /// The tag for `RuleAction::Tag`
+    pub action_value: Option<String>,
+    pub created_on: i64,
+}
+
+impl FilterRule {
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(skip(executor))]
+    pub async fn insert<'a>(
+        user_id: &UserId,
+        field: RuleField,
+        match_kind: RuleMatch,
+        pattern: &str,
+        scope: RuleScope,
+        scope_value: Option<&str>,
+        action: RuleAction,
+        action_value: Option<&str>,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<i64> {
+        let id = query!(
+            r#"INSERT INTO filter_rules (user_id, field, match_kind, pattern, scope, scope_value, action, action_value, created_on)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+            user_id,
+            field,
+            match_kind,
+            pattern,
+            scope,
+            scope_value,
+            action,
+            action_value,
+            now
+        )
+        .execute(executor)
+        .await?
+        .last_insert_rowid();
+        Ok(id)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, user_id as 'user_id:UserId', field as 'field:RuleField', match_kind as 'match_kind:RuleMatch', pattern,
+                scope as 'scope:RuleScope', scope_value, action as 'action:RuleAction', action_value, created_on
+            FROM filter_rules
+            WHERE user_id = $1
+            ORDER BY created_on ASC"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    /// The rules of every user, for the polling loop
+    #[instrument(skip(executor))]
+    pub async fn fetch_everyone<'a>(
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, user_id as 'user_id:UserId', field as 'field:RuleField', match_kind as 'match_kind:RuleMatch', pattern,
+                scope as 'scope:RuleScope', scope_value, action as 'action:RuleAction', action_value, created_on
+            FROM filter_rules
+            ORDER BY user_id, created_on ASC"#
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    /// Returns false if the user has no such rule
+    #[instrument(skip(executor))]
+    pub async fn delete<'a>(
+        user_id: &UserId,
+        id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<bool> {
+        let deleted = query!(
+            r#"DELETE FROM filter_rules WHERE id = $1 AND user_id = $2"#,
+            id,
+            user_id
+        )
+        .execute(executor)
+        .await?
+        .rows_affected();
+        Ok(deleted > 0)
+    }
+}
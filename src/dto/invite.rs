@@ -0,0 +1,73 @@
+use color_eyre::Result;
+use sqlx::{query, query_as, Executor, Sqlite};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::UserId;
+
+/// Invite codes let someone sign up while open signups are turned off
+#[derive(Debug, Clone)]
+pub struct Invite {
+    pub code: String,
+    pub created_by: UserId,
+    pub created_on: i64,
+}
+
+impl Invite {
+    #[instrument(skip(executor))]
+    pub async fn insert<'a>(
+        created_by: &UserId,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Self> {
+        let code = Uuid::new_v4().to_simple().to_string();
+        query!(
+            r#"INSERT INTO invites (code, created_by, created_on) VALUES ($1, $2, $3)"#,
+            code,
+            created_by,
+            now
+        )
+        .execute(executor)
+        .await?;
+        Ok(Self {
+            code,
+            created_by: created_by.clone(),
+            created_on: now,
+        })
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_all_unused<'a>(
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT code, created_by as 'created_by:UserId', created_on
+            FROM invites
+            WHERE used_on IS NULL
+            ORDER BY created_on DESC"#
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    /// Marks the invite as used, returning false when the code is unknown or already used
+    #[instrument(skip(executor))]
+    pub async fn redeem<'a>(
+        code: &str,
+        used_by: &UserId,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<bool> {
+        let result = query!(
+            r#"UPDATE invites SET used_by = $1, used_on = $2 WHERE code = $3 AND used_on IS NULL"#,
+            used_by,
+            now,
+            code
+        )
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+}
@@ -1,11 +1,13 @@
 use color_eyre::eyre::Result;
-use sqlx::{query_as, query_file_as, Executor, Sqlite};
+use serde::Serialize;
+use sqlx::{query, query_file_as, Executor, Sqlite};
 use tracing::instrument;
+use utoipa::ToSchema;
 
 use super::UserId;
 use crate::server::from_requests::user_preferences::{FilterItems, ShowUnreads};
-/// Items are the rss articles
-#[derive(Debug, Clone)]
+/// Items are the rss articles, along with the read/starred state for the user fetching them
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct Item {
     pub id: i64,
     pub subscription_id: i64,
@@ -16,21 +18,35 @@ pub struct Item {
     pub description: Option<String>,
     pub contents: Option<String>,
     pub comments: Option<String>,
+    pub is_read: bool,
+    pub is_starred: bool,
+}
+
+/// Which items a client of the sync apis wants, all the filters are optional
+#[derive(Debug, Clone, Default)]
+pub struct ItemStream {
+    pub subscription_id: Option<i64>,
+    pub category: Option<String>,
+    pub starred_only: bool,
+    pub unread_only: bool,
+    pub newer_than: Option<i64>,
+    pub older_than: Option<i64>,
+    pub oldest_first: bool,
+    /// Text the title, the description or a tag has to contain
+    pub search: Option<String>,
+    pub tag: Option<String>,
 }
 
 impl Item {
     #[instrument(skip(executor))]
     pub async fn fetch<'a>(
+        user_id: &UserId,
         id: i64,
         executor: impl Executor<'a, Database = Sqlite>,
     ) -> Result<Option<Self>> {
-        let answer = query_as!(
-            Self,
-            r#"SELECT id, subscription_id, title, pub_date,  link, author, description,contents, comments FROM items where id = ?"#,
-            id
-        )
-        .fetch_optional(executor)
-        .await?;
+        let answer = query_file_as!(Self, "queries/user_item_fetch.sql", user_id, id)
+            .fetch_optional(executor)
+            .await?;
         Ok(answer)
     }
     #[instrument(skip(executor))]
@@ -39,8 +55,22 @@ impl Item {
         filter_items: &FilterItems,
         show_unreads: &ShowUnreads,
         executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        Self::fetch_page(user_id, filter_items, show_unreads, -1, 0, executor).await
+    }
+
+    /// Newest first, a negative limit means no limit
+    #[instrument(skip(executor))]
+    pub async fn fetch_page<'a>(
+        user_id: &UserId,
+        filter_items: &FilterItems,
+        show_unreads: &ShowUnreads,
+        limit: i64,
+        offset: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
     ) -> Result<Vec<Self>> {
         let (id, title) = filter_items.as_items();
+        let tag = filter_items.tag();
         let show_unreads = show_unreads.query_value();
         let answer = query_file_as!(
             Self,
@@ -48,10 +78,154 @@ impl Item {
             user_id,
             id,
             title,
-            show_unreads
+            show_unreads,
+            limit,
+            offset,
+            tag
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_stream<'a>(
+        user_id: &UserId,
+        stream: &ItemStream,
+        limit: i64,
+        offset: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_file_as!(
+            Self,
+            "queries/user_item_fetch_stream.sql",
+            user_id,
+            stream.subscription_id,
+            stream.category,
+            stream.starred_only,
+            stream.unread_only,
+            stream.newer_than,
+            stream.older_than,
+            stream.oldest_first,
+            limit,
+            offset,
+            stream.search,
+            stream.tag
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    /// Walks the items by id, after `since_id` oldest first or else before `max_id` newest first
+    #[instrument(skip(executor))]
+    pub async fn fetch_id_range<'a>(
+        user_id: &UserId,
+        since_id: Option<i64>,
+        max_id: Option<i64>,
+        limit: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_file_as!(
+            Self,
+            "queries/user_item_fetch_range.sql",
+            user_id,
+            since_id,
+            max_id,
+            limit
         )
         .fetch_all(executor)
         .await?;
         Ok(answer)
     }
+
+    #[instrument(skip(executor))]
+    pub async fn count<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<i64> {
+        let record = query!(
+            r#"SELECT COUNT(*) as "count!: i64"
+            FROM items i
+            INNER JOIN user_subscription_metas m ON m.subscription_id = i.subscription_id
+            WHERE m.user_id = $1"#,
+            user_id
+        )
+        .fetch_one(executor)
+        .await?;
+        Ok(record.count)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_unread_ids<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<i64>> {
+        let records = query!(
+            r#"SELECT i.id
+            FROM items i
+            INNER JOIN user_subscription_metas m ON m.subscription_id = i.subscription_id
+            WHERE m.user_id = $1
+                AND NOT EXISTS (
+                    SELECT 1 FROM user_item_reads r WHERE r.user_id = $1 AND r.item_id = i.id
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM user_item_hides h WHERE h.user_id = $1 AND h.item_id = i.id
+                )
+            ORDER BY i.id"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(records.into_iter().map(|record| record.id).collect())
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_starred_ids<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<i64>> {
+        let records = query!(
+            r#"SELECT i.id
+            FROM items i
+            INNER JOIN user_subscription_metas m ON m.subscription_id = i.subscription_id
+            INNER JOIN user_item_stars s ON s.item_id = i.id AND s.user_id = m.user_id
+            WHERE m.user_id = $1
+            ORDER BY i.id"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(records.into_iter().map(|record| record.id).collect())
+    }
+
+    /// The article downloaded from the link, kept next to the contents of the feed
+    #[instrument(skip(executor))]
+    pub async fn fetch_full_contents<'a>(
+        id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<String>> {
+        let record = query!(r#"SELECT full_contents FROM items WHERE id = $1"#, id)
+            .fetch_optional(executor)
+            .await?;
+        Ok(record.and_then(|record| record.full_contents))
+    }
+
+    /// Items are shared by the subscribers, the contents of the feed stay for the ones who do
+    /// not want the full article
+    #[instrument(skip(full_contents, executor))]
+    pub async fn update_full_contents<'a>(
+        id: i64,
+        full_contents: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            r#"UPDATE items SET full_contents = $1 WHERE id = $2"#,
+            full_contents,
+            id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
 }
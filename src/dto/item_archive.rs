@@ -0,0 +1,109 @@
+use color_eyre::Result;
+use sqlx::{query, query_as, Executor, Sqlite};
+use tracing::instrument;
+
+use super::UserId;
+
+/// A snapshot of the page an item links to, kept for when the page is gone. The html is
+/// sanitized and the images are inlined, see `crate::archive`.
+#[derive(Debug, Clone)]
+pub struct ItemArchive {
+    pub item_id: i64,
+    pub html: String,
+    pub archived_on: i64,
+}
+
+/// A starred item of a user who wants their stars archived, with nothing archived yet
+#[derive(Debug, Clone)]
+pub struct MissingArchive {
+    pub user_id: UserId,
+    pub item_id: i64,
+    pub link: String,
+    pub contents: Option<String>,
+    pub description: Option<String>,
+}
+
+impl ItemArchive {
+    #[instrument(skip(executor))]
+    pub async fn fetch<'a>(
+        user_id: &UserId,
+        item_id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT item_id, html, archived_on FROM item_archives
+            WHERE user_id = $1 AND item_id = $2"#,
+            user_id,
+            item_id
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn exists<'a>(
+        user_id: &UserId,
+        item_id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<bool> {
+        let record = query!(
+            r#"SELECT EXISTS (
+                SELECT 1 FROM item_archives WHERE user_id = $1 AND item_id = $2
+            ) as "exists!: bool""#,
+            user_id,
+            item_id
+        )
+        .fetch_one(executor)
+        .await?;
+        Ok(record.exists)
+    }
+
+    /// Archiving again replaces the snapshot
+    #[instrument(skip(html, executor))]
+    pub async fn save<'a>(
+        user_id: &UserId,
+        item_id: i64,
+        html: &str,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            r#"INSERT INTO item_archives (item_id, user_id, html, archived_on) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (item_id, user_id) DO UPDATE SET html = excluded.html, archived_on = excluded.archived_on"#,
+            item_id,
+            user_id,
+            html,
+            now
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_missing_starred<'a>(
+        limit: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<MissingArchive>> {
+        let answer = query_as!(
+            MissingArchive,
+            r#"SELECT s.user_id as 'user_id:UserId', s.item_id, i.link, i.contents, i.description
+            FROM user_item_stars s
+            INNER JOIN users u ON u.id = s.user_id
+            INNER JOIN items i ON i.id = s.item_id
+            WHERE u.archive_starred
+                AND NOT u.disabled
+                AND NOT EXISTS (
+                    SELECT 1 FROM item_archives a WHERE a.user_id = s.user_id AND a.item_id = s.item_id
+                )
+            ORDER BY s.starred_on DESC
+            LIMIT $1"#,
+            limit
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+}
@@ -2,6 +2,8 @@ use color_eyre::eyre::Result;
 use sqlx::{query, Executor, Sqlite};
 use tracing::instrument;
 
+use super::title_hash;
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ItemInsert {
     pub subscription_id: i64,
@@ -15,11 +17,33 @@ pub struct ItemInsert {
 }
 
 impl ItemInsert {
+    /// The id of the new item, none when the item was already there or was pruned
     #[instrument(skip(executor))]
-    pub async fn insert<'a>(&self, executor: impl Executor<'a, Database = Sqlite>) -> Result<()> {
-        query!("INSERT INTO items (subscription_id, title, link, pub_date, author, description, comments) VALUES (?, ?, ?, ?, ?,?,?) ON CONFLICT DO NOTHING",  self.subscription_id, self.title, self.link, self.pub_date, self.author, self.description, self.comments)
+    pub async fn insert<'a>(
+        &self,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<i64>> {
+        let title_hash = title_hash(&self.title);
+        let result = query!(
+            r#"INSERT INTO items (subscription_id, title, link, pub_date, author, description, comments)
+            SELECT ?, ?, ?, ?, ?, ?, ?
+            WHERE NOT EXISTS (SELECT 1 FROM pruned_items WHERE subscription_id = ? AND title_hash = ?)
+            ON CONFLICT DO NOTHING"#,
+            self.subscription_id,
+            self.title,
+            self.link,
+            self.pub_date,
+            self.author,
+            self.description,
+            self.comments,
+            self.subscription_id,
+            title_hash
+        )
         .execute(executor)
         .await?;
-        Ok(())
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        Ok(Some(result.last_insert_rowid()))
     }
 }
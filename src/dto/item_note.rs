@@ -0,0 +1,183 @@
+use color_eyre::Result;
+use serde::Serialize;
+use sqlx::{query, query_as, Executor, Sqlite};
+use tracing::instrument;
+
+use super::UserId;
+
+/// Free text a user wrote about an item
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemNote {
+    pub item_id: i64,
+    pub note: String,
+    pub updated_on: i64,
+}
+
+/// A quote the user marked in the sanitized content of an item. The offsets count UTF-16 units
+/// of the text the reader sees, like the selections of the browser do, see `crate::highlight`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemHighlight {
+    pub id: i64,
+    pub item_id: i64,
+    pub quote: String,
+    pub start_offset: i64,
+    pub end_offset: i64,
+    pub created_on: i64,
+    /// Made on the full article of the link rather than the contents of the feed
+    pub full_content: bool,
+}
+
+impl ItemNote {
+    #[instrument(skip(executor))]
+    pub async fn fetch<'a>(
+        user_id: &UserId,
+        item_id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT item_id, note, updated_on FROM user_item_notes
+            WHERE user_id = $1 AND item_id = $2"#,
+            user_id,
+            item_id
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT item_id, note, updated_on FROM user_item_notes
+            WHERE user_id = $1
+            ORDER BY updated_on DESC"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    /// An empty note removes the note
+    #[instrument(skip(executor, note))]
+    pub async fn save<'a>(
+        user_id: &UserId,
+        item_id: i64,
+        note: &str,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        if note.trim().is_empty() {
+            query!(
+                "DELETE FROM user_item_notes WHERE item_id = $1 AND user_id = $2",
+                item_id,
+                user_id
+            )
+            .execute(executor)
+            .await?;
+            return Ok(());
+        }
+        query!(
+            r#"INSERT INTO user_item_notes (item_id, user_id, note, updated_on) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (item_id, user_id) DO UPDATE SET note = excluded.note, updated_on = excluded.updated_on"#,
+            item_id,
+            user_id,
+            note,
+            now
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
+
+impl ItemHighlight {
+    #[instrument(skip(executor))]
+    pub async fn fetch_for_item<'a>(
+        user_id: &UserId,
+        item_id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, item_id, quote, start_offset, end_offset, created_on, full_content as 'full_content: bool' FROM user_item_highlights
+            WHERE user_id = $1 AND item_id = $2
+            ORDER BY start_offset"#,
+            user_id,
+            item_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, item_id, quote, start_offset, end_offset, created_on, full_content as 'full_content: bool' FROM user_item_highlights
+            WHERE user_id = $1
+            ORDER BY item_id, start_offset"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor, quote))]
+    pub async fn insert<'a>(
+        user_id: &UserId,
+        item_id: i64,
+        quote: &str,
+        start_offset: i64,
+        end_offset: i64,
+        full_content: bool,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<i64> {
+        let id = query!(
+            r#"INSERT INTO user_item_highlights (item_id, user_id, quote, start_offset, end_offset, full_content, created_on)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            item_id,
+            user_id,
+            quote,
+            start_offset,
+            end_offset,
+            full_content,
+            now
+        )
+        .execute(executor)
+        .await?
+        .last_insert_rowid();
+        Ok(id)
+    }
+
+    /// Returns false if the user has no such highlight on the item
+    #[instrument(skip(executor))]
+    pub async fn delete<'a>(
+        user_id: &UserId,
+        item_id: i64,
+        id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<bool> {
+        let deleted = query!(
+            r#"DELETE FROM user_item_highlights WHERE id = $1 AND item_id = $2 AND user_id = $3"#,
+            id,
+            item_id,
+            user_id
+        )
+        .execute(executor)
+        .await?
+        .rows_affected();
+        Ok(deleted > 0)
+    }
+}
@@ -0,0 +1,120 @@
+use color_eyre::Result;
+use serde::Serialize;
+use sqlx::{query_as, Executor, Sqlite};
+use tracing::instrument;
+
+use super::UserId;
+
+/// Longest tag kept, longer ones are cut
+const MAX_TAG_LENGTH: usize = 64;
+
+/// A free-form tag a user put on an item
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemTag {
+    pub item_id: i64,
+    pub tag: String,
+}
+
+/// How many of the items of the user carry a tag, for the sidebar
+#[derive(Debug, Clone, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: i64,
+}
+
+impl ItemTag {
+    /// Trims the tag and squashes the whitespace inside, none when nothing is left
+    pub fn normalize(tag: &str) -> Option<String> {
+        let tag = tag
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(MAX_TAG_LENGTH)
+            .collect::<String>();
+        if tag.is_empty() {
+            None
+        } else {
+            Some(tag)
+        }
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_for_item<'a>(
+        user_id: &UserId,
+        item_id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<String>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT item_id, tag FROM user_item_tags
+            WHERE user_id = $1 AND item_id = $2
+            ORDER BY tag"#,
+            user_id,
+            item_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer.into_iter().map(|x| x.tag).collect())
+    }
+
+    /// Every tag of the user, to show along a list of items
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT item_id, tag FROM user_item_tags
+            WHERE user_id = $1
+            ORDER BY item_id, tag"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+}
+
+impl TagCount {
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT t.tag, COUNT(*) as "count!: i64"
+            FROM user_item_tags t
+            WHERE t.user_id = $1
+                AND NOT EXISTS (
+                    SELECT 1 FROM user_item_hides h WHERE h.user_id = $1 AND h.item_id = t.item_id
+                )
+            GROUP BY t.tag
+            ORDER BY t.tag"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            ItemTag::normalize("  to   read \n"),
+            Some("to read".to_string())
+        );
+        assert_eq!(ItemTag::normalize(" \t "), None);
+        assert_eq!(
+            ItemTag::normalize(&"x".repeat(100)).map(|x| x.len()),
+            Some(MAX_TAG_LENGTH)
+        );
+    }
+}
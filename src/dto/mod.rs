@@ -1,13 +1,39 @@
+mod api_token;
+mod filter_rule;
+mod invite;
 mod item;
+mod item_archive;
 mod item_insert;
+mod item_note;
+mod item_tag;
+mod output_feed;
+mod pruned_item;
+mod session;
 mod subscription;
+mod subscription_icon;
+mod subscription_settings;
 mod user;
 mod user_id;
+mod user_identity;
+mod user_item;
 mod user_subscription;
 
+pub use api_token::*;
+pub use filter_rule::*;
+pub use invite::*;
 pub use item::*;
+pub use item_archive::*;
 pub use item_insert::*;
+pub use item_note::*;
+pub use item_tag::*;
+pub use output_feed::*;
+pub use pruned_item::*;
+pub use session::*;
 pub use subscription::*;
+pub use subscription_icon::*;
+pub use subscription_settings::*;
 pub use user::*;
 pub use user_id::*;
+pub use user_identity::*;
+pub use user_item::*;
 pub use user_subscription::*;
@@ -0,0 +1,134 @@
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Executor, Sqlite};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::{ItemStream, UserId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFeedKind {
+    Category,
+    Starred,
+    Search,
+    Tag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+}
+
+/// A stream of the items of a user re-published as a feed, anyone with the token can read it
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputFeed {
+    pub id: i64,
+    pub user_id: UserId,
+    #[serde(skip)]
+    pub token: String,
+    pub title: String,
+    pub kind: OutputFeedKind,
+    /// The category, the search or the tag, depending on the kind
+    pub value: Option<String>,
+    pub format: FeedFormat,
+    pub created_on: i64,
+}
+
+impl OutputFeed {
+    #[instrument(skip(executor))]
+    pub async fn insert<'a>(
+        user_id: &UserId,
+        title: &str,
+        kind: OutputFeedKind,
+        value: Option<&str>,
+        format: FeedFormat,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        let token = Uuid::new_v4().to_simple().to_string();
+        query!(
+            r#"INSERT INTO output_feeds (user_id, token, title, kind, value, format, created_on)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            user_id,
+            token,
+            title,
+            kind,
+            value,
+            format,
+            now
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor, token))]
+    pub async fn fetch_by_token<'a>(
+        token: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, user_id as 'user_id:UserId', token, title, kind as 'kind:OutputFeedKind', value, format as 'format:FeedFormat', created_on
+            FROM output_feeds
+            WHERE token = $1"#,
+            token
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, user_id as 'user_id:UserId', token, title, kind as 'kind:OutputFeedKind', value, format as 'format:FeedFormat', created_on
+            FROM output_feeds
+            WHERE user_id = $1
+            ORDER BY created_on DESC"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    /// Returns false if the user has no such feed
+    #[instrument(skip(executor))]
+    pub async fn delete<'a>(
+        user_id: &UserId,
+        id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<bool> {
+        let deleted = query!(
+            r#"DELETE FROM output_feeds WHERE id = $1 AND user_id = $2"#,
+            id,
+            user_id
+        )
+        .execute(executor)
+        .await?
+        .rows_affected();
+        Ok(deleted > 0)
+    }
+
+    /// The items that make up the feed
+    pub fn item_stream(&self) -> ItemStream {
+        let mut item_stream = ItemStream::default();
+        match self.kind {
+            OutputFeedKind::Category => item_stream.category = self.value.clone(),
+            OutputFeedKind::Starred => item_stream.starred_only = true,
+            OutputFeedKind::Search => item_stream.search = self.value.clone(),
+            OutputFeedKind::Tag => item_stream.tag = self.value.clone(),
+        }
+        item_stream
+    }
+}
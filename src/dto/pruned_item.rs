@@ -0,0 +1,209 @@
+use color_eyre::Result;
+use sha3::{Digest, Sha3_256};
+use sqlx::{query, query_as, Connection, Executor, Sqlite, Transaction};
+use tracing::instrument;
+
+/// Items are told apart by their title within a subscription, the tombstones of pruned items
+/// keep a hash of it so the feed does not bring them back
+pub fn title_hash(title: &str) -> String {
+    let mut hasher = Sha3_256::new();
+    hasher.update(title.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// How long a subscription keeps its items, see `crate::retention`. Items are shared between
+/// the subscribers, so this is set for everyone. No value uses the global setting, 0 keeps the
+/// items forever.
+#[derive(Debug, Clone)]
+pub struct SubscriptionRetention {
+    pub subscription_id: i64,
+    pub max_age_days: Option<i64>,
+    pub max_items: Option<i64>,
+}
+
+impl SubscriptionRetention {
+    #[instrument(skip(executor))]
+    pub async fn fetch<'a>(
+        subscription_id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id as subscription_id, retention_max_age_days as max_age_days,
+                retention_max_items as max_items
+            FROM subscriptions WHERE id = $1"#,
+            subscription_id
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id as subscription_id, retention_max_age_days as max_age_days,
+                retention_max_items as max_items
+            FROM subscriptions"#
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn save<'a>(&self, executor: impl Executor<'a, Database = Sqlite>) -> Result<()> {
+        query!(
+            r#"UPDATE subscriptions SET retention_max_age_days = $1, retention_max_items = $2
+            WHERE id = $3"#,
+            self.max_age_days,
+            self.max_items,
+            self.subscription_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
+
+/// An item past the retention of its subscription. Items that are starred, tagged, annotated
+/// or archived by anyone are never prunable, which `prune` checks again.
+#[derive(Debug, Clone)]
+pub struct PrunableItem {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub title: String,
+}
+
+impl PrunableItem {
+    #[instrument(skip(executor))]
+    pub async fn fetch_older<'a>(
+        subscription_id: i64,
+        before: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT i.id, i.subscription_id, i.title
+            FROM items i
+            WHERE i.subscription_id = $1
+                AND i.pub_date < $2
+                AND NOT EXISTS (SELECT 1 FROM user_item_stars s WHERE s.item_id = i.id)
+                AND NOT EXISTS (SELECT 1 FROM user_item_tags t WHERE t.item_id = i.id)
+                AND NOT EXISTS (SELECT 1 FROM user_item_notes n WHERE n.item_id = i.id)
+                AND NOT EXISTS (SELECT 1 FROM user_item_highlights h WHERE h.item_id = i.id)
+                AND NOT EXISTS (SELECT 1 FROM item_archives a WHERE a.item_id = i.id)"#,
+            subscription_id,
+            before
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    /// The items after the newest `keep` ones, kept items count towards `keep`
+    #[instrument(skip(executor))]
+    pub async fn fetch_beyond<'a>(
+        subscription_id: i64,
+        keep: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT i.id, i.subscription_id, i.title
+            FROM items i
+            WHERE i.subscription_id = $1
+                AND i.id NOT IN (
+                    SELECT newest.id FROM items newest
+                    WHERE newest.subscription_id = $1
+                    ORDER BY newest.pub_date DESC, newest.id DESC
+                    LIMIT $2
+                )
+                AND NOT EXISTS (SELECT 1 FROM user_item_stars s WHERE s.item_id = i.id)
+                AND NOT EXISTS (SELECT 1 FROM user_item_tags t WHERE t.item_id = i.id)
+                AND NOT EXISTS (SELECT 1 FROM user_item_notes n WHERE n.item_id = i.id)
+                AND NOT EXISTS (SELECT 1 FROM user_item_highlights h WHERE h.item_id = i.id)
+                AND NOT EXISTS (SELECT 1 FROM item_archives a WHERE a.item_id = i.id)"#,
+            subscription_id,
+            keep
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    /// Removes the item and what the users did with it, leaving a tombstone. Someone may have
+    /// starred, tagged, annotated or archived the item since it was fetched, then it stays and
+    /// this returns false.
+    #[instrument(skip(transaction))]
+    pub async fn prune(&self, now: i64, transaction: &mut Transaction<'_, Sqlite>) -> Result<bool> {
+        // The reads and hides go first for the foreign keys, and come back when the item stays
+        let mut savepoint = Connection::begin(&mut **transaction).await?;
+        query!(r#"DELETE FROM user_item_reads WHERE item_id = $1"#, self.id)
+            .execute(&mut savepoint)
+            .await?;
+        query!(r#"DELETE FROM user_item_hides WHERE item_id = $1"#, self.id)
+            .execute(&mut savepoint)
+            .await?;
+        let deleted = query!(
+            r#"DELETE FROM items
+            WHERE id = $1
+                AND NOT EXISTS (SELECT 1 FROM user_item_stars s WHERE s.item_id = $1)
+                AND NOT EXISTS (SELECT 1 FROM user_item_tags t WHERE t.item_id = $1)
+                AND NOT EXISTS (SELECT 1 FROM user_item_notes n WHERE n.item_id = $1)
+                AND NOT EXISTS (SELECT 1 FROM user_item_highlights h WHERE h.item_id = $1)
+                AND NOT EXISTS (SELECT 1 FROM item_archives a WHERE a.item_id = $1)"#,
+            self.id
+        )
+        .execute(&mut savepoint)
+        .await?
+        .rows_affected();
+        if deleted == 0 {
+            savepoint.rollback().await?;
+            return Ok(false);
+        }
+        let title_hash = title_hash(&self.title);
+        query!(
+            r#"INSERT INTO pruned_items (subscription_id, title_hash, pruned_on) VALUES ($1, $2, $3)
+            ON CONFLICT (subscription_id, title_hash) DO UPDATE SET pruned_on = excluded.pruned_on"#,
+            self.subscription_id,
+            title_hash,
+            now
+        )
+        .execute(&mut savepoint)
+        .await?;
+        savepoint.commit().await?;
+        Ok(true)
+    }
+
+    /// Tombstones older than `before` are dropped, feeds rarely carry items that old
+    #[instrument(skip(executor))]
+    pub async fn delete_tombstones<'a>(
+        before: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<u64> {
+        let deleted = query!(r#"DELETE FROM pruned_items WHERE pruned_on < $1"#, before)
+            .execute(executor)
+            .await?
+            .rows_affected();
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_title_hash() {
+        assert_eq!(
+            title_hash("DXIII - Battlefruit"),
+            title_hash("DXIII - Battlefruit")
+        );
+        assert_ne!(title_hash("DXIII - Battlefruit"), title_hash("DXII"));
+        assert_eq!(title_hash("").len(), 64);
+    }
+}
@@ -0,0 +1,152 @@
+use color_eyre::Result;
+use sqlx::{query, query_as, Executor, Sqlite};
+use tracing::instrument;
+
+use super::UserId;
+
+/// A logged in browser, only the hash of the cookie token is stored
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: i64,
+    pub token_hash: String,
+    pub user_id: UserId,
+    pub created_on: i64,
+    pub last_seen: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub csrf_token: String,
+}
+
+impl Session {
+    #[instrument(skip(executor, token_hash, csrf_token))]
+    pub async fn insert<'a>(
+        token_hash: &str,
+        user_id: &UserId,
+        user_agent: Option<&str>,
+        ip: Option<&str>,
+        csrf_token: &str,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            r#"INSERT INTO sessions (token_hash, user_id, created_on, last_seen, user_agent, ip, csrf_token)
+            VALUES ($1, $2, $3, $3, $4, $5, $6)"#,
+            token_hash,
+            user_id,
+            now,
+            user_agent,
+            ip,
+            csrf_token
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor, token_hash))]
+    pub async fn fetch<'a>(
+        token_hash: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, token_hash, user_id as 'user_id:UserId', created_on, last_seen, user_agent, ip, csrf_token
+            FROM sessions
+            WHERE token_hash = $1"#,
+            token_hash
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, token_hash, user_id as 'user_id:UserId', created_on, last_seen, user_agent, ip, csrf_token
+            FROM sessions
+            WHERE user_id = $1
+            ORDER BY last_seen DESC"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn touch<'a>(
+        id: i64,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(r#"UPDATE sessions SET last_seen = $1 WHERE id = $2"#, now, id)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor, token_hash))]
+    pub async fn delete<'a>(
+        token_hash: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(r#"DELETE FROM sessions WHERE token_hash = $1"#, token_hash)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_by_id<'a>(
+        user_id: &UserId,
+        id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT id, token_hash, user_id as 'user_id:UserId', created_on, last_seen, user_agent, ip, csrf_token
+            FROM sessions
+            WHERE user_id = $1 AND id = $2"#,
+            user_id,
+            id
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor, keep_token_hash))]
+    pub async fn delete_for_user<'a>(
+        user_id: &UserId,
+        keep_token_hash: Option<&str>,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            r#"DELETE FROM sessions WHERE user_id = $1 AND ($2 IS NULL OR token_hash != $2)"#,
+            user_id,
+            keep_token_hash
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn delete_expired<'a>(
+        last_seen_before: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<u64> {
+        let result = query!(
+            r#"DELETE FROM sessions WHERE last_seen < $1"#,
+            last_seen_before
+        )
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
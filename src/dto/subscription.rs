@@ -8,7 +8,7 @@ use tracing::instrument;
 
 use super::ItemInsert;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct Subscription {
     pub id: i64,
     pub rss_feed: String,
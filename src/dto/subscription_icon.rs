@@ -0,0 +1,93 @@
+use color_eyre::Result;
+use sqlx::{query, query_as, Executor, Sqlite};
+use tracing::instrument;
+
+use super::{Subscription, UserId};
+
+/// The favicon of a subscription, see `crate::favicon`. Without data no icon was found the last
+/// time it was looked for.
+#[derive(Debug, Clone)]
+pub struct SubscriptionIcon {
+    pub subscription_id: i64,
+    pub content_type: Option<String>,
+    pub data: Option<Vec<u8>>,
+    pub fetched_on: i64,
+}
+
+impl SubscriptionIcon {
+    #[instrument(skip(executor))]
+    pub async fn fetch<'a>(
+        subscription_id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT subscription_id, content_type, data, fetched_on
+            FROM subscription_icons WHERE subscription_id = $1"#,
+            subscription_id
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    /// The icons found for the subscriptions of the user
+    #[instrument(skip(executor))]
+    pub async fn fetch_for_user<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT i.subscription_id, i.content_type, i.data, i.fetched_on
+            FROM subscription_icons i
+            INNER JOIN user_subscription_metas m ON m.subscription_id = i.subscription_id
+            WHERE m.user_id = $1 AND i.data IS NOT NULL
+            ORDER BY i.subscription_id"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(self, executor), fields(subscription_id = self.subscription_id))]
+    pub async fn save<'a>(&self, executor: impl Executor<'a, Database = Sqlite>) -> Result<()> {
+        query!(
+            r#"INSERT INTO subscription_icons (subscription_id, content_type, data, fetched_on)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (subscription_id) DO UPDATE SET content_type = excluded.content_type,
+                data = excluded.data, fetched_on = excluded.fetched_on"#,
+            self.subscription_id,
+            self.content_type,
+            self.data,
+            self.fetched_on
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Subscriptions without an icon looked up since `before`, the ones never looked up first
+    #[instrument(skip(executor))]
+    pub async fn fetch_stale<'a>(
+        before: i64,
+        limit: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Subscription>> {
+        let answer = query_as!(
+            Subscription,
+            r#"SELECT s.id, s.rss_feed
+            FROM subscriptions s
+            LEFT JOIN subscription_icons i ON i.subscription_id = s.id
+            WHERE i.fetched_on IS NULL OR i.fetched_on < $1
+            ORDER BY IFNULL(i.fetched_on, 0) ASC
+            LIMIT $2"#,
+            before,
+            limit
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Executor, Sqlite};
+use tracing::instrument;
+
+use super::UserId;
+
+/// What the html of the items may keep, see `crate::sanitizer::Sanitizer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SanitizerProfile {
+    /// Text, links and proxied images
+    Strict,
+    /// Also `<video>` and `<audio>`
+    Media,
+    /// Also iframes from the embed hosts, like YouTube and Vimeo
+    Embeds,
+}
+
+impl Default for SanitizerProfile {
+    fn default() -> Self {
+        Self::Strict
+    }
+}
+
+/// How a user wants the items of one of their subscriptions handled
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscriptionSettings {
+    pub subscription_id: i64,
+    /// Download the linked page of new items and show the article instead of the contents
+    pub fetch_full_content: bool,
+    pub sanitizer_profile: SanitizerProfile,
+}
+
+impl SubscriptionSettings {
+    #[instrument(skip(executor))]
+    pub async fn fetch<'a>(
+        user_id: &UserId,
+        subscription_id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT subscription_id, fetch_full_content as "fetch_full_content: bool",
+                sanitizer_profile as "sanitizer_profile: SanitizerProfile"
+            FROM user_subscription_metas
+            WHERE user_id = $1 AND subscription_id = $2"#,
+            user_id,
+            subscription_id
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT subscription_id, fetch_full_content as "fetch_full_content: bool",
+                sanitizer_profile as "sanitizer_profile: SanitizerProfile"
+            FROM user_subscription_metas
+            WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    /// The profile of one of the subscriptions of the user, the default when not subscribed
+    #[instrument(skip(executor))]
+    pub async fn fetch_sanitizer_profile<'a>(
+        user_id: &UserId,
+        subscription_id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<SanitizerProfile> {
+        Ok(Self::fetch(user_id, subscription_id, executor)
+            .await?
+            .map(|x| x.sanitizer_profile)
+            .unwrap_or_default())
+    }
+
+    /// Returns false if the user is not subscribed
+    #[instrument(skip(executor))]
+    pub async fn save<'a>(
+        &self,
+        user_id: &UserId,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<bool> {
+        let updated = query!(
+            r#"UPDATE user_subscription_metas SET fetch_full_content = $1, sanitizer_profile = $2
+            WHERE user_id = $3 AND subscription_id = $4"#,
+            self.fetch_full_content,
+            self.sanitizer_profile,
+            user_id,
+            self.subscription_id
+        )
+        .execute(executor)
+        .await?
+        .rows_affected();
+        Ok(updated > 0)
+    }
+
+    /// The subscriptions at least one subscriber wants the full articles of, items are shared
+    #[instrument(skip(executor))]
+    pub async fn fetch_full_content_subscriptions<'a>(
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<HashSet<i64>> {
+        let records = query!(
+            r#"SELECT DISTINCT subscription_id FROM user_subscription_metas WHERE fetch_full_content"#
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(records.into_iter().map(|x| x.subscription_id).collect())
+    }
+}
@@ -1,6 +1,11 @@
-use color_eyre::Result;
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use color_eyre::{eyre::eyre, Result};
+use md5::Md5;
 use sha3::{Digest, Sha3_256};
-use sqlx::{query_as, Executor, Sqlite};
+use sqlx::{query, query_as, Executor, Sqlite, Transaction};
 use tracing::instrument;
 
 use super::UserId;
@@ -11,6 +16,8 @@ pub struct User {
     salt: String,
     username: String,
     salted_password: String,
+    is_admin: bool,
+    disabled: bool,
 }
 
 impl std::ops::Deref for User {
@@ -29,7 +36,7 @@ impl User {
     ) -> Result<Self> {
         let record = query_as!(
             Self,
-            r#"SELECT id as 'id:UserId', salt, salted_password, username
+            r#"SELECT id as 'id:UserId', salt, salted_password, username, is_admin as 'is_admin: bool', disabled as 'disabled: bool'
             FROM users
             WHERE username = $1"#,
             username,
@@ -39,19 +46,288 @@ impl User {
         Ok(record)
     }
 
+    #[instrument(skip(executor))]
+    pub async fn fetch_by_id<'a>(
+        executor: impl Executor<'a, Database = Sqlite>,
+        user_id: &UserId,
+    ) -> Result<Self> {
+        let record = query_as!(
+            Self,
+            r#"SELECT id as 'id:UserId', salt, salted_password, username, is_admin as 'is_admin: bool', disabled as 'disabled: bool'
+            FROM users
+            WHERE id = $1"#,
+            user_id,
+        )
+        .fetch_one(executor)
+        .await?;
+        Ok(record)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn fetch_all<'a>(executor: impl Executor<'a, Database = Sqlite>) -> Result<Vec<Self>> {
+        let records = query_as!(
+            Self,
+            r#"SELECT id as 'id:UserId', salt, salted_password, username, is_admin as 'is_admin: bool', disabled as 'disabled: bool'
+            FROM users
+            ORDER BY username ASC"#,
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(records)
+    }
+
+    #[instrument(skip(executor, password))]
+    pub async fn insert<'a>(
+        username: &str,
+        password: &str,
+        is_admin: bool,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<UserId> {
+        let salted_password = Self::hash_password(password)?;
+        let id = query!(
+            r#"INSERT INTO users (salt, username, salted_password, is_admin) VALUES ('', $1, $2, $3)"#,
+            username,
+            salted_password,
+            is_admin
+        )
+        .execute(executor)
+        .await?
+        .last_insert_rowid();
+        Ok(UserId(id))
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn set_disabled<'a>(
+        user_id: &UserId,
+        disabled: bool,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            r#"UPDATE users SET disabled = $1 WHERE id = $2"#,
+            disabled,
+            user_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Removes the user along with everything that belongs to them
+    #[instrument(skip(transaction))]
+    pub async fn delete(user_id: &UserId, transaction: &mut Transaction<'_, Sqlite>) -> Result<()> {
+        query!(r#"DELETE FROM sessions WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM filter_rules WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM user_item_tags WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM item_archives WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM user_item_notes WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM user_item_highlights WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM user_item_hides WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM output_feeds WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM api_tokens WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM user_identities WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM user_item_reads WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM user_item_stars WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(
+            r#"DELETE FROM user_subscription_metas WHERE user_id = $1"#,
+            user_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        query!(r#"UPDATE invites SET used_by = NULL WHERE used_by = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM invites WHERE created_by = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        query!(r#"DELETE FROM users WHERE id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        Ok(())
+    }
+
     pub fn username(&self) -> &str {
         &self.username
     }
 
-    pub fn passwords_match(&self, password: &str) -> bool {
+    pub fn is_admin(&self) -> bool {
+        self.is_admin
+    }
+
+    pub fn disabled(&self) -> bool {
+        self.disabled
+    }
+
+    /// Checks the password against either the argon2 PHC string or the legacy salted SHA3
+    pub fn verify_password(&self, password: &str) -> PasswordCheck {
+        if !self.salted_password.starts_with('$') {
+            return if self.salted_password == Self::legacy_hash_password(&self.salt, password) {
+                PasswordCheck::NeedsRehash
+            } else {
+                PasswordCheck::Invalid
+            };
+        }
+        let matches = PasswordHash::new(&self.salted_password)
+            .map(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or(false);
+        if matches {
+            PasswordCheck::Valid
+        } else {
+            PasswordCheck::Invalid
+        }
+    }
+
+    #[instrument(skip(executor, password))]
+    pub async fn set_password<'a>(
+        user_id: &UserId,
+        password: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        let salted_password = Self::hash_password(password)?;
+        query!(
+            r#"UPDATE users SET salt = '', salted_password = $1 WHERE id = $2"#,
+            salted_password,
+            user_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor, api_key))]
+    pub async fn fetch_by_fever_api_key<'a>(
+        executor: impl Executor<'a, Database = Sqlite>,
+        api_key: &str,
+    ) -> Result<Option<Self>> {
+        let record = query_as!(
+            Self,
+            r#"SELECT id as 'id:UserId', salt, salted_password, username, is_admin as 'is_admin: bool', disabled as 'disabled: bool'
+            FROM users
+            WHERE fever_api_key = $1"#,
+            api_key,
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(record)
+    }
+
+    /// Fever clients log in with `md5("username:password")`, so that is what gets stored for the
+    /// separate api password
+    #[instrument(skip(executor, api_password))]
+    pub async fn set_fever_api_password<'a>(
+        &self,
+        api_password: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        let mut hasher = Md5::new();
+        hasher.update(format!("{}:{}", self.username, api_password).as_bytes());
+        let api_key = hex::encode(hasher.finalize().as_slice());
+        query!(
+            r#"UPDATE users SET fever_api_key = $1 WHERE id = $2"#,
+            api_key,
+            self.id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Spends the same time as checking a password, so a missing user can't be told apart
+    pub fn simulate_password_check(password: &str) {
+        let _ = Self::hash_password(password);
+    }
+
+    /// Argon2id hash in the PHC string format, the salt is part of the string
+    fn hash_password(password: &str) -> Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| eyre!("Could not hash password: {}", e))?;
+        Ok(hash.to_string())
+    }
+
+    fn legacy_hash_password(salt: &str, password: &str) -> String {
         let mut hasher = Sha3_256::new();
 
         // write input message
-        hasher.update(format!("{}{}", self.salt, password).as_bytes());
+        hasher.update(format!("{}{}", salt, password).as_bytes());
 
         // read hash digest
         let result = hasher.finalize();
 
-        self.salted_password == hex::encode(result.as_slice())
+        hex::encode(result.as_slice())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Invalid,
+    Valid,
+    /// The password matched a legacy hash and should be stored again with the current hash
+    NeedsRehash,
+}
+
+impl PasswordCheck {
+    pub fn is_valid(&self) -> bool {
+        !matches!(self, PasswordCheck::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(salt: &str, salted_password: String) -> User {
+        User {
+            id: UserId(1),
+            salt: salt.to_string(),
+            username: "test".to_string(),
+            salted_password,
+            is_admin: false,
+            disabled: false,
+        }
+    }
+
+    #[test]
+    fn test_verify_password() {
+        let user = user("", User::hash_password("hunter22").unwrap());
+        assert!(user.salted_password.starts_with("$argon2id$"));
+        assert_eq!(user.verify_password("hunter22"), PasswordCheck::Valid);
+        assert_eq!(user.verify_password("hunter2"), PasswordCheck::Invalid);
+    }
+
+    #[test]
+    fn test_verify_legacy_password() {
+        let salt = "ufRK8ESE2V2N67VXUTzg";
+        let user = user(salt, User::legacy_hash_password(salt, "hunter22"));
+        assert_eq!(user.verify_password("hunter22"), PasswordCheck::NeedsRehash);
+        assert_eq!(user.verify_password("hunter2"), PasswordCheck::Invalid);
     }
 }
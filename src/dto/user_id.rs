@@ -1,42 +1,84 @@
-use color_eyre::eyre::bail;
+use chrono_tz::Tz;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
 use sqlx::{query, Executor, Sqlite};
-use tiny_keccak::Hasher;
-use tiny_keccak::Sha3;
 use tracing::instrument;
 
-#[derive(Debug, Clone, sqlx::Type)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, sqlx::Type)]
+#[serde(transparent)]
 #[sqlx(transparent)]
 pub struct UserId(pub i64);
 
 impl UserId {
-    #[instrument(skip(executor, password))]
-    pub async fn fetch<'a>(
-        executor: impl Executor<'a, Database = Sqlite>,
-        user: &str,
-        password: &str,
-    ) -> Result<Self> {
+    /// Disabled users are never treated as admins
+    #[instrument(skip(executor))]
+    pub async fn is_admin<'a>(&self, executor: impl Executor<'a, Database = Sqlite>) -> Result<bool> {
         let record = query!(
-            r#"SELECT id as 'id:UserId', salt, salted_password 
-            FROM users
-            WHERE username = $1"#,
-            user,
+            r#"SELECT is_admin as 'is_admin: bool' FROM users WHERE id = $1 AND NOT disabled"#,
+            self
         )
-        .fetch_one(executor)
+        .fetch_optional(executor)
         .await?;
+        Ok(record.map(|x| x.is_admin).unwrap_or(false))
+    }
 
-        let mut sha3 = Sha3::v256();
-        let mut output = [0u8; 32];
+    /// The timezone the user wants their dates displayed and grouped in
+    #[instrument(skip(executor))]
+    pub async fn timezone<'a>(&self, executor: impl Executor<'a, Database = Sqlite>) -> Result<Tz> {
+        let record = query!(r#"SELECT timezone FROM users WHERE id = $1"#, self)
+            .fetch_one(executor)
+            .await?;
+        record
+            .timezone
+            .parse()
+            .map_err(|e| eyre!("Invalid timezone {:?}: {}", record.timezone, e))
+    }
 
-        sha3.update(record.salt.as_bytes());
-        sha3.update(password.as_bytes());
-        sha3.finalize(&mut output);
+    /// Whether new stars of the user get archived in the background
+    #[instrument(skip(executor))]
+    pub async fn archive_starred<'a>(
+        &self,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<bool> {
+        let record = query!(
+            r#"SELECT archive_starred as "archive_starred: bool" FROM users WHERE id = $1"#,
+            self
+        )
+        .fetch_one(executor)
+        .await?;
+        Ok(record.archive_starred)
+    }
 
-        let calculated_salted_password = hex::encode(output);
+    #[instrument(skip(executor))]
+    pub async fn set_archive_starred<'a>(
+        &self,
+        archive_starred: bool,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            r#"UPDATE users SET archive_starred = $1 WHERE id = $2"#,
+            archive_starred,
+            self
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
 
-        if calculated_salted_password != record.salted_password {
-            bail!("Invalid password");
-        }
-        Ok(record.id)
+    #[instrument(skip(executor))]
+    pub async fn set_timezone<'a>(
+        &self,
+        timezone: &Tz,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        let timezone = timezone.name();
+        query!(
+            r#"UPDATE users SET timezone = $1 WHERE id = $2"#,
+            timezone,
+            self
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
     }
 }
@@ -0,0 +1,55 @@
+use color_eyre::Result;
+use sqlx::{query, query_as, Executor, Sqlite};
+use tracing::instrument;
+
+use super::UserId;
+
+/// An account at an OpenID Connect provider linked to a user, the subject is only unique per issuer
+#[derive(Debug, Clone)]
+pub struct UserIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub user_id: UserId,
+    pub created_on: i64,
+}
+
+impl UserIdentity {
+    #[instrument(skip(executor))]
+    pub async fn fetch<'a>(
+        issuer: &str,
+        subject: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<Option<Self>> {
+        let answer = query_as!(
+            Self,
+            r#"SELECT issuer, subject, user_id as 'user_id:UserId', created_on
+            FROM user_identities
+            WHERE issuer = $1 AND subject = $2"#,
+            issuer,
+            subject
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(answer)
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn insert<'a>(
+        issuer: &str,
+        subject: &str,
+        user_id: &UserId,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            r#"INSERT INTO user_identities (issuer, subject, user_id, created_on) VALUES ($1, $2, $3, $4)"#,
+            issuer,
+            subject,
+            user_id,
+            now
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use color_eyre::eyre::Result;
+use sqlx::{query, query_file, Executor, Sqlite};
+use tracing::instrument;
+
+use super::UserId;
+
+/// The state a single user keeps on an item: whether it was read and whether it is starred
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct UserItem {
+    pub user_id: UserId,
+    pub item_id: i64,
+}
+
+impl UserItem {
+    pub fn new(user_id: &UserId, item_id: i64) -> Self {
+        Self {
+            user_id: user_id.clone(),
+            item_id,
+        }
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn mark_read<'a>(
+        &self,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            "INSERT OR IGNORE INTO user_item_reads (item_id, user_id, read_on) VALUES ($1, $2, $3)",
+            self.item_id,
+            self.user_id,
+            now
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn mark_unread<'a>(
+        &self,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            "DELETE FROM user_item_reads WHERE item_id = $1 AND user_id = $2",
+            self.item_id,
+            self.user_id,
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Marks everything published up to `before` as read, optionally only for one subscription or
+    /// one category, or only the starred items
+    #[instrument(skip(executor))]
+    pub async fn mark_all_read<'a>(
+        user_id: &UserId,
+        before: i64,
+        now: i64,
+        subscription_id: Option<i64>,
+        category: Option<&str>,
+        starred_only: bool,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query_file!(
+            "queries/user_item_reads_insert.sql",
+            user_id,
+            before,
+            now,
+            subscription_id,
+            Option::<&str>::None,
+            category,
+            Option::<&str>::None,
+            starred_only
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn star<'a>(
+        &self,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            "INSERT OR IGNORE INTO user_item_stars (item_id, user_id, starred_on) VALUES ($1, $2, $3)",
+            self.item_id,
+            self.user_id,
+            now
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn unstar<'a>(&self, executor: impl Executor<'a, Database = Sqlite>) -> Result<()> {
+        query!(
+            "DELETE FROM user_item_stars WHERE item_id = $1 AND user_id = $2",
+            self.item_id,
+            self.user_id,
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn add_tag<'a>(
+        &self,
+        tag: &str,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            "INSERT OR IGNORE INTO user_item_tags (item_id, user_id, tag, tagged_on) VALUES ($1, $2, $3, $4)",
+            self.item_id,
+            self.user_id,
+            tag,
+            now
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(executor))]
+    pub async fn remove_tag<'a>(
+        &self,
+        tag: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            "DELETE FROM user_item_tags WHERE item_id = $1 AND user_id = $2 AND tag = $3",
+            self.item_id,
+            self.user_id,
+            tag
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Hidden items are left out of every list of items
+    #[instrument(skip(executor))]
+    pub async fn hide<'a>(
+        &self,
+        now: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<()> {
+        query!(
+            "INSERT OR IGNORE INTO user_item_hides (item_id, user_id, hidden_on) VALUES ($1, $2, $3)",
+            self.item_id,
+            self.user_id,
+            now
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
@@ -1,10 +1,12 @@
 use color_eyre::Result;
-use sqlx::{query_file_as, Executor, Sqlite};
+use serde::Serialize;
+use sqlx::{query, query_file_as, Executor, Sqlite};
 use tracing::instrument;
+use utoipa::ToSchema;
 
 use super::{Subscription, UserId};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct UserSubscription {
     pub id: i64,
     pub title: String,
@@ -55,4 +57,58 @@ impl UserSubscription {
         .await?;
         Ok(answer)
     }
+
+    /// Returns false if the user is not subscribed
+    #[instrument(skip(executor))]
+    pub async fn update<'a>(
+        user_id: &UserId,
+        id: i64,
+        title: &str,
+        category: &str,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<bool> {
+        let updated = query!(
+            r#"UPDATE user_subscription_metas SET title = $1, category = $2
+            WHERE user_id = $3 AND subscription_id = $4"#,
+            title,
+            category,
+            user_id,
+            id
+        )
+        .execute(executor)
+        .await?
+        .rows_affected();
+        Ok(updated > 0)
+    }
+
+    /// Unsubscribes the user, the feed itself stays for the other subscribers
+    #[instrument(skip(executor))]
+    pub async fn delete<'a>(
+        user_id: &UserId,
+        id: i64,
+        executor: impl Executor<'a, Database = Sqlite>,
+    ) -> Result<bool> {
+        let deleted = query!(
+            r#"DELETE FROM user_subscription_metas WHERE user_id = $1 AND subscription_id = $2"#,
+            user_id,
+            id
+        )
+        .execute(executor)
+        .await?
+        .rows_affected();
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+impl UserSubscription {
+    /// A subscription to `https://example.com/<id>.xml`, for the tests
+    pub fn for_tests(id: i64, category: &str) -> Self {
+        Self {
+            id,
+            title: format!("Feed {}", id),
+            category: category.to_string(),
+            rss_feed: format!("https://example.com/{}.xml", id),
+        }
+    }
 }
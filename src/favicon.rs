@@ -0,0 +1,185 @@
+use chrono::Utc;
+use color_eyre::Result;
+use html5ever::{parse_document, tendril::TendrilSink};
+use markup5ever_rcdom::RcDom;
+use rss::Channel;
+use sqlx::SqlitePool;
+use tokio::task::spawn_blocking;
+use tracing::{debug, instrument, warn};
+use url::Url;
+
+use crate::{
+    dto::{Subscription, SubscriptionIcon},
+    image_proxy::IMAGE_TYPES,
+    readability::{self, attribute, descendants, tag_name},
+};
+
+const MAX_FEED_SIZE: usize = 10 * 1024 * 1024;
+const MAX_ICON_SIZE: usize = 256 * 1024;
+/// Icons are looked up again after this long, sites change their icons
+const ICON_REFRESH_S: i64 = 7 * 24 * 60 * 60;
+/// Subscriptions looked up on each poll, the rest wait for the next polls
+const ICONS_PER_PASS: i64 = 10;
+const ICON_TYPES: &[&str] = &["image/x-icon", "image/vnd.microsoft.icon"];
+
+/// Looks for an icon in the `<image>` of the channel, then at `/favicon.ico` of the site, then
+/// in the `<link rel="icon">` of the site. Everything is fetched through `readability::fetch`,
+/// which refuses the addresses of our own network.
+#[instrument]
+pub async fn find_icon(rss_feed: &str) -> Result<Option<(String, Vec<u8>)>> {
+    let (feed, feed_url, _) = readability::fetch(rss_feed, MAX_FEED_SIZE).await?;
+    let channel = Channel::read_from(&feed[..]).ok();
+    if let Some(image) = channel.as_ref().and_then(|x| x.image.as_ref()) {
+        if let Some(icon) = fetch_icon(&image.url).await {
+            return Ok(Some(icon));
+        }
+    }
+    let site = channel
+        .as_ref()
+        .and_then(|x| Url::parse(&x.link).ok())
+        .unwrap_or(feed_url);
+    if let Ok(favicon) = site.join("/favicon.ico") {
+        if let Some(icon) = fetch_icon(favicon.as_str()).await {
+            return Ok(Some(icon));
+        }
+    }
+    let (page, base) = readability::fetch_page(site.as_str()).await?;
+    // Big pages take a while to read, off the workers serving the requests
+    for link in spawn_blocking(move || icon_links(&page, &base)).await? {
+        if let Some(icon) = fetch_icon(link.as_str()).await {
+            return Ok(Some(icon));
+        }
+    }
+    Ok(None)
+}
+
+/// Looks the icon up again, remembering when nothing was found so it is not retried every poll
+#[instrument(skip(pool))]
+pub async fn refresh_icon(subscription: &Subscription, pool: &SqlitePool) -> Result<()> {
+    let (content_type, data) = match find_icon(&subscription.rss_feed).await {
+        Ok(Some((content_type, data))) => (Some(content_type), Some(data)),
+        Ok(None) => (None, None),
+        Err(e) => {
+            warn!(
+                "Could not look for the icon of {}: {:?}",
+                subscription.rss_feed, e
+            );
+            (None, None)
+        }
+    };
+    SubscriptionIcon {
+        subscription_id: subscription.id,
+        content_type,
+        data,
+        fetched_on: Utc::now().timestamp(),
+    }
+    .save(pool)
+    .await
+}
+
+#[instrument(skip(pool))]
+pub async fn refresh_stale_icons(pool: &SqlitePool) -> Result<()> {
+    let before = Utc::now().timestamp() - ICON_REFRESH_S;
+    for subscription in SubscriptionIcon::fetch_stale(before, ICONS_PER_PASS, pool).await? {
+        refresh_icon(&subscription, pool).await?;
+    }
+    Ok(())
+}
+
+async fn fetch_icon(link: &str) -> Option<(String, Vec<u8>)> {
+    let (icon, _, content_type) = match readability::fetch(link, MAX_ICON_SIZE).await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            debug!("No icon at {}: {:?}", link, e);
+            return None;
+        }
+    };
+    let content_type = content_type
+        .filter(|x| IMAGE_TYPES.contains(&x.as_str()) || ICON_TYPES.contains(&x.as_str()))
+        .or_else(|| sniff(&icon).map(String::from))?;
+    if icon.is_empty() {
+        return None;
+    }
+    Some((content_type, icon))
+}
+
+/// Servers often send icons as `application/octet-stream`, the first bytes tell what they are
+fn sniff(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        [0, 0, 1, 0, ..] => Some("image/x-icon"),
+        _ => None,
+    }
+}
+
+/// The icons the page links to, the `icon` ones before the `apple-touch-icon` ones
+fn icon_links(html: &str, base: &Url) -> Vec<Url> {
+    let dom = parse_document(RcDom::default(), Default::default()).one(html);
+    let mut links: Vec<(bool, Url)> = descendants(&dom.document)
+        .iter()
+        .filter(|x| tag_name(x).as_deref() == Some("link"))
+        .filter_map(|x| {
+            let rel = attribute(x, "rel")?.to_lowercase();
+            let is_icon = rel.split_whitespace().any(|x| x == "icon");
+            let is_touch_icon = rel.split_whitespace().any(|x| x == "apple-touch-icon");
+            if !is_icon && !is_touch_icon {
+                return None;
+            }
+            Some((!is_icon, base.join(&attribute(x, "href")?).ok()?))
+        })
+        .collect();
+    links.sort_by_key(|(is_touch_icon, _)| *is_touch_icon);
+    links.into_iter().map(|(_, link)| link).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_icon_links() {
+        let base = Url::parse("https://example.com/blog/").unwrap();
+        let links = icon_links(
+            r#"<html><head>
+            <link rel="apple-touch-icon" href="/touch.png">
+            <link rel="stylesheet" href="/styles.css">
+            <link rel="Shortcut Icon" href="icon.png">
+            </head><body></body></html>"#,
+            &base,
+        );
+        assert_eq!(
+            links,
+            vec![
+                Url::parse("https://example.com/blog/icon.png").unwrap(),
+                Url::parse("https://example.com/touch.png").unwrap(),
+            ]
+        );
+    }
+
+    #[actix_web::rt::test]
+    async fn test_fetch_icon_refuses_private_addresses() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let link = format!("http://{}/favicon.ico", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0; 1024]);
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 4\r\n\r\n\x89PNG",
+                );
+            }
+        });
+        assert_eq!(fetch_icon(&link).await, None);
+        assert!(find_icon(&link).await.is_err());
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(&[0, 0, 1, 0, 1, 0]), Some("image/x-icon"));
+        assert_eq!(sniff(b"\x89PNG\r\n"), Some("image/png"));
+        assert_eq!(sniff(b"<html>"), None);
+    }
+}
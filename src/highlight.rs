@@ -0,0 +1,191 @@
+use html5ever::{
+    tendril::StrTendril,
+    tokenizer::{
+        BufferQueue, Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
+    },
+};
+
+/// A piece of sanitized html, either markup or one character of the text. Highlight offsets count
+/// the UTF-16 units of the text like the selections of the browser do, an entity counts as the
+/// character it stands for.
+enum Piece {
+    Markup(String),
+    Char(char),
+}
+
+/// Collects the tokens of the html. The tokenizer reads the tags like the browser, so a `>`
+/// inside an attribute value does not end the tag.
+#[derive(Default)]
+struct Pieces(Vec<Piece>);
+
+impl TokenSink for Pieces {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) => self.0.push(Piece::Markup(serialize_tag(&tag))),
+            Token::CharacterTokens(text) => self.0.extend(text.chars().map(Piece::Char)),
+            Token::CommentToken(comment) => {
+                self.0.push(Piece::Markup(format!("<!--{}-->", comment)))
+            }
+            Token::DoctypeToken(_)
+            | Token::NullCharacterToken
+            | Token::EOFToken
+            | Token::ParseError(_) => (),
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+fn pieces(html: &str) -> Vec<Piece> {
+    let mut tokenizer = Tokenizer::new(Pieces::default(), TokenizerOpts::default());
+    let mut queue = BufferQueue::new();
+    queue.push_back(StrTendril::from_slice(html));
+    let _ = tokenizer.feed(&mut queue);
+    tokenizer.end();
+    tokenizer.sink.0
+}
+
+fn serialize_tag(tag: &Tag) -> String {
+    match tag.kind {
+        TagKind::EndTag => format!("</{}>", tag.name),
+        TagKind::StartTag => {
+            let mut markup = format!("<{}", tag.name);
+            for attribute in tag.attrs.iter() {
+                markup.push_str(&format!(
+                    r#" {}="{}""#,
+                    attribute.name.local,
+                    attribute.value.replace('&', "&amp;").replace('"', "&quot;")
+                ));
+            }
+            markup.push_str(if tag.self_closing { "/>" } else { ">" });
+            markup
+        }
+    }
+}
+
+/// Escapes the text like the sanitizer does
+fn push_escaped(html: &mut String, c: char) {
+    match c {
+        '&' => html.push_str("&amp;"),
+        '<' => html.push_str("&lt;"),
+        '>' => html.push_str("&gt;"),
+        '\u{a0}' => html.push_str("&nbsp;"),
+        c => html.push(c),
+    }
+}
+
+/// The text of the html, like `textContent` in the browser
+pub fn text_content(html: &str) -> String {
+    pieces(html)
+        .into_iter()
+        .filter_map(|piece| match piece {
+            Piece::Markup(_) => None,
+            Piece::Char(c) => Some(c),
+        })
+        .collect()
+}
+
+fn utf16_length(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+/// Wraps the ranges of the text in `<mark>`. The marks are closed around every tag so the
+/// nesting of the html stays valid.
+pub fn mark_highlights(html: &str, ranges: &[(usize, usize)]) -> String {
+    if ranges.is_empty() {
+        return html.to_string();
+    }
+    let mut marked = String::with_capacity(html.len());
+    let mut position = 0;
+    let mut open = false;
+    for piece in pieces(html) {
+        match piece {
+            Piece::Markup(markup) => {
+                if open {
+                    marked.push_str("</mark>");
+                    open = false;
+                }
+                marked.push_str(&markup);
+            }
+            Piece::Char(c) => {
+                let highlighted = ranges
+                    .iter()
+                    .any(|(start, end)| *start <= position && position < *end);
+                if highlighted && !open {
+                    marked.push_str("<mark>");
+                    open = true;
+                } else if !highlighted && open {
+                    marked.push_str("</mark>");
+                    open = false;
+                }
+                push_escaped(&mut marked, c);
+                position += c.len_utf16();
+            }
+        }
+    }
+    if open {
+        marked.push_str("</mark>");
+    }
+    marked
+}
+
+/// Checks a highlight against the text. When the offsets do not hold the quote any more, the
+/// first place of the quote in the text is used instead.
+pub fn resolve_highlight(
+    text: &str,
+    quote: &str,
+    start: usize,
+    end: usize,
+) -> Option<(usize, usize)> {
+    if quote.trim().is_empty() {
+        return None;
+    }
+    let units: Vec<u16> = text.encode_utf16().collect();
+    if start < end && end <= units.len() && String::from_utf16_lossy(&units[start..end]) == quote {
+        return Some((start, end));
+    }
+    let byte_start = text.find(quote)?;
+    let start = utf16_length(&text[..byte_start]);
+    Some((start, start + utf16_length(quote)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_content() {
+        assert_eq!(
+            text_content("<p>Fish &amp; <b>chips</b>&#33;</p>"),
+            "Fish & chips!"
+        );
+    }
+
+    #[test]
+    fn test_mark_highlights() {
+        let html = "<p>Fish &amp; <b>chips</b> today</p>";
+        assert_eq!(
+            mark_highlights(html, &[(5, 12)]),
+            "<p>Fish <mark>&amp; </mark><b><mark>chips</mark></b> today</p>"
+        );
+        assert_eq!(mark_highlights(html, &[]), html);
+
+        // A `>` in an attribute value is not the end of the tag
+        let html = r#"<p><abbr title="a>b">Fish</abbr> &lt;chips&gt;</p>"#;
+        assert_eq!(text_content(html), "Fish <chips>");
+        assert_eq!(
+            mark_highlights(html, &[(0, 6)]),
+            r#"<p><abbr title="a>b"><mark>Fish</mark></abbr><mark> &lt;</mark>chips&gt;</p>"#
+        );
+    }
+
+    #[test]
+    fn test_resolve_highlight() {
+        let text = "Ünïcode and more and more";
+        assert_eq!(resolve_highlight(text, "and", 8, 11), Some((8, 11)));
+        assert_eq!(resolve_highlight(text, "more", 0, 4), Some((12, 16)));
+        assert_eq!(resolve_highlight(text, "missing", 0, 7), None);
+        assert_eq!(resolve_highlight(text, " ", 0, 1), None);
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use color_eyre::{eyre::eyre, Result};
+use sha3::{Digest, Sha3_256};
+use tracing::{instrument, warn};
+use url::form_urlencoded;
+use uuid::Uuid;
+
+use crate::{readability, settings::ImageProxySettings};
+
+/// Only images that can not carry scripts are served
+pub const IMAGE_TYPES: &[&str] = &[
+    "image/avif",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+];
+/// Images being written to the cache, renamed to their key once complete
+const PARTIAL_EXTENSION: &str = "partial";
+
+/// Serves the images of the articles through us, so the feeds never see the ip or the referrer
+/// of the readers and http images do not break https pages. The urls are signed so only the
+/// images we put in the pages can be fetched, and the images are cached on disk.
+#[derive(Clone)]
+pub struct ImageProxy {
+    secret: Arc<Vec<u8>>,
+    cache_dir: PathBuf,
+    max_cache_size: u64,
+    max_image_size: usize,
+    index: Arc<Mutex<CacheIndex>>,
+}
+
+impl fmt::Debug for ImageProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImageProxy")
+            .field("secret", &"redacted")
+            .field("cache_dir", &self.cache_dir)
+            .field("max_cache_size", &self.max_cache_size)
+            .finish()
+    }
+}
+
+impl ImageProxy {
+    /// Picks up the images cached by an earlier run
+    pub fn new(settings: &ImageProxySettings) -> Result<Self> {
+        let secret = match &settings.secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => Uuid::new_v4().as_bytes().to_vec(),
+        };
+        let cache_dir = PathBuf::from(&settings.cache_dir);
+        std::fs::create_dir_all(&cache_dir)?;
+        let mut cached = Vec::new();
+        for entry in std::fs::read_dir(&cache_dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            // Left over by a write that never finished
+            if entry.path().extension().and_then(|x| x.to_str()) == Some(PARTIAL_EXTENSION) {
+                if let Err(e) = std::fs::remove_file(entry.path()) {
+                    warn!(
+                        "Could not remove the partial image {:?}: {:?}",
+                        entry.path(),
+                        e
+                    );
+                }
+                continue;
+            }
+            if metadata.is_file() {
+                cached.push((
+                    metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    entry.file_name().to_string_lossy().to_string(),
+                    metadata.len(),
+                ));
+            }
+        }
+        cached.sort();
+        let mut index = CacheIndex::default();
+        for (_, key, size) in cached {
+            index.insert(key, size);
+        }
+        // The cache may have been made smaller since
+        for key in index.evict(settings.max_cache_size) {
+            if let Err(e) = std::fs::remove_file(cache_dir.join(&key)) {
+                warn!("Could not remove the cached image {}: {:?}", key, e);
+            }
+        }
+        Ok(Self {
+            secret: Arc::new(secret),
+            cache_dir,
+            max_cache_size: settings.max_cache_size,
+            max_image_size: settings.max_image_size,
+            index: Arc::new(Mutex::new(index)),
+        })
+    }
+
+    fn index(&self) -> MutexGuard<'_, CacheIndex> {
+        self.index
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn signature(&self, url: &str) -> String {
+        let mut hasher = Sha3_256::new();
+        hasher.update(self.secret.as_slice());
+        hasher.update(url.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    pub fn verify(&self, url: &str, signature: &str) -> bool {
+        let expected = self.signature(url);
+        expected.len() == signature.len()
+            && expected
+                .bytes()
+                .zip(signature.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+
+    /// Where the page should load the image from, only absolute http urls are proxied
+    pub fn proxied_url(&self, url: &str) -> Option<String> {
+        self.signed_url("/proxy/image", url)
+    }
+
+    /// Where the page should play the audio, video or subtitles from, see `media`
+    pub fn proxied_media_url(&self, url: &str) -> Option<String> {
+        self.signed_url("/proxy/media", url)
+    }
+
+    fn signed_url(&self, path: &str, url: &str) -> Option<String> {
+        let url = url.trim();
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return None;
+        }
+        Some(format!(
+            "{}?{}",
+            path,
+            form_urlencoded::Serializer::new(String::new())
+                .append_pair("url", url)
+                .append_pair("sig", &self.signature(url))
+                .finish()
+        ))
+    }
+
+    /// Proxies every candidate of a `srcset`, dropping the ones that can not be proxied
+    pub fn proxied_srcset(&self, srcset: &str) -> Option<String> {
+        let candidates: Vec<String> = srcset
+            .split(',')
+            .filter_map(|candidate| {
+                let mut parts = candidate.split_whitespace();
+                let url = self.proxied_url(parts.next()?)?;
+                Some(
+                    std::iter::once(url)
+                        .chain(parts.map(String::from))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                )
+            })
+            .collect();
+        if candidates.is_empty() {
+            None
+        } else {
+            Some(candidates.join(", "))
+        }
+    }
+
+    /// The content type and the bytes of the image, from the cache when we have it
+    #[instrument(skip(self))]
+    pub async fn image(&self, url: &str) -> Result<(String, Vec<u8>)> {
+        let key = cache_key(url);
+        let path = self.cache_dir.join(&key);
+        if self.index().touch(&key) {
+            match tokio::fs::read(&path).await {
+                Ok(cached) => {
+                    if let Some(image) = split_cached(&cached) {
+                        return Ok(image);
+                    }
+                }
+                Err(e) => warn!("Could not read the cached image {}: {:?}", key, e),
+            }
+            self.index().remove(&key);
+        }
+        let (image, _, content_type) = readability::fetch(url, self.max_image_size).await?;
+        let content_type = content_type.unwrap_or_default();
+        if !IMAGE_TYPES.contains(&content_type.as_str()) {
+            return Err(eyre!("{} is not an image but {:?}", url, content_type));
+        }
+        let mut cached = format!("{}\n", content_type).into_bytes();
+        cached.extend_from_slice(&image);
+        // Readers never see half an image, and concurrent writes of the same image each get
+        // their own partial file
+        let partial =
+            self.cache_dir
+                .join(format!("{}.{}.{}", key, Uuid::new_v4(), PARTIAL_EXTENSION));
+        tokio::fs::write(&partial, &cached).await?;
+        if let Err(e) = tokio::fs::rename(&partial, &path).await {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e.into());
+        }
+        let evicted = {
+            let mut index = self.index();
+            index.insert(key, cached.len() as u64);
+            index.evict(self.max_cache_size)
+        };
+        for key in evicted {
+            if let Err(e) = tokio::fs::remove_file(self.cache_dir.join(&key)).await {
+                warn!("Could not remove the cached image {}: {:?}", key, e);
+            }
+        }
+        Ok((content_type, image))
+    }
+
+    /// Starts downloading audio, video or subtitles, which are too big to cache and get streamed
+    /// instead. The range the player asked for is passed on so it can seek. Media can take long
+    /// to download, only the connection is timed.
+    #[instrument(skip(self))]
+    pub async fn media(&self, url: &str, range: Option<&str>) -> Result<reqwest::Response> {
+        let response = readability::get(url, range, None).await?;
+        let content_type = readability::mime_type(response.headers()).unwrap_or_default();
+        if !is_media_type(&content_type) {
+            return Err(eyre!("{} is not media but {:?}", url, content_type));
+        }
+        Ok(response)
+    }
+}
+
+fn is_media_type(content_type: &str) -> bool {
+    content_type.starts_with("audio/")
+        || content_type.starts_with("video/")
+        || content_type == "text/vtt"
+}
+
+/// The name of the cached file, without the secret so the cache outlives a new secret
+fn cache_key(url: &str) -> String {
+    let mut hasher = Sha3_256::new();
+    hasher.update(url.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Cached files are the content type on the first line, then the image
+fn split_cached(cached: &[u8]) -> Option<(String, Vec<u8>)> {
+    let newline = cached.iter().position(|x| *x == b'\n')?;
+    let content_type = std::str::from_utf8(&cached[..newline]).ok()?;
+    if !IMAGE_TYPES.contains(&content_type) {
+        return None;
+    }
+    Some((content_type.to_string(), cached[newline + 1..].to_vec()))
+}
+
+/// Sizes of the cached files with when they were last used, to evict the least recently used
+#[derive(Debug, Default)]
+struct CacheIndex {
+    entries: HashMap<String, (u64, u64)>,
+    total_size: u64,
+    clock: u64,
+}
+
+impl CacheIndex {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Marks the entry as used, false when it is not cached
+    fn touch(&mut self, key: &str) -> bool {
+        let now = self.tick();
+        match self.entries.get_mut(key) {
+            Some((_, used)) => {
+                *used = now;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, key: String, size: u64) {
+        let now = self.tick();
+        self.total_size += size;
+        if let Some((old_size, _)) = self.entries.insert(key, (size, now)) {
+            self.total_size -= old_size;
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((size, _)) = self.entries.remove(key) {
+            self.total_size -= size;
+        }
+    }
+
+    /// Drops the least recently used entries until the cache fits, returning their keys
+    fn evict(&mut self, max_size: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.total_size > max_size {
+            let oldest = match self.entries.iter().min_by_key(|(_, (_, used))| *used) {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+            self.remove(&oldest);
+            evicted.push(oldest);
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy() -> ImageProxy {
+        ImageProxy {
+            secret: Arc::new(b"secret".to_vec()),
+            cache_dir: PathBuf::from("/nonexistent"),
+            max_cache_size: 0,
+            max_image_size: 0,
+            index: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        let proxy = proxy();
+        let signature = proxy.signature("https://example.com/a.png");
+        assert!(proxy.verify("https://example.com/a.png", &signature));
+        assert!(!proxy.verify("https://example.com/b.png", &signature));
+        assert!(!proxy.verify("https://example.com/a.png", ""));
+    }
+
+    #[test]
+    fn test_proxied_urls() {
+        let proxy = proxy();
+        assert_eq!(
+            proxy.proxied_url("https://example.com/a b.png"),
+            Some(format!(
+                "/proxy/image?url=https%3A%2F%2Fexample.com%2Fa+b.png&sig={}",
+                proxy.signature("https://example.com/a b.png")
+            ))
+        );
+        assert_eq!(proxy.proxied_url("/relative.png"), None);
+        assert_eq!(proxy.proxied_url("data:image/png;base64,AAAA"), None);
+        assert_eq!(
+            proxy.proxied_srcset("https://example.com/a.png 1x, /b.png 2x"),
+            Some(format!(
+                "{} 1x",
+                proxy.proxied_url("https://example.com/a.png").unwrap()
+            ))
+        );
+        assert_eq!(proxy.proxied_srcset("/b.png 2x"), None);
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let mut index = CacheIndex::default();
+        index.insert("a".to_string(), 10);
+        index.insert("b".to_string(), 10);
+        index.insert("c".to_string(), 10);
+        assert!(index.touch("a"));
+        assert_eq!(index.evict(20), vec!["b".to_string()]);
+        assert_eq!(index.evict(10), vec!["c".to_string()]);
+        assert!(!index.touch("b"));
+        assert_eq!(index.total_size, 10);
+    }
+
+    #[test]
+    fn test_new_drops_partial_images() {
+        let cache_dir = std::env::temp_dir().join(format!("rss-image-proxy-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        let key = cache_key("https://example.com/a.png");
+        std::fs::write(cache_dir.join(&key), b"image/png\n\x89PNG").unwrap();
+        let partial = cache_dir.join(format!("{}.{}.{}", key, Uuid::new_v4(), PARTIAL_EXTENSION));
+        std::fs::write(&partial, b"image/png\n").unwrap();
+        let proxy = ImageProxy::new(&ImageProxySettings {
+            secret: Some("secret".to_string()),
+            cache_dir: cache_dir.to_string_lossy().to_string(),
+            max_cache_size: 1024,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!partial.exists());
+        assert!(proxy.index().touch(&key));
+        assert_eq!(proxy.index().entries.len(), 1);
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_cached() {
+        assert_eq!(
+            split_cached(b"image/png\n\x89PNG"),
+            Some(("image/png".to_string(), b"\x89PNG".to_vec()))
+        );
+        assert_eq!(split_cached(b"text/html\n<script>"), None);
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use lru_time_cache::LruCache;
+
+/// Failures are forgotten after this long without a new failure
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Failures before the responses start getting slowed down
+const FREE_ATTEMPTS: u32 = 3;
+const MAX_DELAY: Duration = Duration::from_secs(8);
+/// Failures before the key gets locked out
+const LOCKOUT_AFTER: u32 = 10;
+const LOCKOUT_DURATION: Duration = Duration::from_secs(15 * 60);
+const MAX_TRACKED_KEYS: usize = 100_000;
+
+#[derive(Debug, Clone)]
+struct Failures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Tracks failed logins per ip and per username in memory, to slow down and then lock out
+/// password guessing. Signups are tracked per ip the same way, to limit the accounts one
+/// address can create.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    failures: Arc<Mutex<LruCache<String, Failures>>>,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self {
+            failures: Arc::new(Mutex::new(LruCache::with_expiry_duration_and_capacity(
+                FAILURE_WINDOW,
+                MAX_TRACKED_KEYS,
+            ))),
+        }
+    }
+}
+
+impl LoginThrottle {
+    pub fn ip_key(ip: &str) -> String {
+        format!("ip:{}", ip)
+    }
+
+    pub fn username_key(username: &str) -> String {
+        format!("user:{}", username.trim().to_lowercase())
+    }
+
+    pub fn signup_key(ip: &str) -> String {
+        format!("signup:{}", ip)
+    }
+
+    fn failures(&self) -> MutexGuard<'_, LruCache<String, Failures>> {
+        self.failures
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// How long until the first of the keys that is locked out gets unlocked
+    pub fn locked_for(&self, keys: &[String]) -> Option<Duration> {
+        let now = Instant::now();
+        let failures = self.failures();
+        keys.iter()
+            .filter_map(|key| failures.peek(key)?.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .max()
+            .map(|locked_until| locked_until - now)
+    }
+
+    /// Records a failure for all the keys, returning how long to delay the response
+    pub fn record_failure(&self, keys: &[String]) -> Duration {
+        let mut failures = self.failures();
+        let mut most_failures = 0;
+        for key in keys {
+            let entry = failures.entry(key.clone()).or_insert(Failures {
+                count: 0,
+                locked_until: None,
+            });
+            entry.count += 1;
+            if entry.count >= LOCKOUT_AFTER {
+                entry.locked_until = Some(Instant::now() + LOCKOUT_DURATION);
+            }
+            most_failures = most_failures.max(entry.count);
+        }
+        delay_for(most_failures)
+    }
+
+    pub fn record_success(&self, key: &str) {
+        self.failures().remove(key);
+    }
+}
+
+/// Doubles with every failure past the free attempts, up to `MAX_DELAY`
+fn delay_for(failures: u32) -> Duration {
+    if failures <= FREE_ATTEMPTS {
+        return Duration::from_secs(0);
+    }
+    let exponent = (failures - FREE_ATTEMPTS - 1).min(16);
+    Duration::from_millis(500 * 2u64.pow(exponent)).min(MAX_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_for() {
+        assert_eq!(delay_for(1), Duration::from_secs(0));
+        assert_eq!(delay_for(FREE_ATTEMPTS), Duration::from_secs(0));
+        assert_eq!(delay_for(FREE_ATTEMPTS + 1), Duration::from_millis(500));
+        assert_eq!(delay_for(FREE_ATTEMPTS + 2), Duration::from_secs(1));
+        assert_eq!(delay_for(100), MAX_DELAY);
+    }
+
+    #[test]
+    fn test_lockout() {
+        let throttle = LoginThrottle::default();
+        let keys = vec![
+            LoginThrottle::ip_key("127.0.0.1"),
+            LoginThrottle::username_key("Test"),
+        ];
+        for _ in 1..LOCKOUT_AFTER {
+            throttle.record_failure(&keys);
+        }
+        assert!(throttle.locked_for(&keys).is_none());
+        throttle.record_failure(&keys);
+        assert!(throttle.locked_for(&keys).is_some());
+        assert!(throttle
+            .locked_for(&[LoginThrottle::username_key("test")])
+            .is_some());
+
+        throttle.record_success(&LoginThrottle::username_key("test"));
+        assert!(throttle
+            .locked_for(&[LoginThrottle::username_key("test")])
+            .is_none());
+        assert!(throttle.locked_for(&keys).is_some());
+    }
+}
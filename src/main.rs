@@ -12,13 +12,24 @@ use actix_web::rt::{
 
 use chrono::Utc;
 use color_eyre::Report;
-use futures::{select, stream, FutureExt, StreamExt};
+use futures::{select, FutureExt};
 
 use settings::Settings;
 use tracing::{error, info, warn};
 
+pub mod archive;
 pub mod clients;
 pub mod dto;
+pub mod favicon;
+pub mod highlight;
+pub mod image_proxy;
+pub mod login_throttle;
+pub mod oidc;
+pub mod poll;
+pub mod readability;
+pub mod retention;
+pub mod rules;
+pub mod sanitizer;
 pub mod server;
 pub mod session;
 pub mod settings;
@@ -30,6 +41,7 @@ async fn main() -> color_eyre::Result<()> {
     let clients = Clients::new(Settings::new().unwrap()).await?;
 
     let server = spawn_server(clients.clone());
+    let prune_task = spawn(retention::prune_daemon(clients.clone()));
     let task2 = spawn(async move {
         async {
             let mut sigup = signal(SignalKind::hangup())?;
@@ -37,22 +49,12 @@ async fn main() -> color_eyre::Result<()> {
                 match select! {
                     x = timeout(Duration::from_secs(clients.settings.time_of_polling_items + 60), async {
                         let start = Utc::now();
-                        let items_to_insert: Vec<_> = stream::iter(dto::Subscription::fetch_all(&clients.pool).await?.into_iter().map(|subscription| async move{
-                            subscription.get_items().await
-                        })).buffer_unordered(10).filter_map(|x| async {match x {
-                            Ok(x) => Some(x),
-                            Err(e) => {
-                                warn!("Ran into issues getting rss: {:?}", e);
-                                None
-                            }
-                        }}).concat().await;
-                        let mut transaction = clients.pool.begin().await?;
-                        for item in items_to_insert.iter() {
-                            item.insert(&mut transaction).await?;
+                        let report = poll::poll(&clients, dto::Subscription::fetch_all(&clients.pool).await?).await?;
+                        for (_, e) in report.errors.iter() {
+                            warn!("Ran into issues getting rss: {:?}", e);
                         }
-                        transaction.commit().await?;
                         let duration = Utc::now().sub(start);
-                        info!("Time to insert {} items: {}", items_to_insert.len(), duration);
+                        info!("Time to insert {} items: {}", report.new_items, duration);
 
                         time::sleep(Duration::from_secs(clients.settings.time_of_polling_items)).await;
                         Ok::<_, Report>(())
@@ -73,6 +75,7 @@ async fn main() -> color_eyre::Result<()> {
     });
     server.await?;
     task2.await?;
+    prune_task.await?;
     Ok(())
 }
 fn install_tracing() -> color_eyre::Result<()> {
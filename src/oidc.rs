@@ -0,0 +1,201 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use color_eyre::{eyre::eyre, Result};
+use lru_time_cache::LruCache;
+use openidconnect::{
+    core::{CoreClient, CoreIdToken, CoreProviderMetadata, CoreResponseType},
+    reqwest::async_http_client,
+    AuthenticationFlow, AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use tracing::instrument;
+
+use crate::settings::OidcSettings;
+
+/// How long the user has to come back from the provider
+const PENDING_LOGIN_DURATION: Duration = Duration::from_secs(10 * 60);
+const MAX_PENDING_LOGINS: usize = 10_000;
+
+/// What we need to remember between sending the user to the provider and the callback
+struct PendingLogin {
+    nonce: Nonce,
+    pkce_verifier: PkceCodeVerifier,
+}
+
+/// The identity the provider vouched for
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub issuer: String,
+    pub subject: String,
+    pub username: Option<String>,
+}
+
+/// Authorization code flow with PKCE against the configured issuer
+#[derive(Clone)]
+pub struct Oidc {
+    client: CoreClient,
+    /// Keyed by the state sent to the provider
+    pending: Arc<Mutex<LruCache<String, PendingLogin>>>,
+}
+
+impl Oidc {
+    /// Fetches the provider metadata and keys of the issuer
+    #[instrument]
+    pub async fn discover(settings: &OidcSettings) -> Result<Self> {
+        let provider_metadata = CoreProviderMetadata::discover_async(
+            IssuerUrl::new(settings.issuer_url.clone())?,
+            async_http_client,
+        )
+        .await
+        .map_err(|e| eyre!("Could not discover {}: {:?}", settings.issuer_url, e))?;
+        let client = CoreClient::from_provider_metadata(
+            provider_metadata,
+            ClientId::new(settings.client_id.clone()),
+            settings.client_secret.clone().map(ClientSecret::new),
+        )
+        .set_redirect_uri(RedirectUrl::new(settings.redirect_url.clone())?);
+        Ok(Self::new(client))
+    }
+
+    pub fn new(client: CoreClient) -> Self {
+        Self {
+            client,
+            pending: Arc::new(Mutex::new(LruCache::with_expiry_duration_and_capacity(
+                PENDING_LOGIN_DURATION,
+                MAX_PENDING_LOGINS,
+            ))),
+        }
+    }
+
+    fn pending(&self) -> MutexGuard<'_, LruCache<String, PendingLogin>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The url to send the user to, along with the state that has to come back on the callback
+    pub fn start_login(&self) -> (String, String) {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, state, nonce) = self
+            .client
+            .authorize_url(
+                AuthenticationFlow::<CoreResponseType>::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new("profile".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+        self.pending().insert(
+            state.secret().clone(),
+            PendingLogin {
+                nonce,
+                pkce_verifier,
+            },
+        );
+        (url.to_string(), state.secret().clone())
+    }
+
+    /// Trades the code from the callback for the verified identity, a state can only be used once
+    #[instrument(skip(self, code))]
+    pub async fn finish_login(&self, state: &str, code: &str) -> Result<Identity> {
+        let pending = self
+            .pending()
+            .remove(state)
+            .ok_or_else(|| eyre!("Unknown or expired login state"))?;
+        let token_response = self
+            .client
+            .exchange_code(AuthorizationCode::new(code.to_string()))
+            .set_pkce_verifier(pending.pkce_verifier)
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| eyre!("Could not exchange the code: {:?}", e))?;
+        let id_token = token_response
+            .id_token()
+            .ok_or_else(|| eyre!("Provider did not return an id token"))?;
+        self.verify_id_token(id_token, &pending.nonce)
+    }
+
+    fn verify_id_token(&self, id_token: &CoreIdToken, nonce: &Nonce) -> Result<Identity> {
+        let claims = id_token
+            .claims(&self.client.id_token_verifier(), nonce)
+            .map_err(|e| eyre!("Invalid id token: {:?}", e))?;
+        Ok(Identity {
+            issuer: claims.issuer().to_string(),
+            subject: claims.subject().to_string(),
+            username: claims
+                .preferred_username()
+                .map(|username| username.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openidconnect::{core::CoreJsonWebKeySet, AuthUrl, TokenUrl};
+    use serde::Deserialize;
+
+    use super::*;
+
+    /// Keys and a signed id token of a fake provider, see `test/artifacts/oidc_provider.json`
+    #[derive(Deserialize)]
+    struct MockProvider {
+        issuer: String,
+        client_id: String,
+        nonce: String,
+        jwks: CoreJsonWebKeySet,
+        id_token: CoreIdToken,
+    }
+
+    fn mock_provider() -> (MockProvider, Oidc) {
+        let provider: MockProvider = serde_json::from_str(
+            &std::fs::read_to_string("./test/artifacts/oidc_provider.json").unwrap(),
+        )
+        .unwrap();
+        let client = CoreClient::new(
+            ClientId::new(provider.client_id.clone()),
+            None,
+            IssuerUrl::new(provider.issuer.clone()).unwrap(),
+            AuthUrl::new(format!("{}/authorize", provider.issuer)).unwrap(),
+            Some(TokenUrl::new(format!("{}/token", provider.issuer)).unwrap()),
+            None,
+            provider.jwks.clone(),
+        )
+        .set_redirect_uri(RedirectUrl::new("http://localhost/login/oidc/callback".into()).unwrap());
+        (provider, Oidc::new(client))
+    }
+
+    #[test]
+    fn test_start_login() {
+        let (_, oidc) = mock_provider();
+        let (url, state) = oidc.start_login();
+        assert!(url.starts_with("https://issuer.test/authorize?"));
+        assert!(url.contains("code_challenge_method=S256"));
+        assert!(url.contains(&format!("state={}", state)));
+        assert!(oidc.pending().peek(&state).is_some());
+    }
+
+    #[test]
+    fn test_verify_id_token() {
+        let (provider, oidc) = mock_provider();
+        let identity = oidc
+            .verify_id_token(&provider.id_token, &Nonce::new(provider.nonce.clone()))
+            .unwrap();
+        assert_eq!(identity.issuer, "https://issuer.test");
+        assert_eq!(identity.subject, "248289761001");
+        assert_eq!(identity.username.as_deref(), Some("jane"));
+
+        assert!(oidc
+            .verify_id_token(&provider.id_token, &Nonce::new("other".to_string()))
+            .is_err());
+    }
+
+    #[actix_web::rt::test]
+    async fn test_unknown_state() {
+        let (_, oidc) = mock_provider();
+        assert!(oidc.finish_login("not-a-state", "code").await.is_err());
+    }
+}
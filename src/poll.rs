@@ -0,0 +1,196 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use actix_web::rt::spawn;
+use color_eyre::{Report, Result};
+use futures::{stream, StreamExt};
+use lru_time_cache::LruCache;
+use tracing::{error, instrument};
+
+use crate::{archive, clients::Clients, dto::Subscription, favicon, readability, rules};
+
+/// Feeds fetched at the same time
+const CONCURRENT_FETCHES: usize = 10;
+/// A subscription is refreshed by hand at most once in this long, so the feed hosts are not
+/// hammered. The polls of the daemon do not count, they would leave no time for a refresh when
+/// the daemon polls more often than this.
+const REFRESH_DEBOUNCE: Duration = Duration::from_secs(5 * 60);
+const MAX_TRACKED_SUBSCRIPTIONS: usize = 100_000;
+
+#[derive(Debug, Default)]
+pub struct PollReport {
+    pub new_items: usize,
+    /// The subscriptions whose feed could not be fetched or read
+    pub errors: Vec<(Subscription, Report)>,
+}
+
+/// Fetches the feeds and inserts their new items, used by the daemon and the manual refreshes
+#[instrument(skip(clients, subscriptions))]
+pub async fn poll(clients: &Clients, subscriptions: Vec<Subscription>) -> Result<PollReport> {
+    let fetched: Vec<_> = stream::iter(subscriptions.into_iter().map(|subscription| async move {
+        let items = subscription.get_items().await;
+        (subscription, items)
+    }))
+    .buffer_unordered(CONCURRENT_FETCHES)
+    .collect()
+    .await;
+    let mut report = PollReport::default();
+    let mut items_to_insert = Vec::new();
+    for (subscription, items) in fetched {
+        match items {
+            Ok(items) => items_to_insert.extend(items),
+            Err(e) => report.errors.push((subscription, e)),
+        }
+    }
+    let mut transaction = clients.pool.begin().await?;
+    let mut new_items = Vec::new();
+    for item in items_to_insert.iter() {
+        if let Some(id) = item.insert(&mut transaction).await? {
+            new_items.push((id, item));
+        }
+    }
+    transaction.commit().await?;
+    report.new_items = new_items.len();
+    if let Err(e) = rules::apply_to_new_items(&new_items, &clients.pool).await {
+        error!("Could not apply the filter rules: {:?}", e);
+    }
+    // Downloading the articles can take longer than a poll, so it runs on its own
+    let new_items: Vec<_> = new_items
+        .into_iter()
+        .map(|(id, item)| (id, item.clone()))
+        .collect();
+    let pool = clients.pool.clone();
+    spawn(async move {
+        if let Err(e) = readability::fill_new_items(new_items, pool.clone()).await {
+            error!("Could not fill in the full articles: {:?}", e);
+        }
+        if let Err(e) = archive::archive_starred(&pool).await {
+            error!("Could not archive the starred items: {:?}", e);
+        }
+        if let Err(e) = favicon::refresh_stale_icons(&pool).await {
+            error!("Could not refresh the icons: {:?}", e);
+        }
+    });
+    Ok(report)
+}
+
+/// Remembers when the subscriptions were last refreshed by hand, shared between the users
+#[derive(Clone)]
+pub struct Refreshes {
+    refreshed: Arc<Mutex<LruCache<i64, Instant>>>,
+}
+
+impl Default for Refreshes {
+    fn default() -> Self {
+        Self {
+            refreshed: Arc::new(Mutex::new(LruCache::with_expiry_duration_and_capacity(
+                REFRESH_DEBOUNCE,
+                MAX_TRACKED_SUBSCRIPTIONS,
+            ))),
+        }
+    }
+}
+
+impl Refreshes {
+    fn refreshed(&self) -> MutexGuard<'_, LruCache<i64, Instant>> {
+        self.refreshed
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Marks the subscriptions that can be refreshed now and returns them, with how long until
+    /// the first of the others can be refreshed again
+    pub fn start(&self, subscriptions: Vec<Subscription>) -> (Vec<Subscription>, Option<Duration>) {
+        let now = Instant::now();
+        let mut refreshed = self.refreshed();
+        let mut retry_in: Option<Duration> = None;
+        let mut ready = Vec::new();
+        for subscription in subscriptions {
+            let wait = refreshed
+                .peek(&subscription.id)
+                .map(|last| REFRESH_DEBOUNCE.saturating_sub(now.duration_since(*last)))
+                .filter(|wait| *wait > Duration::from_secs(0));
+            match wait {
+                Some(wait) => retry_in = Some(retry_in.map_or(wait, |x| x.min(wait))),
+                None => {
+                    refreshed.insert(subscription.id, now);
+                    ready.push(subscription);
+                }
+            }
+        }
+        (ready, retry_in)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_refreshes() {
+        let refreshes = Refreshes::default();
+        let subscription = |id| Subscription {
+            id,
+            rss_feed: format!("https://example.com/{}.xml", id),
+        };
+        let (ready, retry_in) = refreshes.start(vec![subscription(1)]);
+        assert_eq!(ready.len(), 1);
+        assert!(retry_in.is_none());
+
+        let (ready, retry_in) = refreshes.start(vec![subscription(1), subscription(2)]);
+        assert_eq!(ready.iter().map(|x| x.id).collect::<Vec<_>>(), vec![2]);
+        assert!(retry_in.unwrap() <= REFRESH_DEBOUNCE);
+
+        let (ready, retry_in) = refreshes.start(vec![subscription(1), subscription(2)]);
+        assert!(ready.is_empty());
+        assert!(retry_in.is_some());
+    }
+
+    #[actix_web::rt::test]
+    async fn test_refresh_after_daemon_poll() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let rss_feed = format!("http://{}/feed.xml", listener.local_addr().unwrap());
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counted = fetches.clone();
+        std::thread::spawn(move || {
+            let feed = r#"<rss version="2.0"><channel><title>Feed</title><link>https://example.com</link><description>Feed</description></channel></rss>"#;
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0; 1024]);
+                counted.fetch_add(1, Ordering::SeqCst);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/rss+xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    feed.len(),
+                    feed
+                );
+            }
+        });
+        let clients = Clients::in_memory(Settings::for_tests()).await.unwrap();
+        let refreshes = Refreshes::default();
+        let subscription = Subscription { id: 1, rss_feed };
+
+        // What the daemon does on each pass
+        poll(&clients, vec![subscription.clone()]).await.unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        let (ready, retry_in) = refreshes.start(vec![subscription.clone()]);
+        assert_eq!(ready.len(), 1);
+        assert!(retry_in.is_none());
+        let report = poll(&clients, ready).await.unwrap();
+        assert!(report.errors.is_empty());
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        let (ready, retry_in) = refreshes.start(vec![subscription]);
+        assert!(ready.is_empty());
+        assert!(retry_in.is_some());
+    }
+}
@@ -0,0 +1,646 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    rc::Rc,
+    time::Duration,
+};
+
+use color_eyre::{eyre::eyre, Result};
+use futures::{stream, StreamExt};
+use html5ever::{
+    parse_document,
+    serialize::{serialize, SerializeOpts, TraversalScope},
+    tendril::TendrilSink,
+    Attribute, LocalName, Namespace, QualName,
+};
+use markup5ever_rcdom::{Handle, Node, NodeData, RcDom, SerializableHandle};
+use reqwest::{
+    header::{LOCATION, RANGE},
+    redirect::Policy,
+};
+use sqlx::SqlitePool;
+use tokio::{net::lookup_host, task::spawn_blocking};
+use tracing::{instrument, warn};
+use url::{Host, Url};
+
+use crate::dto::{Item, ItemInsert, SubscriptionSettings};
+
+/// Pages bigger than this are not read
+const MAX_PAGE_SIZE: usize = 5 * 1024 * 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+/// Redirects followed before giving up
+const MAX_REDIRECTS: usize = 10;
+/// Pages downloaded at the same time when filling in new items
+const CONCURRENT_FETCHES: usize = 4;
+/// Paragraphs shorter than this do not count towards the score of their parents
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+/// Below this much text the page is taken to be an image, like the pages of comics
+const MIN_ARTICLE_LENGTH: usize = 140;
+
+/// Never part of the article
+const REMOVED_TAGS: &[&str] = &[
+    "script", "style", "noscript", "iframe", "form", "nav", "header", "footer", "aside", "button",
+    "input", "select", "textarea", "svg", "link", "meta",
+];
+const UNLIKELY: &[&str] = &[
+    "banner",
+    "combx",
+    "comment",
+    "community",
+    "cookie",
+    "disqus",
+    "extra",
+    "foot",
+    "header",
+    "menu",
+    "modal",
+    "nav",
+    "popup",
+    "promo",
+    "related",
+    "remark",
+    "rss",
+    "share",
+    "shoutbox",
+    "sidebar",
+    "social",
+    "sponsor",
+    "subscribe",
+    "tags",
+    "tool",
+    "widget",
+];
+const LIKELY: &[&str] = &[
+    "article", "body", "column", "content", "entry", "h-entry", "main", "page", "post", "story",
+    "text",
+];
+
+/// Fills in the full article of the new items of the subscriptions someone wants the full
+/// articles of. Pages that fail are skipped, those items only have what the feed gave.
+#[instrument(skip(new_items, pool))]
+pub async fn fill_new_items(new_items: Vec<(i64, ItemInsert)>, pool: SqlitePool) -> Result<()> {
+    let wanted = SubscriptionSettings::fetch_full_content_subscriptions(&pool).await?;
+    let pool = &pool;
+    stream::iter(
+        new_items
+            .into_iter()
+            .filter(|(_, item)| wanted.contains(&item.subscription_id)),
+    )
+    .map(|(id, item)| async move { (id, fetch_full_content(&item.link).await) })
+    .buffer_unordered(CONCURRENT_FETCHES)
+    .for_each(|(id, contents)| async move {
+        let stored = match contents {
+            Ok(Some(contents)) => Item::update_full_contents(id, &contents, pool).await,
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = stored {
+            warn!("Could not fill in the article of item {}: {:?}", id, e);
+        }
+    })
+    .await;
+    Ok(())
+}
+
+/// Downloads the page of an item and pulls out the article, see `extract`
+pub async fn fetch_full_content(link: &str) -> Result<Option<String>> {
+    let (page, base) = fetch_page(link).await?;
+    // Big pages take a while to read, off the workers serving the requests
+    Ok(spawn_blocking(move || extract(&page, &base)).await?)
+}
+
+/// The html of the page with the url it came from, after redirects
+pub async fn fetch_page(link: &str) -> Result<(String, Url)> {
+    let (page, base, _) = fetch(link, MAX_PAGE_SIZE).await?;
+    Ok((String::from_utf8_lossy(&page).into_owned(), base))
+}
+
+/// Downloads at most `max_size` bytes over http, with the final url and the content type, see
+/// `mime_type`
+pub(crate) async fn fetch(link: &str, max_size: usize) -> Result<(Vec<u8>, Url, Option<String>)> {
+    let mut response = get(link, None, Some(FETCH_TIMEOUT)).await?;
+    let url = response.url().clone();
+    let content_type = mime_type(response.headers());
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > max_size {
+            return Err(eyre!("{} is bigger than {} bytes", link, max_size));
+        }
+    }
+    Ok((body, url, content_type))
+}
+
+/// Starts a GET of a link from a feed or a page, with the `range` of the body when given. Anyone
+/// can put any link in their feed, so the links to our own network, like the loopback, the
+/// private ranges or the metadata service of the cloud at 169.254.169.254, are refused. The
+/// redirects are followed by hand to check them the same way, and the connection goes to the
+/// address that was checked, so the name can not resolve somewhere else in between.
+pub(crate) async fn get(
+    link: &str,
+    range: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<reqwest::Response> {
+    let mut url = Url::parse(link)?;
+    for _ in 0..=MAX_REDIRECTS {
+        let address = public_address(&url).await?;
+        let mut client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .connect_timeout(CONNECT_TIMEOUT);
+        if let Some(timeout) = timeout {
+            client = client.timeout(timeout);
+        }
+        if let Some(Host::Domain(domain)) = url.host() {
+            client = client.resolve(domain, address);
+        }
+        let mut request = client.build()?.get(url.clone());
+        if let Some(range) = range {
+            request = request.header(RANGE, range);
+        }
+        let response = request.send().await?;
+        let location = match response
+            .headers()
+            .get(LOCATION)
+            .and_then(|x| x.to_str().ok())
+        {
+            Some(location) if response.status().is_redirection() => url.join(location)?,
+            _ => return Ok(response.error_for_status()?),
+        };
+        url = location;
+    }
+    Err(eyre!(
+        "{} redirects more than {} times",
+        link,
+        MAX_REDIRECTS
+    ))
+}
+
+/// Where to connect to for the url, when all the addresses of the host are public
+async fn public_address(url: &Url) -> Result<SocketAddr> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(eyre!("Cannot fetch {}", url));
+    }
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| eyre!("No port for {}", url))?;
+    let addresses: Vec<SocketAddr> = match url.host() {
+        Some(Host::Domain(domain)) => lookup_host((domain, port)).await?.collect(),
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        None => Vec::new(),
+    };
+    match addresses.iter().find(|x| !is_public(x.ip())) {
+        Some(private) => Err(eyre!("{} is at the private address {}", url, private.ip())),
+        None => addresses
+            .first()
+            .copied()
+            .ok_or_else(|| eyre!("{} has no address", url)),
+    }
+}
+
+/// False for the addresses that are not on the internet, like `IpAddr::is_global` which is not
+/// stable yet. IPv6 addresses carrying an IPv4 address are judged by that address.
+pub(crate) fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // This network
+        || a == 0
+        // Shared between the customers of a provider
+        || (a == 100 && (64..128).contains(&b))
+        // Protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking
+        || (a == 198 && (b == 18 || b == 19))
+        // Reserved
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // IPv4 compatible and mapped, `::1` and `::` land in 0.0.0.0/8 which is refused
+    if let Some(v4) = ip.to_ipv4() {
+        return is_public_v4(v4);
+    }
+    // The NAT64 prefix
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [a, b] = segments[6].to_be_bytes();
+        let [c, d] = segments[7].to_be_bytes();
+        return is_public_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    !(ip.is_multicast()
+        // Unique local
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link local and the old site local
+        || (segments[0] & 0xffc0) == 0xfe80
+        || (segments[0] & 0xffc0) == 0xfec0
+        // Documentation
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+/// The type of a response without its parameters, like the charset, in lowercase
+pub(crate) fn mime_type(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.split(';').next())
+        .map(|x| x.trim().to_lowercase())
+}
+
+/// Readability style extraction of the main content of a page. Paragraphs score their parents
+/// by their length and commas, class names that look like content or clutter move the score,
+/// and links count against it. The best scored element is the article. Pages with little
+/// text, like comics, fall back to their `og:image`. Urls are made absolute with `base`.
+pub fn extract(html: &str, base: &Url) -> Option<String> {
+    let dom = parse_document(RcDom::default(), Default::default()).one(html);
+    let og_image = meta_content(&dom.document, "og:image");
+    clean(&dom.document);
+    let stats = text_stats(&dom.document);
+    let stats_of = |handle: &Handle| stats.get(&Rc::as_ptr(handle)).copied().unwrap_or_default();
+
+    let mut scores: HashMap<*const Node, (Handle, f64)> = HashMap::new();
+    for paragraph in descendants(&dom.document).into_iter().filter(|x| {
+        matches!(
+            tag_name(x).as_deref(),
+            Some("p" | "pre" | "td" | "blockquote")
+        )
+    }) {
+        let text = stats_of(&paragraph);
+        if text.length < MIN_PARAGRAPH_LENGTH {
+            continue;
+        }
+        let score = 1.0 + text.commas as f64 + (text.length as f64 / 100.0).min(3.0);
+        let parent = match parent(&paragraph) {
+            Some(parent) => parent,
+            None => continue,
+        };
+        let grandparent = parent_element(&parent);
+        add_score(&mut scores, &parent, score);
+        if let Some(grandparent) = grandparent {
+            add_score(&mut scores, &grandparent, score / 2.0);
+        }
+    }
+    let best = scores
+        .into_iter()
+        .map(|(_, (handle, score))| {
+            let score = score * (1.0 - stats_of(&handle).link_density());
+            (handle, score)
+        })
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(handle, _)| handle);
+
+    let article = best.filter(|x| stats_of(x).length >= MIN_ARTICLE_LENGTH);
+    match (article, og_image) {
+        (Some(article), _) => {
+            absolute_urls(&article, base);
+            serialize_children(&article)
+        }
+        (None, Some(image)) => {
+            let image = base.join(&image).ok()?;
+            Some(format!(
+                r#"<p><img src="{}"></p>"#,
+                image.as_str().replace('"', "%22")
+            ))
+        }
+        (None, None) => None,
+    }
+}
+
+pub(crate) fn tag_name(handle: &Handle) -> Option<String> {
+    match &handle.data {
+        NodeData::Element { name, .. } => Some(name.local.to_string()),
+        _ => None,
+    }
+}
+
+pub(crate) fn attribute(handle: &Handle, attribute: &str) -> Option<String> {
+    match &handle.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|x| &*x.name.local == attribute)
+            .map(|x| x.value.to_string()),
+        _ => None,
+    }
+}
+
+pub(crate) fn set_attribute(handle: &Handle, attribute: &str, value: &str) {
+    if let NodeData::Element { attrs, .. } = &handle.data {
+        let mut attrs = attrs.borrow_mut();
+        match attrs.iter_mut().find(|x| &*x.name.local == attribute) {
+            Some(existing) => existing.value = value.into(),
+            None => attrs.push(Attribute {
+                name: QualName::new(None, Namespace::from(""), LocalName::from(attribute)),
+                value: value.into(),
+            }),
+        }
+    }
+}
+
+pub(crate) fn remove_attribute(handle: &Handle, attribute: &str) {
+    if let NodeData::Element { attrs, .. } = &handle.data {
+        attrs.borrow_mut().retain(|x| &*x.name.local != attribute);
+    }
+}
+
+fn parent(handle: &Handle) -> Option<Handle> {
+    let weak = handle.parent.take();
+    let parent = weak.as_ref().and_then(|x| x.upgrade());
+    handle.parent.set(weak);
+    parent
+}
+
+fn parent_element(handle: &Handle) -> Option<Handle> {
+    parent(handle).filter(|x| tag_name(x).is_some())
+}
+
+/// In document order. Pages can nest deep enough to run out of stack, so the tree is walked
+/// without recursion, here and in the other walks.
+pub(crate) fn descendants(handle: &Handle) -> Vec<Handle> {
+    let mut found = Vec::new();
+    let mut stack: Vec<Handle> = handle.children.borrow().iter().rev().cloned().collect();
+    while let Some(node) = stack.pop() {
+        stack.extend(node.children.borrow().iter().rev().cloned());
+        found.push(node);
+    }
+    found
+}
+
+/// How much text an element holds, with its commas and how much of it sits in links
+#[derive(Debug, Clone, Copy, Default)]
+struct TextStats {
+    length: usize,
+    commas: usize,
+    link_length: usize,
+}
+
+impl TextStats {
+    /// Menus are mostly links
+    fn link_density(&self) -> f64 {
+        if self.length == 0 {
+            return 1.0;
+        }
+        self.link_length as f64 / self.length as f64
+    }
+}
+
+/// The text of every node, summed up from the leaves in one pass instead of walking the
+/// subtree of every element
+fn text_stats(document: &Handle) -> HashMap<*const Node, TextStats> {
+    let mut stats: HashMap<*const Node, TextStats> = HashMap::new();
+    // Backwards through the document order the children come before their parent
+    for handle in descendants(document).iter().rev() {
+        let node_stats = match &handle.data {
+            NodeData::Text { contents } => {
+                let text = contents.borrow();
+                TextStats {
+                    length: text.chars().count(),
+                    commas: text.matches(',').count(),
+                    link_length: 0,
+                }
+            }
+            _ => {
+                let mut total = TextStats::default();
+                for child in handle.children.borrow().iter() {
+                    if let Some(child) = stats.get(&Rc::as_ptr(child)) {
+                        total.length += child.length;
+                        total.commas += child.commas;
+                        total.link_length += child.link_length;
+                    }
+                }
+                if tag_name(handle).as_deref() == Some("a") {
+                    total.link_length = total.length;
+                }
+                total
+            }
+        };
+        stats.insert(Rc::as_ptr(handle), node_stats);
+    }
+    stats
+}
+
+fn meta_content(document: &Handle, property: &str) -> Option<String> {
+    descendants(document)
+        .into_iter()
+        .filter(|x| tag_name(x).as_deref() == Some("meta"))
+        .find(|x| {
+            attribute(x, "property").as_deref() == Some(property)
+                || attribute(x, "name").as_deref() == Some(property)
+        })
+        .and_then(|x| attribute(&x, "content"))
+}
+
+/// The class and id of an element, to guess what the element holds
+fn class_and_id(handle: &Handle) -> String {
+    format!(
+        "{} {}",
+        attribute(handle, "class").unwrap_or_default(),
+        attribute(handle, "id").unwrap_or_default()
+    )
+    .to_lowercase()
+}
+
+fn class_weight(handle: &Handle) -> f64 {
+    let class_and_id = class_and_id(handle);
+    let mut weight = 0.0;
+    if LIKELY.iter().any(|x| class_and_id.contains(x)) {
+        weight += 25.0;
+    }
+    if UNLIKELY.iter().any(|x| class_and_id.contains(x)) {
+        weight -= 25.0;
+    }
+    weight
+}
+
+/// Drops the elements that are never part of an article
+fn clean(document: &Handle) {
+    let mut stack = vec![document.clone()];
+    while let Some(handle) = stack.pop() {
+        handle.children.borrow_mut().retain(|child| {
+            let name = match tag_name(child) {
+                Some(name) => name,
+                None => return !matches!(child.data, NodeData::Comment { .. }),
+            };
+            if REMOVED_TAGS.contains(&name.as_str()) {
+                return false;
+            }
+            if matches!(name.as_str(), "html" | "body" | "article" | "main") {
+                return true;
+            }
+            let class_and_id = class_and_id(child);
+            !(UNLIKELY.iter().any(|x| class_and_id.contains(x))
+                && !LIKELY.iter().any(|x| class_and_id.contains(x)))
+        });
+        stack.extend(handle.children.borrow().iter().cloned());
+    }
+}
+
+fn add_score(scores: &mut HashMap<*const Node, (Handle, f64)>, handle: &Handle, score: f64) {
+    let entry = scores.entry(Rc::as_ptr(handle)).or_insert_with(|| {
+        let initial = match tag_name(handle).as_deref() {
+            Some("article") => 10.0,
+            Some("div") => 5.0,
+            Some("pre" | "td" | "blockquote") => 3.0,
+            Some("address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form") => -3.0,
+            Some("h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th") => -5.0,
+            _ => 0.0,
+        };
+        (handle.clone(), initial + class_weight(handle))
+    });
+    entry.1 += score;
+}
+
+/// Lazy loaded images keep their url in `data-src`
+fn absolute_urls(handle: &Handle, base: &Url) {
+    for element in descendants(handle) {
+        let url_attribute = match tag_name(&element).as_deref() {
+            Some("a") => "href",
+            Some("img") => {
+                if attribute(&element, "src").is_none() {
+                    if let Some(lazy) = attribute(&element, "data-src") {
+                        set_attribute(&element, "src", &lazy);
+                    }
+                }
+                "src"
+            }
+            _ => continue,
+        };
+        if let Some(url) = attribute(&element, url_attribute) {
+            if let Ok(absolute) = base.join(&url) {
+                set_attribute(&element, url_attribute, absolute.as_str());
+            }
+        }
+    }
+}
+
+pub(crate) fn serialize_children(handle: &Handle) -> Option<String> {
+    let mut html = Vec::new();
+    serialize(
+        &mut html,
+        &SerializableHandle::from(handle.clone()),
+        SerializeOpts {
+            traversal_scope: TraversalScope::ChildrenOnly(None),
+            ..Default::default()
+        },
+    )
+    .ok()?;
+    String::from_utf8(html).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_article() {
+        let html = r#"<html><head><title>Post</title></head><body>
+            <nav class="menu"><a href="/">Home</a> <a href="/about">About us and the rest</a></nav>
+            <div class="sidebar"><p>Subscribe to the newsletter, it is great, really, trust us.</p></div>
+            <div class="post-content">
+                <p>The first paragraph of the article, long enough to count, with a comma.</p>
+                <p>The second paragraph goes on about the topic, and then, some more of it.</p>
+                <img src="/images/figure.png">
+            </div>
+            <footer>Copyright, all rights reserved, forever and ever and ever more.</footer>
+        </body></html>"#;
+        let base = Url::parse("https://example.com/posts/1").unwrap();
+        let article = extract(html, &base).unwrap();
+        assert!(article.contains("The first paragraph"));
+        assert!(article.contains("The second paragraph"));
+        assert!(article.contains(r#"src="https://example.com/images/figure.png""#));
+        assert!(!article.contains("newsletter"));
+        assert!(!article.contains("About us"));
+        assert!(!article.contains("Copyright"));
+    }
+
+    #[test]
+    fn test_extract_image_page() {
+        let html = r#"<html><head><meta property="og:image" content="/comics/42.png"></head>
+            <body><div class="comic"><img src="/comics/42.png"></div></body></html>"#;
+        let base = Url::parse("https://comic.example.com/42/").unwrap();
+        assert_eq!(
+            extract(html, &base).as_deref(),
+            Some(r#"<p><img src="https://comic.example.com/comics/42.png"></p>"#)
+        );
+        assert_eq!(extract("<p>Short</p>", &base), None);
+    }
+
+    #[test]
+    fn test_extract_deep_page() {
+        let paragraph = "<p>A paragraph long enough to count, with a comma or two, and more.</p>";
+        let html = format!(
+            "<html><body>{}{}</body></html>",
+            "<div>".repeat(5_000),
+            paragraph.repeat(3)
+        );
+        let base = Url::parse("https://example.com/deep").unwrap();
+        // On a small stack, walking the page with recursion runs out of it
+        let article = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || extract(&html, &base))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(article.unwrap().contains("A paragraph"));
+    }
+
+    #[test]
+    fn test_is_public() {
+        for public in &["93.184.216.34", "2606:2800:220:1::", "::ffff:93.184.216.34"] {
+            assert!(is_public(public.parse().unwrap()), "{}", public);
+        }
+        for private in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(!is_public(private.parse().unwrap()), "{}", private);
+        }
+    }
+
+    #[actix_web::rt::test]
+    async fn test_public_address() {
+        let address = public_address(&Url::parse("http://93.184.216.34/a").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(address, "93.184.216.34:80".parse().unwrap());
+        for refused in &[
+            "http://127.0.0.1:8080/",
+            "http://169.254.169.254/latest/meta-data/",
+            "https://[::1]/",
+            "http://[::ffff:10.0.0.1]/",
+            "file:///etc/passwd",
+        ] {
+            assert!(
+                public_address(&Url::parse(refused).unwrap()).await.is_err(),
+                "{}",
+                refused
+            );
+        }
+        assert!(get("http://127.0.0.1:1/", None, None).await.is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use actix_web::rt::{
+    signal::{
+        ctrl_c,
+        unix::{signal, SignalKind},
+    },
+    time,
+};
+use chrono::Utc;
+use color_eyre::{Report, Result};
+use futures::{select, FutureExt};
+use sqlx::SqlitePool;
+use tracing::{error, info, instrument};
+
+use crate::{
+    clients::Clients,
+    dto::{PrunableItem, SubscriptionRetention},
+    settings::RetentionSettings,
+};
+
+const DAY_S: i64 = 24 * 60 * 60;
+/// Tombstones are kept for this long, feeds rarely carry items that old
+const TOMBSTONE_DAYS: i64 = 365;
+
+/// The limits of a subscription, with its overrides applied to the global settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub max_age_days: Option<i64>,
+    pub max_items: Option<i64>,
+}
+
+impl RetentionPolicy {
+    pub fn new(settings: &RetentionSettings, retention: &SubscriptionRetention) -> Self {
+        Self {
+            max_age_days: limit(retention.max_age_days, settings.max_age_days),
+            max_items: limit(retention.max_items, settings.max_items_per_feed),
+        }
+    }
+}
+
+/// An override of 0 or less keeps the items forever
+fn limit(subscription: Option<i64>, global: Option<i64>) -> Option<i64> {
+    subscription.or(global).filter(|x| *x > 0)
+}
+
+/// Removes the items past the retention of their subscriptions, returns how many were pruned
+#[instrument(skip(pool))]
+pub async fn prune(settings: &RetentionSettings, pool: &SqlitePool) -> Result<usize> {
+    let now = Utc::now().timestamp();
+    let mut pruned = 0;
+    for retention in SubscriptionRetention::fetch_all(pool).await? {
+        let policy = RetentionPolicy::new(settings, &retention);
+        let mut items = Vec::new();
+        if let Some(max_age_days) = policy.max_age_days {
+            items.extend(
+                PrunableItem::fetch_older(
+                    retention.subscription_id,
+                    now - max_age_days * DAY_S,
+                    pool,
+                )
+                .await?,
+            );
+        }
+        if let Some(max_items) = policy.max_items {
+            items.extend(
+                PrunableItem::fetch_beyond(retention.subscription_id, max_items, pool).await?,
+            );
+        }
+        if items.is_empty() {
+            continue;
+        }
+        items.sort_by_key(|x| x.id);
+        items.dedup_by_key(|x| x.id);
+        let mut transaction = pool.begin().await?;
+        for item in items.iter() {
+            if item.prune(now, &mut transaction).await? {
+                pruned += 1;
+            }
+        }
+        transaction.commit().await?;
+    }
+    PrunableItem::delete_tombstones(now - TOMBSTONE_DAYS * DAY_S, pool).await?;
+    if pruned > 0 {
+        // Gives the space of the pruned items back, it can not run in a transaction
+        sqlx::query!("VACUUM").execute(pool).await?;
+    }
+    Ok(pruned)
+}
+
+/// Prunes every `interval_s`, next to the daemon polling the feeds
+pub async fn prune_daemon(clients: Clients) {
+    let result = async {
+        let mut sigup = signal(SignalKind::hangup())?;
+        let mut interval = time::interval(Duration::from_secs(
+            clients.settings.retention.interval_s.max(1),
+        ));
+        loop {
+            select! {
+                _ = interval.tick().fuse() => {
+                    match prune(&clients.settings.retention, &clients.pool).await {
+                        Ok(0) => (),
+                        Ok(pruned) => info!("Pruned {} items", pruned),
+                        Err(e) => error!("Item Prune Daemon error: {:?}", e),
+                    }
+                },
+                _ = ctrl_c().fuse() => break,
+                _ = sigup.recv().fuse() => break,
+            }
+        }
+        Ok::<_, Report>(())
+    }
+    .await;
+    if let Err(e) = result {
+        error!("Item Prune Daemon stopped: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        dto::{Item, ItemInsert, ItemNote, UserId, UserItem},
+        settings::Settings,
+    };
+
+    #[test]
+    fn test_policy() {
+        let settings = RetentionSettings {
+            max_age_days: Some(90),
+            max_items_per_feed: None,
+            ..Default::default()
+        };
+        let retention = |max_age_days, max_items| SubscriptionRetention {
+            subscription_id: 1,
+            max_age_days,
+            max_items,
+        };
+        assert_eq!(
+            RetentionPolicy::new(&settings, &retention(None, None)),
+            RetentionPolicy {
+                max_age_days: Some(90),
+                max_items: None
+            }
+        );
+        assert_eq!(
+            RetentionPolicy::new(&settings, &retention(Some(7), Some(100))),
+            RetentionPolicy {
+                max_age_days: Some(7),
+                max_items: Some(100)
+            }
+        );
+        assert_eq!(
+            RetentionPolicy::new(&settings, &retention(Some(0), None)),
+            RetentionPolicy {
+                max_age_days: None,
+                max_items: None
+            }
+        );
+    }
+
+    #[actix_web::rt::test]
+    async fn test_prune() {
+        let clients = Clients::in_memory(Settings::for_tests()).await.unwrap();
+        let pool = &clients.pool;
+        // The user and the subscription of the first migration
+        let user_id = UserId(1);
+        let item = |title: &str| ItemInsert {
+            subscription_id: 1,
+            title: title.to_string(),
+            link: format!("https://example.com/{}", title),
+            pub_date: 0,
+            author: None,
+            description: None,
+            contents: None,
+            comments: None,
+        };
+        let mut ids = HashMap::new();
+        for title in &["plain", "starred", "tagged", "noted", "late star"] {
+            ids.insert(*title, item(title).insert(pool).await.unwrap().unwrap());
+        }
+        UserItem::new(&user_id, ids["plain"])
+            .mark_read(0, pool)
+            .await
+            .unwrap();
+        UserItem::new(&user_id, ids["starred"])
+            .star(0, pool)
+            .await
+            .unwrap();
+        UserItem::new(&user_id, ids["tagged"])
+            .add_tag("keep", 0, pool)
+            .await
+            .unwrap();
+        ItemNote::save(&user_id, ids["noted"], "Remember this", 0, pool)
+            .await
+            .unwrap();
+
+        // Starred after the items were picked for pruning
+        let prunable = PrunableItem::fetch_older(1, 1, pool).await.unwrap();
+        let late_star = prunable.iter().find(|x| x.id == ids["late star"]).unwrap();
+        UserItem::new(&user_id, late_star.id)
+            .star(0, pool)
+            .await
+            .unwrap();
+        let mut transaction = pool.begin().await.unwrap();
+        assert!(!late_star.prune(0, &mut transaction).await.unwrap());
+        transaction.commit().await.unwrap();
+
+        let settings = RetentionSettings {
+            max_age_days: Some(1),
+            max_items_per_feed: None,
+            ..Default::default()
+        };
+        assert_eq!(prune(&settings, pool).await.unwrap(), 1);
+        assert!(Item::fetch(&user_id, ids["plain"], pool)
+            .await
+            .unwrap()
+            .is_none());
+        for title in &["starred", "tagged", "noted", "late star"] {
+            assert!(Item::fetch(&user_id, ids[title], pool)
+                .await
+                .unwrap()
+                .is_some());
+        }
+
+        // The feed still carries the pruned item
+        assert_eq!(item("plain").insert(pool).await.unwrap(), None);
+        assert_eq!(prune(&settings, pool).await.unwrap(), 0);
+    }
+}
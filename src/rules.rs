@@ -0,0 +1,305 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::Utc;
+use color_eyre::Result;
+use regex::{Regex, RegexBuilder};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use tracing::{instrument, warn};
+
+use crate::dto::{
+    FilterRule, Item, ItemInsert, RuleAction, RuleField, RuleMatch, RuleScope, UserId, UserItem,
+    UserSubscription,
+};
+use crate::server::from_requests::user_preferences::{FilterItems, ShowUnreads};
+
+/// Keeps a user from making the matching slow with a huge regex
+const REGEX_SIZE_LIMIT: usize = 1 << 16;
+/// Stored items read at a time when the rules are applied again
+const EXISTING_ITEMS_PAGE: i64 = 500;
+
+/// The parts of an item a rule can look at, so that new and stored items match the same way
+#[derive(Debug, Clone, Copy)]
+pub struct RuleTarget<'a> {
+    pub subscription_id: i64,
+    pub title: &'a str,
+    pub author: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub link: &'a str,
+}
+
+impl<'a> From<&'a ItemInsert> for RuleTarget<'a> {
+    fn from(item: &'a ItemInsert) -> Self {
+        Self {
+            subscription_id: item.subscription_id,
+            title: &item.title,
+            author: item.author.as_deref(),
+            description: item.description.as_deref(),
+            link: &item.link,
+        }
+    }
+}
+
+impl<'a> From<&'a Item> for RuleTarget<'a> {
+    fn from(item: &'a Item) -> Self {
+        Self {
+            subscription_id: item.subscription_id,
+            title: &item.title,
+            author: item.author.as_deref(),
+            description: item.description.as_deref(),
+            link: &item.link,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Matcher {
+    /// Lowercased, matched case insensitive
+    Keyword(String),
+    Regex(Regex),
+}
+
+/// A rule with its pattern ready for matching
+#[derive(Debug)]
+pub struct CompiledRule {
+    pub rule: FilterRule,
+    matcher: Matcher,
+}
+
+impl CompiledRule {
+    pub fn new(rule: FilterRule) -> Result<Self, regex::Error> {
+        let matcher = match rule.match_kind {
+            RuleMatch::Keyword => Matcher::Keyword(rule.pattern.to_lowercase()),
+            RuleMatch::Regex => Matcher::Regex(compile_regex(&rule.pattern)?),
+        };
+        Ok(Self { rule, matcher })
+    }
+
+    /// The subscription is the one of the user the item came from, needed for category scopes
+    pub fn matches(&self, target: &RuleTarget, subscription: &UserSubscription) -> bool {
+        let in_scope = match self.rule.scope {
+            RuleScope::All => true,
+            RuleScope::Category => {
+                self.rule.scope_value.as_deref() == Some(subscription.category.as_str())
+            }
+            RuleScope::Subscription => {
+                self.rule.scope_value.as_deref() == Some(&target.subscription_id.to_string())
+            }
+        };
+        if !in_scope {
+            return false;
+        }
+        let text = match self.rule.field {
+            RuleField::Title => Some(target.title),
+            RuleField::Author => target.author,
+            RuleField::Description => target.description,
+            RuleField::Link => Some(target.link),
+        };
+        match (text, &self.matcher) {
+            (None, _) => false,
+            (Some(text), Matcher::Keyword(keyword)) => text.to_lowercase().contains(keyword),
+            (Some(text), Matcher::Regex(regex)) => regex.is_match(text),
+        }
+    }
+
+    async fn apply(
+        &self,
+        user_item: &UserItem,
+        now: i64,
+        transaction: &mut Transaction<'_, Sqlite>,
+    ) -> Result<()> {
+        match self.rule.action {
+            RuleAction::Read => user_item.mark_read(now, &mut *transaction).await,
+            RuleAction::Star => user_item.star(now, &mut *transaction).await,
+            RuleAction::Hide => user_item.hide(now, &mut *transaction).await,
+            RuleAction::Tag => match self.rule.action_value.as_deref() {
+                Some(tag) => user_item.add_tag(tag, now, &mut *transaction).await,
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// Also used to check the pattern of a rule before saving it
+pub fn compile_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+/// Rules that no longer compile are skipped instead of stopping the others
+fn compile_rules(rules: Vec<FilterRule>) -> Vec<CompiledRule> {
+    rules
+        .into_iter()
+        .filter_map(|rule| match CompiledRule::new(rule) {
+            Ok(compiled) => Some(compiled),
+            Err(e) => {
+                warn!("Skipping filter rule: {:?}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Runs the rules of every user over the items the polling loop just inserted
+#[instrument(skip(new_items, pool))]
+pub async fn apply_to_new_items(new_items: &[(i64, &ItemInsert)], pool: &SqlitePool) -> Result<()> {
+    if new_items.is_empty() {
+        return Ok(());
+    }
+    let mut rules_by_user: BTreeMap<i64, Vec<FilterRule>> = BTreeMap::new();
+    for rule in FilterRule::fetch_everyone(pool).await? {
+        rules_by_user.entry(rule.user_id.0).or_default().push(rule);
+    }
+    let now = Utc::now().timestamp();
+    for (user_id, rules) in rules_by_user {
+        let user_id = UserId(user_id);
+        let rules = compile_rules(rules);
+        let subscriptions = subscriptions_by_id(&user_id, pool).await?;
+        let mut transaction = pool.begin().await?;
+        for (item_id, item) in new_items {
+            let subscription = match subscriptions.get(&item.subscription_id) {
+                Some(subscription) => subscription,
+                None => continue,
+            };
+            let target = RuleTarget::from(*item);
+            let user_item = UserItem::new(&user_id, *item_id);
+            for rule in rules
+                .iter()
+                .filter(|rule| rule.matches(&target, subscription))
+            {
+                rule.apply(&user_item, now, &mut transaction).await?;
+            }
+        }
+        transaction.commit().await?;
+    }
+    Ok(())
+}
+
+/// Runs the rules of the user over the items already stored, returns how many items matched.
+/// The matches are written once every page is read, a hidden item would otherwise shift the
+/// pages that come after it.
+#[instrument(skip(pool))]
+pub async fn apply_to_existing_items(user_id: &UserId, pool: &SqlitePool) -> Result<usize> {
+    let rules = compile_rules(FilterRule::fetch_all(user_id, pool).await?);
+    if rules.is_empty() {
+        return Ok(0);
+    }
+    let subscriptions = subscriptions_by_id(user_id, pool).await?;
+    // Keyed by item, items polled in the meantime push the pages down and show up twice
+    let mut matches: BTreeMap<i64, Vec<&CompiledRule>> = BTreeMap::new();
+    let mut offset = 0;
+    loop {
+        let items = Item::fetch_page(
+            user_id,
+            &FilterItems::All,
+            &ShowUnreads::ShowEverything,
+            EXISTING_ITEMS_PAGE,
+            offset,
+            pool,
+        )
+        .await?;
+        for item in items.iter() {
+            let subscription = match subscriptions.get(&item.subscription_id) {
+                Some(subscription) => subscription,
+                None => continue,
+            };
+            let target = RuleTarget::from(item);
+            let matching: Vec<_> = rules
+                .iter()
+                .filter(|rule| rule.matches(&target, subscription))
+                .collect();
+            if !matching.is_empty() {
+                matches.insert(item.id, matching);
+            }
+        }
+        if (items.len() as i64) < EXISTING_ITEMS_PAGE {
+            break;
+        }
+        offset += EXISTING_ITEMS_PAGE;
+    }
+    let now = Utc::now().timestamp();
+    let mut transaction = pool.begin().await?;
+    for (item_id, matching) in matches.iter() {
+        let user_item = UserItem::new(user_id, *item_id);
+        for rule in matching {
+            rule.apply(&user_item, now, &mut transaction).await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(matches.len())
+}
+
+async fn subscriptions_by_id(
+    user_id: &UserId,
+    pool: &SqlitePool,
+) -> Result<HashMap<i64, UserSubscription>> {
+    Ok(UserSubscription::fetch_all(user_id, pool)
+        .await?
+        .into_iter()
+        .map(|subscription| (subscription.id, subscription))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(field: RuleField, match_kind: RuleMatch, pattern: &str) -> FilterRule {
+        FilterRule {
+            id: 1,
+            user_id: UserId(1),
+            field,
+            match_kind,
+            pattern: pattern.to_string(),
+            scope: RuleScope::All,
+            scope_value: None,
+            action: RuleAction::Read,
+            action_value: None,
+            created_on: 0,
+        }
+    }
+
+    const TARGET: RuleTarget<'static> = RuleTarget {
+        subscription_id: 2,
+        title: "Weekly Sponsored Post",
+        author: None,
+        description: Some("Buy things"),
+        link: "https://example.com/posts/42",
+    };
+
+    #[test]
+    fn test_matching() {
+        let comics = UserSubscription::for_tests(2, "Comics");
+        let keyword =
+            CompiledRule::new(rule(RuleField::Title, RuleMatch::Keyword, "sponsored")).unwrap();
+        assert!(keyword.matches(&TARGET, &comics));
+        let regex =
+            CompiledRule::new(rule(RuleField::Link, RuleMatch::Regex, r"/posts/\d+$")).unwrap();
+        assert!(regex.matches(&TARGET, &comics));
+        let author = CompiledRule::new(rule(RuleField::Author, RuleMatch::Keyword, "")).unwrap();
+        assert!(!author.matches(&TARGET, &comics));
+        assert!(CompiledRule::new(rule(RuleField::Title, RuleMatch::Regex, "(")).is_err());
+    }
+
+    #[test]
+    fn test_scopes() {
+        let comics = UserSubscription::for_tests(2, "Comics");
+        let mut in_category = rule(RuleField::Description, RuleMatch::Keyword, "buy");
+        in_category.scope = RuleScope::Category;
+        in_category.scope_value = Some("Comics".to_string());
+        assert!(CompiledRule::new(in_category.clone())
+            .unwrap()
+            .matches(&TARGET, &comics));
+        in_category.scope_value = Some("Blogs".to_string());
+        assert!(!CompiledRule::new(in_category)
+            .unwrap()
+            .matches(&TARGET, &comics));
+
+        let mut in_subscription = rule(RuleField::Description, RuleMatch::Keyword, "buy");
+        in_subscription.scope = RuleScope::Subscription;
+        in_subscription.scope_value = Some("3".to_string());
+        assert!(!CompiledRule::new(in_subscription)
+            .unwrap()
+            .matches(&TARGET, &comics));
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use ammonia::Builder;
+use lru_time_cache::LruCache;
+use sha3::{Digest, Sha3_256};
+use url::Url;
+
+use crate::{dto::SanitizerProfile, image_proxy::ImageProxy, settings::SanitizerSettings};
+
+/// The same items get rendered again and again, their sanitized html is kept around
+const CACHE_CAPACITY: usize = 2_000;
+const MEDIA_TAGS: &[&str] = &["audio", "source", "track", "video"];
+
+/// Cleans the html of the feeds before it gets in our pages. Every profile drops scripts,
+/// styles, classes and inline styles, so feeds can not pick up or break our css, and loads the
+/// images through the image proxy. The profiles then allow more, see `SanitizerProfile`, with the
+/// media also coming through the proxy.
+#[derive(Clone)]
+pub struct Sanitizer {
+    images: ImageProxy,
+    embed_hosts: Arc<Vec<String>>,
+    /// Keyed by the digest of the html, a short hash could hand out the html of another item
+    cache: Arc<Mutex<LruCache<(SanitizerProfile, String), String>>>,
+}
+
+impl fmt::Debug for Sanitizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sanitizer")
+            .field("embed_hosts", &self.embed_hosts)
+            .finish()
+    }
+}
+
+impl Sanitizer {
+    pub fn new(settings: &SanitizerSettings, images: ImageProxy) -> Self {
+        Self {
+            images,
+            embed_hosts: Arc::new(settings.embed_hosts.clone()),
+            cache: Arc::new(Mutex::new(LruCache::with_capacity(CACHE_CAPACITY))),
+        }
+    }
+
+    fn cache(&self) -> MutexGuard<'_, LruCache<(SanitizerProfile, String), String>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn clean(&self, html: &str, profile: SanitizerProfile) -> String {
+        let key = (profile, hex::encode(Sha3_256::digest(html.as_bytes())));
+        if let Some(cleaned) = self.cache().get(&key) {
+            return cleaned.clone();
+        }
+        let cleaned = self.builder(profile).clean(html).to_string();
+        self.cache().insert(key, cleaned.clone());
+        cleaned
+    }
+
+    fn builder(&self, profile: SanitizerProfile) -> Builder<'static> {
+        let mut builder = Builder::default();
+        builder
+            .add_tag_attributes("img", &["srcset"])
+            .set_tag_attribute_value("img", "loading", "lazy");
+        if profile != SanitizerProfile::Strict {
+            builder
+                .add_tags(MEDIA_TAGS)
+                .add_tag_attributes("video", &["src", "poster", "width", "height", "loop"])
+                .add_tag_attributes("audio", &["src", "loop"])
+                .add_tag_attributes("source", &["src", "type"])
+                .add_tag_attributes("track", &["src", "kind", "srclang", "label"])
+                .set_tag_attribute_value("video", "controls", "controls")
+                .set_tag_attribute_value("video", "preload", "none")
+                .set_tag_attribute_value("audio", "controls", "controls")
+                .set_tag_attribute_value("audio", "preload", "none");
+        }
+        if profile == SanitizerProfile::Embeds {
+            builder
+                .add_tags(&["iframe"])
+                .add_tag_attributes(
+                    "iframe",
+                    &["src", "width", "height", "title", "allowfullscreen"],
+                )
+                .set_tag_attribute_value(
+                    "iframe",
+                    "sandbox",
+                    "allow-scripts allow-same-origin allow-presentation allow-popups",
+                )
+                .set_tag_attribute_value("iframe", "loading", "lazy");
+        }
+        let images = self.images.clone();
+        let embed_hosts = self.embed_hosts.clone();
+        builder.attribute_filter(
+            move |element, attribute, value| match (element, attribute) {
+                ("img", "src") | ("video", "poster") => images.proxied_url(value).map(Cow::Owned),
+                ("img", "srcset") => images.proxied_srcset(value).map(Cow::Owned),
+                ("video" | "audio" | "source" | "track", "src") => {
+                    images.proxied_media_url(value).map(Cow::Owned)
+                }
+                ("iframe", "src") => embeddable(&embed_hosts, value).then(|| Cow::Borrowed(value)),
+                _ => Some(Cow::Borrowed(value)),
+            },
+        );
+        builder
+    }
+}
+
+/// Only https pages of the embed hosts can be framed
+fn embeddable(embed_hosts: &[String], src: &str) -> bool {
+    match Url::parse(src) {
+        Ok(url) => {
+            url.scheme() == "https"
+                && matches!(url.host_str(), Some(host) if embed_hosts.iter().any(|x| x.eq_ignore_ascii_case(host)))
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::ImageProxySettings;
+
+    fn sanitizer() -> Sanitizer {
+        let images = ImageProxy::new(&ImageProxySettings {
+            secret: Some("secret".to_string()),
+            cache_dir: std::env::temp_dir()
+                .join("rss-sanitizer-test")
+                .to_string_lossy()
+                .to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        Sanitizer::new(&SanitizerSettings::default(), images)
+    }
+
+    #[test]
+    fn test_strict() {
+        let sanitizer = sanitizer();
+        let cleaned = sanitizer.clean(
+            r#"<style>.tag { color: red; }</style><p class="tag" style="color: red">Hi</p><iframe src="https://www.youtube.com/embed/1"></iframe><video src="https://example.com/a.mp4">Video</video>"#,
+            SanitizerProfile::Strict,
+        );
+        assert_eq!(cleaned, "<p>Hi</p>Video");
+        let image = sanitizer.clean(
+            r#"<img src="https://example.com/a.png">"#,
+            SanitizerProfile::Strict,
+        );
+        assert!(image.starts_with(
+            r#"<img src="/proxy/image?url=https%3A%2F%2Fexample.com%2Fa.png&amp;sig="#
+        ));
+    }
+
+    #[test]
+    fn test_media() {
+        let cleaned = sanitizer().clean(
+            r#"<video src="https://example.com/a.mp4" autoplay=""></video><iframe src="https://www.youtube.com/embed/1"></iframe>"#,
+            SanitizerProfile::Media,
+        );
+        assert!(cleaned.starts_with(
+            r#"<video src="/proxy/media?url=https%3A%2F%2Fexample.com%2Fa.mp4&amp;sig="#
+        ));
+        assert!(cleaned.contains(r#"controls="controls""#));
+        assert!(!cleaned.contains("autoplay"));
+        assert!(!cleaned.contains("iframe"));
+        let relative = sanitizer().clean(
+            r#"<audio><source src="/a.mp3" type="audio/mpeg"></audio>"#,
+            SanitizerProfile::Media,
+        );
+        assert!(!relative.contains("a.mp3"));
+    }
+
+    #[test]
+    fn test_embeds() {
+        let sanitizer = sanitizer();
+        let cleaned = sanitizer.clean(
+            r#"<iframe src="https://www.youtube.com/embed/1"></iframe><iframe src="https://evil.example.com/"></iframe>"#,
+            SanitizerProfile::Embeds,
+        );
+        assert!(cleaned.contains(r#"src="https://www.youtube.com/embed/1""#));
+        assert!(!cleaned.contains("evil"));
+        assert_eq!(
+            sanitizer.clean(
+                r#"<iframe src="https://www.youtube.com/embed/1"></iframe><iframe src="https://evil.example.com/"></iframe>"#,
+                SanitizerProfile::Embeds,
+            ),
+            cleaned
+        );
+    }
+}
@@ -1,22 +1,57 @@
 use self::{
-    items::{get_full_item, get_full_item_part},
-    subscriptions::{new_subscription, page_all_subscriptions, page_rss_subscription_form},
+    account::{
+        create_api_token, page_account, page_api_tokens, page_sessions, revoke_api_token,
+        revoke_session, update_archive_starred, update_fever_password, update_password,
+        update_timezone,
+    },
+    admin::{
+        admin_delete_user, admin_disable_user, admin_enable_user, admin_new_invite,
+        admin_new_user, page_admin_users,
+    },
+    items::{
+        add_item_highlight, add_item_tag, archive_item, delete_item_highlight, get_full_item,
+        get_full_item_part, get_item_archive, load_full_content, remove_item_tag, save_item_note,
+        toggle_item_read, toggle_item_star,
+    },
+    oidc::{oidc_callback, oidc_login},
+    subscriptions::{
+        new_subscription, page_all_subscriptions, page_rss_subscription_form,
+        page_subscription_settings, refresh_all_subscriptions, refresh_subscription,
+        update_subscription_retention, update_subscription_settings,
+    },
+};
+use crate::{
+    clients::Clients, login_throttle::LoginThrottle, oidc::Oidc, poll::Refreshes, session::Sessions,
 };
-use crate::{clients::Clients, session::SessionMap};
 use actix_web::{
-    body::Body, error, http::StatusCode, middleware, rt::spawn, web, App, HttpResponse, HttpServer,
+    body::Body,
+    error,
+    http::StatusCode,
+    middleware,
+    rt::{spawn, time},
+    web, App, HttpResponse, HttpServer,
 };
 use color_eyre::Report;
-use futures::lock::Mutex;
-use login::{login_post, page_login};
-use lru_time_cache::LruCache;
-use std::{fmt::Display, sync::Arc, time::Duration};
-use tracing::warn;
+use from_requests::csrf::CsrfToken;
+use login::{login_post, logout_post, page_login, page_signup, signup_post};
+use std::{fmt::Display, time::Duration};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
+mod account;
 mod actions;
+mod admin;
+mod api;
+mod fever;
+mod greader;
+mod icons;
+mod images;
 mod items;
 mod login;
+mod notes;
+mod oidc;
+mod output_feeds;
+mod rules;
 mod subscriptions;
 pub mod templates;
 
@@ -34,31 +69,123 @@ pub enum MyError {
     Missing(String),
     #[error("User Not Logged in")]
     NotLoggedIn(Report),
+    #[error("Forbidden")]
+    Forbidden(Report),
+    #[error("Unauthorized")]
+    Unauthorized(Report),
+    #[error("Invalid username or password")]
+    LoginFailed,
+    #[error("Too many login attempts, try again in {} seconds", .0)]
+    TooManyAttempts(u64),
 }
 
 pub fn spawn_server(clients: Clients) -> tokio::task::JoinHandle<()> {
     spawn(async move {
-        let sessions: SessionMap =
-            Arc::new(Mutex::new(LruCache::with_expiry_duration_and_capacity(
-                Duration::from_secs(clients.settings.time_of_cookies_s),
-                clients.settings.max_sessions as usize,
-            )));
+        let sessions = Sessions::new(&clients.settings);
+        let login_throttle = LoginThrottle::default();
+        let refreshes = Refreshes::default();
+        let oidc = match &clients.settings.oidc {
+            Some(settings) => match Oidc::discover(settings).await {
+                Ok(oidc) => Some(oidc),
+                Err(e) => {
+                    error!("OpenID Connect login is unavailable: {:?}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        spawn(clean_sessions(sessions.clone(), clients.clone()));
         HttpServer::new(move || {
             App::new()
                 .app_data(web::Data::new(clients.clone()))
                 .app_data(web::Data::new(sessions.clone()))
+                .app_data(web::Data::new(login_throttle.clone()))
+                .app_data(web::Data::new(refreshes.clone()))
+                .app_data(web::Data::new(oidc.clone()))
                 .wrap(middleware::Compress::default())
                 .service(page_login)
                 .service(page_all_subscriptions)
                 .service(page_rss_subscription_form)
                 .service(login_post)
+                .service(logout_post)
+                .service(oidc_login)
+                .service(oidc_callback)
+                .service(page_signup)
+                .service(signup_post)
+                .service(page_account)
+                .service(update_timezone)
+                .service(update_password)
+                .service(page_sessions)
+                .service(revoke_session)
+                .service(page_api_tokens)
+                .service(create_api_token)
+                .service(revoke_api_token)
+                .service(update_fever_password)
+                .service(update_archive_starred)
+                .service(output_feeds::page_output_feeds)
+                .service(output_feeds::create_output_feed)
+                .service(output_feeds::delete_output_feed)
+                .service(output_feeds::output_feed)
+                .service(notes::page_notes)
+                .service(notes::export_notes)
+                .service(rules::page_rules)
+                .service(rules::create_rule)
+                .service(rules::delete_rule)
+                .service(rules::apply_rules)
+                .service(page_admin_users)
+                .service(admin_new_user)
+                .service(admin_disable_user)
+                .service(admin_enable_user)
+                .service(admin_delete_user)
+                .service(admin_new_invite)
                 .service(new_subscription)
+                .service(page_subscription_settings)
+                .service(update_subscription_settings)
+                .service(update_subscription_retention)
+                .service(refresh_all_subscriptions)
+                .service(refresh_subscription)
                 .service(get_full_item)
+                .service(get_item_archive)
                 .service(get_full_item_part)
+                .service(toggle_item_read)
+                .service(toggle_item_star)
+                .service(add_item_tag)
+                .service(remove_item_tag)
+                .service(save_item_note)
+                .service(load_full_content)
+                .service(archive_item)
+                .service(add_item_highlight)
+                .service(delete_item_highlight)
+                .service(api::list_subscriptions)
+                .service(api::create_subscription)
+                .service(api::update_subscription)
+                .service(api::delete_subscription)
+                .service(api::list_items)
+                .service(api::mark_items_read)
+                .service(api::mark_items_unread)
+                .service(api::get_item)
+                .service(api::openapi_json)
+                .service(fever::fever_api)
+                .service(greader::client_login)
+                .service(greader::token)
+                .service(greader::user_info)
+                .service(greader::subscription_list)
+                .service(greader::tag_list)
+                .service(greader::subscription_edit)
+                .service(greader::unread_count)
+                .service(greader::stream_item_ids)
+                .service(greader::stream_contents)
+                .service(greader::stream_items_contents)
+                .service(greader::edit_tag)
+                .service(greader::mark_all_as_read)
+                .service(images::proxy_image)
+                .service(images::proxy_media)
+                .service(icons::subscription_icon)
                 .service(actions::action_mark_all_read)
                 .service(actions::filter_all_subscriptions)
                 .service(actions::filter_by_category)
                 .service(actions::filter_by_category_title)
+                .service(actions::filter_by_tag)
                 .service(actions::expand_sidebar)
                 .service(actions::collapse_sidebar)
                 .service(actions::show_everything)
@@ -73,9 +200,22 @@ pub fn spawn_server(clients: Clients) -> tokio::task::JoinHandle<()> {
     })
 }
 
-fn wrap_body<A: Display>(wrapped: A) -> String {
+/// Sessions that have not been seen in `time_of_cookies_s` are removed every hour
+async fn clean_sessions(sessions: Sessions, clients: Clients) {
+    let mut interval = time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match sessions.remove_expired(&clients.pool).await {
+            Ok(count) => info!("Removed {} expired sessions", count),
+            Err(e) => warn!("Could not remove expired sessions: {:?}", e),
+        }
+    }
+}
+
+fn wrap_body<A: Display>(csrf_token: &CsrfToken, wrapped: A) -> String {
     templates::Home {
         body: &format!("{}", wrapped),
+        csrf_token: &csrf_token.0,
     }
     .to_string()
 }
@@ -90,6 +230,19 @@ impl error::ResponseError for MyError {
                     .append_header(("Location", "/login"))
                     .body("Not logged in")
             }
+            MyError::Forbidden(x) => {
+                warn!("Forbidden: {:?}", x);
+                HttpResponse::with_body(self.status_code(), Body::from_message("Forbidden"))
+            }
+            MyError::Unauthorized(x) => {
+                warn!("Unauthorized: {:?}", x);
+                HttpResponse::build(self.status_code())
+                    .append_header(("WWW-Authenticate", "Bearer"))
+                    .body("Unauthorized")
+            }
+            MyError::TooManyAttempts(retry_after_s) => HttpResponse::build(self.status_code())
+                .append_header(("Retry-After", retry_after_s.to_string()))
+                .body(format!("{}", self)),
             MyError::CannotFind(x) => {
                 let uuid = Uuid::new_v4();
                 warn!("Cannot Find ({}): {:?}", uuid, x);
@@ -119,11 +272,19 @@ impl error::ResponseError for MyError {
             | MyError::NotLoggedIn(_)
             | MyError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
             MyError::Missing(_) | MyError::CannotFind(_) => StatusCode::NOT_FOUND,
+            MyError::Forbidden(_) => StatusCode::FORBIDDEN,
+            MyError::LoginFailed | MyError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            MyError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
         }
     }
 }
 
 pub(crate) mod from_requests {
+    pub mod admin_id;
+    pub mod api_user_id;
+    pub mod client_ip;
+    pub mod csrf;
+    pub mod session;
     pub mod user_id;
     pub mod user_preferences;
 }
@@ -0,0 +1,322 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use chrono_tz::Tz;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    clients::Clients,
+    dto::{self, User},
+    login_throttle::LoginThrottle,
+    session::{Sessions, SSID},
+};
+
+use super::{
+    from_requests::{
+        client_ip::ClientIp,
+        csrf::{CsrfChecked, CsrfToken},
+        user_id::UserIdPart,
+    },
+    login::{authenticate, MIN_PASSWORD_LENGTH},
+    templates, wrap_body, MyError,
+};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TimezoneForm {
+    timezone: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ArchiveStarredForm {
+    archive_starred: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiTokenForm {
+    name: String,
+    scope: dto::ApiScope,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct FeverPasswordForm {
+    api_password: String,
+}
+
+impl std::fmt::Debug for FeverPasswordForm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FeverPasswordForm")
+            .field("api_password", &"redacted")
+            .finish()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PasswordForm {
+    old_password: String,
+    new_password: String,
+    confirm_password: String,
+}
+
+impl std::fmt::Debug for PasswordForm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PasswordForm")
+            .field("old_password", &"redacted")
+            .field("new_password", &"redacted")
+            .field("confirm_password", &"redacted")
+            .finish()
+    }
+}
+
+#[get("/account")]
+#[instrument(skip(clients))]
+pub async fn page_account(
+    clients: web::Data<Clients>,
+    UserIdPart(user_id): UserIdPart,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, MyError> {
+    let timezone = user_id.timezone(&clients.pool).await?;
+    let is_admin = user_id.is_admin(&clients.pool).await?;
+    let archive_starred = user_id.archive_starred(&clients.pool).await?;
+    let body = wrap_body(&csrf, templates::Account {
+        timezone: timezone.name(),
+        is_admin,
+        archive_starred,
+        oidc: clients.settings.oidc.is_some(),
+    });
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[post("/account/timezone")]
+#[instrument(skip(clients))]
+pub async fn update_timezone(
+    clients: web::Data<Clients>,
+    form: web::Form<TimezoneForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let timezone: Tz = form
+        .timezone
+        .trim()
+        .parse()
+        .map_err(|e| MyError::BadParam("timezone".into(), format!("{}", e)))?;
+    user_id.set_timezone(&timezone, &clients.pool).await?;
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/account"))
+        .finish())
+}
+
+/// Stars get archived in the background after each poll
+#[post("/account/archive_starred")]
+#[instrument(skip(clients))]
+pub async fn update_archive_starred(
+    clients: web::Data<Clients>,
+    form: web::Form<ArchiveStarredForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    user_id
+        .set_archive_starred(form.archive_starred.is_some(), &clients.pool)
+        .await?;
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/account"))
+        .finish())
+}
+
+#[post("/account/password")]
+#[instrument(skip(clients, sessions, login_throttle, request))]
+pub async fn update_password(
+    clients: web::Data<Clients>,
+    sessions: web::Data<Sessions>,
+    login_throttle: web::Data<LoginThrottle>,
+    form: web::Form<PasswordForm>,
+    request: HttpRequest,
+    UserIdPart(user_id): UserIdPart,
+    ClientIp(ip): ClientIp,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    // The other sessions are logged out after the change, which needs our own session
+    let ssid = request
+        .cookie(SSID)
+        .ok_or_else(|| MyError::NotLoggedIn(eyre!("Need to have a SSID cookie")))?;
+    let user = User::fetch_by_id(&clients.pool, &user_id).await?;
+    // Throttled like the logins, a stolen session must not be able to guess the password
+    if let Err(e) = authenticate(
+        &clients,
+        &login_throttle,
+        user.username(),
+        &form.old_password,
+        &ip,
+    )
+    .await
+    {
+        return Err(match e {
+            MyError::LoginFailed => {
+                MyError::BadParam("old_password".into(), "Old password does not match".into())
+            }
+            e => e,
+        });
+    }
+    if form.new_password != form.confirm_password {
+        return Err(MyError::BadParam(
+            "confirm_password".into(),
+            "Passwords do not match".into(),
+        ));
+    }
+    if form.new_password.len() < MIN_PASSWORD_LENGTH {
+        return Err(MyError::BadParam(
+            "new_password".into(),
+            format!("Needs at least {} characters", MIN_PASSWORD_LENGTH),
+        ));
+    }
+    User::set_password(&user_id, &form.new_password, &clients.pool).await?;
+    sessions
+        .remove_user_sessions(&user_id, Some(ssid.value()), &clients.pool)
+        .await?;
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/account"))
+        .finish())
+}
+
+/// A separate password for Fever clients, they send it unsalted as md5
+#[post("/account/fever")]
+#[instrument(skip(clients))]
+pub async fn update_fever_password(
+    clients: web::Data<Clients>,
+    form: web::Form<FeverPasswordForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    if form.api_password.len() < MIN_PASSWORD_LENGTH {
+        return Err(MyError::BadParam(
+            "api_password".into(),
+            format!("Needs at least {} characters", MIN_PASSWORD_LENGTH),
+        ));
+    }
+    let user = User::fetch_by_id(&clients.pool, &user_id).await?;
+    user.set_fever_api_password(&form.api_password, &clients.pool)
+        .await?;
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/account"))
+        .finish())
+}
+
+#[get("/account/sessions")]
+#[instrument(skip(clients, request))]
+pub async fn page_sessions(
+    clients: web::Data<Clients>,
+    request: HttpRequest,
+    UserIdPart(user_id): UserIdPart,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, MyError> {
+    let sessions = dto::Session::fetch_all(&user_id, &clients.pool).await?;
+    let timezone = user_id.timezone(&clients.pool).await?;
+    let current = request
+        .cookie(SSID)
+        .map(|ssid| Sessions::hash_token(ssid.value()))
+        .unwrap_or_default();
+    let body = wrap_body(&csrf, templates::AccountSessions {
+        sessions: sessions.iter().collect(),
+        current: &current,
+        timezone: &timezone,
+    });
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[post("/account/sessions/{id}/revoke")]
+#[instrument(skip(clients, sessions))]
+pub async fn revoke_session(
+    clients: web::Data<Clients>,
+    sessions: web::Data<Sessions>,
+    id: web::Path<i64>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    if !sessions.revoke(&user_id, *id, &clients.pool).await? {
+        return Err(MyError::Missing("Session".to_string()));
+    }
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/account/sessions"))
+        .finish())
+}
+
+#[get("/account/tokens")]
+#[instrument(skip(clients))]
+pub async fn page_api_tokens(
+    clients: web::Data<Clients>,
+    UserIdPart(user_id): UserIdPart,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, MyError> {
+    api_tokens_page(&clients, &user_id, &csrf, None).await
+}
+
+#[post("/account/tokens")]
+#[instrument(skip(clients))]
+pub async fn create_api_token(
+    clients: web::Data<Clients>,
+    form: web::Form<ApiTokenForm>,
+    UserIdPart(user_id): UserIdPart,
+    csrf: CsrfToken,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(MyError::BadParam("name".into(), "Name is required".into()));
+    }
+    let token = new_api_token(&clients, &user_id, name, form.scope).await?;
+    api_tokens_page(&clients, &user_id, &csrf, Some(&token)).await
+}
+
+#[post("/account/tokens/{id}/revoke")]
+#[instrument(skip(clients))]
+pub async fn revoke_api_token(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    if !dto::ApiToken::delete(&user_id, *id, &clients.pool).await? {
+        return Err(MyError::Missing("Token".to_string()));
+    }
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/account/tokens"))
+        .finish())
+}
+
+/// Returns the token, only its hash is kept
+pub(super) async fn new_api_token(
+    clients: &Clients,
+    user_id: &dto::UserId,
+    name: &str,
+    scope: dto::ApiScope,
+) -> Result<String, MyError> {
+    let token = format!("rss_{}", Uuid::new_v4().to_simple());
+    dto::ApiToken::insert(
+        user_id,
+        name,
+        &Sessions::hash_token(&token),
+        scope,
+        Utc::now().timestamp(),
+        &clients.pool,
+    )
+    .await?;
+    Ok(token)
+}
+
+/// The token itself is only shown once, right after it was created
+async fn api_tokens_page(
+    clients: &Clients,
+    user_id: &dto::UserId,
+    csrf: &CsrfToken,
+    new_token: Option<&str>,
+) -> Result<HttpResponse, MyError> {
+    let tokens = dto::ApiToken::fetch_all(user_id, &clients.pool).await?;
+    let timezone = user_id.timezone(&clients.pool).await?;
+    let body = wrap_body(csrf, templates::AccountApiTokens {
+        tokens: tokens.iter().collect(),
+        new_token,
+        timezone: &timezone,
+    });
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
@@ -1,29 +1,36 @@
 use std::time::SystemTime;
 
-use actix_web::{cookie::Cookie, get, web, HttpResponse};
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    post, web, HttpResponse,
+};
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
 use tracing::instrument;
 
 use crate::clients::Clients;
 
 use super::{
     from_requests::{
+        csrf::CsrfChecked,
         user_id::UserIdPart,
         user_preferences::{FilterItems, ShowUnreads, UserPreferences, USER_PREFERENCE},
     },
     MyError,
 };
 
-#[get("/actions/mark_all_read/{date}")]
+#[post("/actions/mark_all_read/{date}")]
 #[instrument(skip(clients))]
 pub async fn action_mark_all_read(
     clients: web::Data<Clients>,
     date_secs: web::Path<u64>,
     UserIdPart(user_id): UserIdPart,
     user_preference: UserPreferences,
+    _csrf: CsrfChecked,
 ) -> Result<HttpResponse, MyError> {
     let date_secs = *date_secs as i64;
     let (filter_session_id, filter_session_title) = user_preference.filter_items.as_items();
+    let filter_tag = user_preference.filter_items.tag();
     let now = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .map_err(|error| MyError::Internal(eyre!("Could not get now time: {:?}", error)))?
@@ -34,7 +41,10 @@ pub async fn action_mark_all_read(
         date_secs,
         now,
         filter_session_id,
-        filter_session_title
+        filter_session_title,
+        Option::<&str>::None,
+        filter_tag,
+        false
     )
     .execute(&clients.pool)
     .await
@@ -45,10 +55,11 @@ pub async fn action_mark_all_read(
         .append_header(("Location", "/"))
         .finish())
 }
-#[get("/actions/filter_all_subscriptions")]
+#[post("/actions/filter_all_subscriptions")]
 #[instrument(skip())]
 pub async fn filter_all_subscriptions(
     user_preference: UserPreferences,
+    _csrf: CsrfChecked,
 ) -> Result<HttpResponse, MyError> {
     let user_preference = serde_json::to_string(&UserPreferences {
         filter_items: FilterItems::All,
@@ -61,16 +72,18 @@ pub async fn filter_all_subscriptions(
             Cookie::build(USER_PREFERENCE, user_preference)
                 .path("/")
                 .http_only(true)
+                .same_site(SameSite::Lax)
                 .finish(),
         )
         .append_header(("Location", "/"))
         .finish())
 }
-#[get("/actions/filter_by_category/{category_id}")]
+#[post("/actions/filter_by_category/{category_id}")]
 #[instrument(skip())]
 pub async fn filter_by_category(
     category_id: web::Path<i64>,
     user_preference: UserPreferences,
+    _csrf: CsrfChecked,
 ) -> Result<HttpResponse, MyError> {
     let user_preference = serde_json::to_string(&UserPreferences {
         filter_items: FilterItems::Id(*category_id),
@@ -83,17 +96,19 @@ pub async fn filter_by_category(
             Cookie::build(USER_PREFERENCE, user_preference)
                 .path("/")
                 .http_only(true)
+                .same_site(SameSite::Lax)
                 .finish(),
         )
         .append_header(("Location", "/"))
         .finish())
 }
 
-#[get("/actions/filter_by_category/{category_title}")]
+#[post("/actions/filter_by_category/{category_title}")]
 #[instrument(skip())]
 pub async fn filter_by_category_title(
     category_title: web::Path<String>,
     user_preference: UserPreferences,
+    _csrf: CsrfChecked,
 ) -> Result<HttpResponse, MyError> {
     let user_preference = serde_json::to_string(&UserPreferences {
         filter_items: FilterItems::Title(category_title.to_owned()),
@@ -106,15 +121,50 @@ pub async fn filter_by_category_title(
             Cookie::build(USER_PREFERENCE, user_preference)
                 .path("/")
                 .http_only(true)
+                .same_site(SameSite::Lax)
+                .finish(),
+        )
+        .append_header(("Location", "/"))
+        .finish())
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TagForm {
+    tag: String,
+}
+
+/// The tag comes in the form, tags are free text which does not sit well in a path
+#[post("/actions/filter_by_tag")]
+#[instrument(skip())]
+pub async fn filter_by_tag(
+    form: web::Form<TagForm>,
+    user_preference: UserPreferences,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let user_preference = serde_json::to_string(&UserPreferences {
+        filter_items: FilterItems::Tag(form.into_inner().tag),
+        ..user_preference
+    })
+    .ok()
+    .unwrap_or_default();
+    Ok(HttpResponse::Found()
+        .cookie(
+            Cookie::build(USER_PREFERENCE, user_preference)
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax)
                 .finish(),
         )
         .append_header(("Location", "/"))
         .finish())
 }
 
-#[get("/actions/collapse_sidebar")]
+#[post("/actions/collapse_sidebar")]
 #[instrument(skip())]
-pub async fn collapse_sidebar(user_preference: UserPreferences) -> Result<HttpResponse, MyError> {
+pub async fn collapse_sidebar(
+    user_preference: UserPreferences,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
     let user_preference = serde_json::to_string(&UserPreferences {
         sidebar_collapsed: true,
         ..user_preference
@@ -126,14 +176,18 @@ pub async fn collapse_sidebar(user_preference: UserPreferences) -> Result<HttpRe
             Cookie::build(USER_PREFERENCE, user_preference)
                 .path("/")
                 .http_only(true)
+                .same_site(SameSite::Lax)
                 .finish(),
         )
         .append_header(("Location", "/"))
         .finish())
 }
-#[get("/actions/expand_sidebar")]
+#[post("/actions/expand_sidebar")]
 #[instrument(skip())]
-pub async fn expand_sidebar(user_preference: UserPreferences) -> Result<HttpResponse, MyError> {
+pub async fn expand_sidebar(
+    user_preference: UserPreferences,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
     let user_preference = serde_json::to_string(&UserPreferences {
         sidebar_collapsed: false,
         ..user_preference
@@ -145,14 +199,18 @@ pub async fn expand_sidebar(user_preference: UserPreferences) -> Result<HttpResp
             Cookie::build(USER_PREFERENCE, user_preference)
                 .path("/")
                 .http_only(true)
+                .same_site(SameSite::Lax)
                 .finish(),
         )
         .append_header(("Location", "/"))
         .finish())
 }
-#[get("/actions/show_unreads")]
+#[post("/actions/show_unreads")]
 #[instrument(skip())]
-pub async fn show_unreads(user_preference: UserPreferences) -> Result<HttpResponse, MyError> {
+pub async fn show_unreads(
+    user_preference: UserPreferences,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
     let user_preference = serde_json::to_string(&UserPreferences {
         show_unreads: ShowUnreads::ShowUnreads,
         ..user_preference
@@ -164,14 +222,18 @@ pub async fn show_unreads(user_preference: UserPreferences) -> Result<HttpRespon
             Cookie::build(USER_PREFERENCE, user_preference)
                 .path("/")
                 .http_only(true)
+                .same_site(SameSite::Lax)
                 .finish(),
         )
         .append_header(("Location", "/"))
         .finish())
 }
-#[get("/actions/show_everything")]
+#[post("/actions/show_everything")]
 #[instrument(skip())]
-pub async fn show_everything(user_preference: UserPreferences) -> Result<HttpResponse, MyError> {
+pub async fn show_everything(
+    user_preference: UserPreferences,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
     let user_preference = serde_json::to_string(&UserPreferences {
         show_unreads: ShowUnreads::ShowEverything,
         ..user_preference
@@ -183,6 +245,7 @@ pub async fn show_everything(user_preference: UserPreferences) -> Result<HttpRes
             Cookie::build(USER_PREFERENCE, user_preference)
                 .path("/")
                 .http_only(true)
+                .same_site(SameSite::Lax)
                 .finish(),
         )
         .append_header(("Location", "/"))
@@ -0,0 +1,162 @@
+use actix_web::{get, post, web, HttpResponse};
+use chrono::Utc;
+use color_eyre::{eyre::eyre, Report};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    clients::Clients,
+    dto::{Invite, User, UserId},
+    session::Sessions,
+};
+
+use super::{
+    from_requests::{
+        admin_id::AdminIdPart,
+        csrf::{CsrfChecked, CsrfToken},
+    },
+    login::MIN_PASSWORD_LENGTH,
+    templates, wrap_body, MyError,
+};
+
+#[derive(Clone, Deserialize)]
+pub struct NewUserForm {
+    username: String,
+    password: String,
+    is_admin: Option<String>,
+}
+
+impl std::fmt::Debug for NewUserForm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NewUserForm")
+            .field("username", &self.username)
+            .field("password", &"redacted")
+            .field("is_admin", &self.is_admin)
+            .finish()
+    }
+}
+
+#[get("/admin/users")]
+#[instrument(skip(clients))]
+pub async fn page_admin_users(
+    clients: web::Data<Clients>,
+    AdminIdPart(admin_id): AdminIdPart,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, MyError> {
+    let users = User::fetch_all(&clients.pool).await?;
+    let invites = Invite::fetch_all_unused(&clients.pool).await?;
+    let body = wrap_body(&csrf, templates::AdminUsers {
+        users: users.iter().collect(),
+        invites: invites.iter().collect(),
+        current: &admin_id,
+    });
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[post("/admin/users")]
+#[instrument(skip(clients))]
+pub async fn admin_new_user(
+    clients: web::Data<Clients>,
+    form: web::Form<NewUserForm>,
+    AdminIdPart(_admin_id): AdminIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let username = form.username.trim();
+    if username.is_empty() {
+        return Err(MyError::BadParam(
+            "username".into(),
+            "Username is required".into(),
+        ));
+    }
+    if form.password.len() < MIN_PASSWORD_LENGTH {
+        return Err(MyError::BadParam(
+            "password".into(),
+            format!("Needs at least {} characters", MIN_PASSWORD_LENGTH),
+        ));
+    }
+    if User::fetch(&clients.pool, username).await.is_ok() {
+        return Err(MyError::BadParam(
+            "username".into(),
+            "Username is taken".into(),
+        ));
+    }
+    User::insert(
+        username,
+        &form.password,
+        form.is_admin.is_some(),
+        &clients.pool,
+    )
+    .await?;
+    Ok(redirect_admin_users())
+}
+
+#[post("/admin/users/{id}/disable")]
+#[instrument(skip(clients, sessions))]
+pub async fn admin_disable_user(
+    clients: web::Data<Clients>,
+    sessions: web::Data<Sessions>,
+    id: web::Path<i64>,
+    AdminIdPart(admin_id): AdminIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let user_id = UserId(*id);
+    if user_id == admin_id {
+        return Err(MyError::Forbidden(eyre!("Admins cannot disable themselves")));
+    }
+    User::set_disabled(&user_id, true, &clients.pool).await?;
+    sessions
+        .remove_user_sessions(&user_id, None, &clients.pool)
+        .await?;
+    Ok(redirect_admin_users())
+}
+
+#[post("/admin/users/{id}/enable")]
+#[instrument(skip(clients))]
+pub async fn admin_enable_user(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    AdminIdPart(_admin_id): AdminIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    User::set_disabled(&UserId(*id), false, &clients.pool).await?;
+    Ok(redirect_admin_users())
+}
+
+#[post("/admin/users/{id}/delete")]
+#[instrument(skip(clients, sessions))]
+pub async fn admin_delete_user(
+    clients: web::Data<Clients>,
+    sessions: web::Data<Sessions>,
+    id: web::Path<i64>,
+    AdminIdPart(admin_id): AdminIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let user_id = UserId(*id);
+    if user_id == admin_id {
+        return Err(MyError::Forbidden(eyre!("Admins cannot delete themselves")));
+    }
+    let mut transaction = clients.pool.begin().await.map_err(Report::from)?;
+    User::delete(&user_id, &mut transaction).await?;
+    transaction.commit().await.map_err(Report::from)?;
+    sessions
+        .remove_user_sessions(&user_id, None, &clients.pool)
+        .await?;
+    Ok(redirect_admin_users())
+}
+
+#[post("/admin/invites")]
+#[instrument(skip(clients))]
+pub async fn admin_new_invite(
+    clients: web::Data<Clients>,
+    AdminIdPart(admin_id): AdminIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    Invite::insert(&admin_id, Utc::now().timestamp(), &clients.pool).await?;
+    Ok(redirect_admin_users())
+}
+
+fn redirect_admin_users() -> HttpResponse {
+    HttpResponse::Found()
+        .append_header(("Location", "/admin/users"))
+        .finish()
+}
@@ -0,0 +1,383 @@
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use chrono::Utc;
+use color_eyre::Report;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::{
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+    IntoParams, Modify, OpenApi, ToSchema,
+};
+
+use crate::{
+    clients::Clients,
+    dto::{self, ApiScope, Item, UserSubscription},
+    server::from_requests::user_preferences::{FilterItems, ShowUnreads},
+};
+
+use super::{from_requests::api_user_id::ApiUserIdPart, subscriptions::subscribe, MyError};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+/// Most items a single bulk read/unread call may touch
+const MAX_BULK_ITEMS: usize = 1000;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        list_subscriptions,
+        create_subscription,
+        update_subscription,
+        delete_subscription,
+        list_items,
+        get_item,
+        mark_items_read,
+        mark_items_unread,
+    ),
+    components(schemas(
+        UserSubscription,
+        Item,
+        NewSubscription,
+        SubscriptionUpdate,
+        ItemPage,
+        ItemIds,
+        Updated
+    )),
+    modifiers(&BearerToken)
+)]
+pub struct ApiDoc;
+
+/// Every route takes a personal api token, see `/account/tokens`
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "api_token",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, ToSchema)]
+pub struct NewSubscription {
+    url: String,
+    title: String,
+    category: String,
+}
+
+#[derive(Debug, Deserialize, Clone, ToSchema)]
+pub struct SubscriptionUpdate {
+    title: String,
+    category: String,
+}
+
+#[derive(Debug, Deserialize, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ItemsQuery {
+    /// Only items of this subscription
+    subscription_id: Option<i64>,
+    /// Only items of the subscription with this title
+    title: Option<String>,
+    /// Only items with this tag
+    tag: Option<String>,
+    /// Leave out the items already read
+    #[serde(default)]
+    unread_only: bool,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ItemPage {
+    items: Vec<Item>,
+    /// Offset of the next page, missing on the last page
+    next_offset: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Clone, ToSchema)]
+pub struct ItemIds {
+    ids: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct Updated {
+    updated: usize,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscriptions",
+    responses((status = 200, description = "Subscriptions of the user", body = [UserSubscription])),
+    security(("api_token" = []))
+)]
+#[get("/api/v1/subscriptions")]
+#[instrument(skip(clients))]
+pub async fn list_subscriptions(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Read)?;
+    let subscriptions = dto::UserSubscription::fetch_all(&user.user_id, &clients.pool).await?;
+    Ok(HttpResponse::Ok().json(subscriptions))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions",
+    request_body = NewSubscription,
+    responses(
+        (status = 201, description = "Subscribed", body = UserSubscription),
+        (status = 400, description = "The url is not a feed")
+    ),
+    security(("api_token" = []))
+)]
+#[post("/api/v1/subscriptions")]
+#[instrument(skip(clients))]
+pub async fn create_subscription(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    body: web::Json<NewSubscription>,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Write)?;
+    let subscription = subscribe(
+        &clients,
+        &user.user_id,
+        &body.category,
+        &body.title,
+        &body.url,
+    )
+    .await?
+    .ok_or_else(|| MyError::Missing("Subscription".to_string()))?;
+    Ok(HttpResponse::Created().json(subscription))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/subscriptions/{id}",
+    params(("id" = i64, Path, description = "Subscription id")),
+    request_body = SubscriptionUpdate,
+    responses(
+        (status = 200, description = "Updated", body = UserSubscription),
+        (status = 404, description = "Not subscribed")
+    ),
+    security(("api_token" = []))
+)]
+#[put("/api/v1/subscriptions/{id}")]
+#[instrument(skip(clients))]
+pub async fn update_subscription(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    id: web::Path<i64>,
+    body: web::Json<SubscriptionUpdate>,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Write)?;
+    if !dto::UserSubscription::update(
+        &user.user_id,
+        *id,
+        &body.title,
+        &body.category,
+        &clients.pool,
+    )
+    .await?
+    {
+        return Err(MyError::Missing("Subscription".to_string()));
+    }
+    let subscription = dto::UserSubscription::fetch(&user.user_id, *id, &clients.pool).await?;
+    Ok(HttpResponse::Ok().json(subscription))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/subscriptions/{id}",
+    params(("id" = i64, Path, description = "Subscription id")),
+    responses(
+        (status = 204, description = "Unsubscribed"),
+        (status = 404, description = "Not subscribed")
+    ),
+    security(("api_token" = []))
+)]
+#[delete("/api/v1/subscriptions/{id}")]
+#[instrument(skip(clients))]
+pub async fn delete_subscription(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Write)?;
+    if !dto::UserSubscription::delete(&user.user_id, *id, &clients.pool).await? {
+        return Err(MyError::Missing("Subscription".to_string()));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/items",
+    params(ItemsQuery),
+    responses((status = 200, description = "Items, newest first", body = ItemPage)),
+    security(("api_token" = []))
+)]
+#[get("/api/v1/items")]
+#[instrument(skip(clients))]
+pub async fn list_items(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    query: web::Query<ItemsQuery>,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Read)?;
+    let filter_items = match (query.subscription_id, &query.title, &query.tag) {
+        (Some(id), _, _) => FilterItems::Id(id),
+        (None, Some(title), _) => FilterItems::Title(title.clone()),
+        (None, None, Some(tag)) => FilterItems::Tag(tag.clone()),
+        (None, None, None) => FilterItems::All,
+    };
+    let show_unreads = if query.unread_only {
+        ShowUnreads::ShowUnreads
+    } else {
+        ShowUnreads::ShowEverything
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let items = dto::Item::fetch_page(
+        &user.user_id,
+        &filter_items,
+        &show_unreads,
+        limit,
+        offset,
+        &clients.pool,
+    )
+    .await?;
+    let next_offset = if items.len() as i64 == limit {
+        Some(offset + limit)
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(ItemPage { items, next_offset }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/items/{id}",
+    params(("id" = i64, Path, description = "Item id")),
+    responses(
+        (status = 200, description = "The item", body = Item),
+        (status = 404, description = "No such item for the user")
+    ),
+    security(("api_token" = []))
+)]
+#[get("/api/v1/items/{id}")]
+#[instrument(skip(clients))]
+pub async fn get_item(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Read)?;
+    let item = dto::Item::fetch(&user.user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    Ok(HttpResponse::Ok().json(item))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/items/read",
+    request_body = ItemIds,
+    responses((status = 200, description = "How many of the items the user can see were marked", body = Updated)),
+    security(("api_token" = []))
+)]
+#[post("/api/v1/items/read")]
+#[instrument(skip(clients))]
+pub async fn mark_items_read(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    body: web::Json<ItemIds>,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Write)?;
+    let updated = set_items_read(&clients, &user.user_id, &body.ids, true).await?;
+    Ok(HttpResponse::Ok().json(Updated { updated }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/items/unread",
+    request_body = ItemIds,
+    responses((status = 200, description = "How many of the items the user can see were marked", body = Updated)),
+    security(("api_token" = []))
+)]
+#[post("/api/v1/items/unread")]
+#[instrument(skip(clients))]
+pub async fn mark_items_unread(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    body: web::Json<ItemIds>,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Write)?;
+    let updated = set_items_read(&clients, &user.user_id, &body.ids, false).await?;
+    Ok(HttpResponse::Ok().json(Updated { updated }))
+}
+
+#[get("/api/v1/openapi.json")]
+#[instrument]
+pub async fn openapi_json() -> Result<HttpResponse, MyError> {
+    Ok(HttpResponse::Ok().json(ApiDoc::openapi()))
+}
+
+/// Only touches the items of the subscriptions of the user
+async fn set_items_read(
+    clients: &Clients,
+    user_id: &dto::UserId,
+    ids: &[i64],
+    read: bool,
+) -> Result<usize, MyError> {
+    if ids.len() > MAX_BULK_ITEMS {
+        return Err(MyError::BadParam(
+            "ids".into(),
+            format!("At most {} items at a time", MAX_BULK_ITEMS),
+        ));
+    }
+    let now = Utc::now().timestamp();
+    let mut updated = 0;
+    let mut transaction = clients.pool.begin().await.map_err(Report::from)?;
+    for id in ids {
+        if dto::Item::fetch(user_id, *id, &mut transaction)
+            .await?
+            .is_none()
+        {
+            continue;
+        }
+        let user_item = dto::UserItem::new(user_id, *id);
+        if read {
+            user_item.mark_read(now, &mut transaction).await?;
+        } else {
+            user_item.mark_unread(&mut transaction).await?;
+        }
+        updated += 1;
+    }
+    transaction.commit().await.map_err(Report::from)?;
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_document() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = document["paths"].as_object().unwrap();
+        assert!(paths.contains_key("/api/v1/subscriptions"));
+        assert!(paths.contains_key("/api/v1/subscriptions/{id}"));
+        assert!(paths.contains_key("/api/v1/items"));
+        assert!(paths.contains_key("/api/v1/items/{id}"));
+        assert!(paths.contains_key("/api/v1/items/read"));
+        assert!(paths.contains_key("/api/v1/items/unread"));
+        assert!(document["components"]["schemas"]["Item"].is_object());
+        assert!(document["components"]["securitySchemes"]["api_token"].is_object());
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use actix_web::{post, web, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use sha3::{Digest, Sha3_256};
+use tracing::instrument;
+
+use crate::{
+    clients::Clients,
+    dto::{self, User, UserId},
+};
+
+use super::MyError;
+
+const API_VERSION: i64 = 3;
+/// Fever hands out at most this many items per call
+const ITEMS_PER_CALL: i64 = 50;
+/// Group ids fit in 32 bits, some clients keep them in an `int`
+const MAX_GROUP_ID: i64 = i32::MAX as i64;
+
+#[derive(Deserialize, Clone, Default)]
+pub struct FeverForm {
+    api_key: Option<String>,
+    mark: Option<String>,
+    #[serde(rename = "as")]
+    mark_as: Option<String>,
+    id: Option<String>,
+    before: Option<i64>,
+}
+
+impl std::fmt::Debug for FeverForm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FeverForm")
+            .field("api_key", &"redacted")
+            .field("mark", &self.mark)
+            .field("as", &self.mark_as)
+            .field("id", &self.id)
+            .field("before", &self.before)
+            .finish()
+    }
+}
+
+/// The Fever api, what to return is picked by the keys of the query string like
+/// `/fever/?api&items&since_id=10`. Clients authenticate with `api_key` in the form body,
+/// `md5("username:api password")`, set from the account page.
+#[post("/fever/")]
+#[instrument(skip(clients, query))]
+pub async fn fever_api(
+    clients: web::Data<Clients>,
+    query: web::Query<HashMap<String, String>>,
+    form: web::Form<FeverForm>,
+) -> Result<HttpResponse, MyError> {
+    let mut response = Map::new();
+    response.insert("api_version".into(), json!(API_VERSION));
+    let user = match &form.api_key {
+        Some(api_key) => User::fetch_by_fever_api_key(&clients.pool, &api_key.to_lowercase())
+            .await?
+            .filter(|user| !user.disabled()),
+        None => None,
+    };
+    let user_id = match user {
+        Some(user) => UserId::clone(&user),
+        None => {
+            response.insert("auth".into(), json!(0));
+            return Ok(HttpResponse::Ok().json(Value::Object(response)));
+        }
+    };
+    response.insert("auth".into(), json!(1));
+    response.insert(
+        "last_refreshed_on_time".into(),
+        json!(Utc::now().timestamp()),
+    );
+
+    let subscriptions = dto::UserSubscription::fetch_all(&user_id, &clients.pool).await?;
+    let groups = Groups::new(&subscriptions);
+
+    if form.mark.is_some() {
+        mark(&clients, &user_id, &form, &groups).await?;
+    }
+    if query.contains_key("groups") {
+        response.insert("groups".into(), groups.groups());
+        response.insert("feeds_groups".into(), groups.feeds_groups(&subscriptions));
+    }
+    let icons = if query.contains_key("feeds") || query.contains_key("favicons") {
+        dto::SubscriptionIcon::fetch_for_user(&user_id, &clients.pool).await?
+    } else {
+        Vec::new()
+    };
+    if query.contains_key("feeds") {
+        let icon_ids: BTreeSet<i64> = icons.iter().map(|x| x.subscription_id).collect();
+        let feeds: Vec<Value> = subscriptions
+            .iter()
+            .map(|subscription| {
+                let favicon_id = if icon_ids.contains(&subscription.id) {
+                    subscription.id
+                } else {
+                    0
+                };
+                json!({
+                    "id": subscription.id,
+                    "favicon_id": favicon_id,
+                    "title": subscription.title,
+                    "url": subscription.rss_feed,
+                    "site_url": subscription.rss_feed,
+                    "is_spark": 0,
+                    "last_updated_on_time": 0,
+                })
+            })
+            .collect();
+        response.insert("feeds".into(), json!(feeds));
+        response.insert("feeds_groups".into(), groups.feeds_groups(&subscriptions));
+    }
+    if query.contains_key("favicons") {
+        let favicons: Vec<Value> = icons.iter().filter_map(favicon).collect();
+        response.insert("favicons".into(), json!(favicons));
+    }
+    if query.contains_key("items") {
+        let items = items(&clients, &user_id, &query).await?;
+        let total_items = dto::Item::count(&user_id, &clients.pool).await?;
+        response.insert("items".into(), json!(items));
+        response.insert("total_items".into(), json!(total_items));
+    }
+    if query.contains_key("unread_item_ids") {
+        let ids = dto::Item::fetch_unread_ids(&user_id, &clients.pool).await?;
+        response.insert("unread_item_ids".into(), json!(join_ids(&ids)));
+    }
+    if query.contains_key("saved_item_ids") {
+        let ids = dto::Item::fetch_starred_ids(&user_id, &clients.pool).await?;
+        response.insert("saved_item_ids".into(), json!(join_ids(&ids)));
+    }
+    Ok(HttpResponse::Ok().json(Value::Object(response)))
+}
+
+/// Fever favicons are data urls without the `data:`, numbered like the subscriptions
+fn favicon(icon: &dto::SubscriptionIcon) -> Option<Value> {
+    Some(json!({
+        "id": icon.subscription_id,
+        "data": format!(
+            "{};base64,{}",
+            icon.content_type.as_deref()?,
+            base64::encode(icon.data.as_deref()?)
+        ),
+    }))
+}
+
+/// Fever groups are our categories. Categories are only names, so the id of a group is a hash of
+/// its name, which does not change when other categories come and go.
+struct Groups {
+    /// The ids and the categories, in alphabetical order
+    groups: Vec<(i64, String)>,
+}
+
+impl Groups {
+    fn new(subscriptions: &[dto::UserSubscription]) -> Self {
+        let categories: BTreeSet<&String> = subscriptions.iter().map(|x| &x.category).collect();
+        let mut taken = HashSet::new();
+        let mut groups = Vec::new();
+        for category in categories {
+            let mut id = group_id(category);
+            // Names hashing to the same id are told apart by their alphabetical order
+            while !taken.insert(id) {
+                id = id % MAX_GROUP_ID + 1;
+            }
+            groups.push((id, category.clone()));
+        }
+        Self { groups }
+    }
+
+    fn id(&self, category: &str) -> Option<i64> {
+        self.groups
+            .iter()
+            .find(|(_, x)| x == category)
+            .map(|(id, _)| *id)
+    }
+
+    fn category(&self, id: i64) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|(x, _)| *x == id)
+            .map(|(_, category)| category.as_str())
+    }
+
+    fn groups(&self) -> Value {
+        let groups: Vec<Value> = self
+            .groups
+            .iter()
+            .map(|(id, category)| json!({ "id": id, "title": category }))
+            .collect();
+        json!(groups)
+    }
+
+    fn feeds_groups(&self, subscriptions: &[dto::UserSubscription]) -> Value {
+        let feeds_groups: Vec<Value> = self
+            .groups
+            .iter()
+            .map(|(id, category)| {
+                let feed_ids: Vec<i64> = subscriptions
+                    .iter()
+                    .filter(|x| &x.category == category)
+                    .map(|x| x.id)
+                    .collect();
+                json!({ "group_id": id, "feed_ids": join_ids(&feed_ids) })
+            })
+            .collect();
+        json!(feeds_groups)
+    }
+}
+
+/// Between 1 and `MAX_GROUP_ID`, from the first bytes of the digest of the name
+fn group_id(category: &str) -> i64 {
+    let digest = Sha3_256::digest(category.as_bytes());
+    let hash = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    i64::from(hash) % MAX_GROUP_ID + 1
+}
+
+async fn items(
+    clients: &Clients,
+    user_id: &UserId,
+    query: &HashMap<String, String>,
+) -> Result<Vec<Value>, MyError> {
+    let items = if let Some(with_ids) = query.get("with_ids") {
+        let mut items = Vec::new();
+        for id in parse_ids(with_ids).into_iter().take(ITEMS_PER_CALL as usize) {
+            if let Some(item) = dto::Item::fetch(user_id, id, &clients.pool).await? {
+                items.push(item);
+            }
+        }
+        items
+    } else {
+        let since_id = query.get("since_id").and_then(|x| x.parse().ok());
+        let max_id = query.get("max_id").and_then(|x| x.parse().ok());
+        // Without either the client wants to start from the beginning
+        let since_id = if max_id.is_none() {
+            since_id.or(Some(0))
+        } else {
+            since_id
+        };
+        dto::Item::fetch_id_range(user_id, since_id, max_id, ITEMS_PER_CALL, &clients.pool).await?
+    };
+    Ok(items
+        .into_iter()
+        .map(|item| {
+            json!({
+                "id": item.id,
+                "feed_id": item.subscription_id,
+                "title": item.title,
+                "author": item.author.unwrap_or_default(),
+                "html": item.contents.or(item.description).unwrap_or_default(),
+                "url": item.link,
+                "is_saved": item.is_starred as i64,
+                "is_read": item.is_read as i64,
+                "created_on_time": item.pub_date,
+            })
+        })
+        .collect())
+}
+
+/// `mark=item&as=read&id=1` or `mark=feed|group&as=read&id=1&before=1626912000`
+async fn mark(
+    clients: &Clients,
+    user_id: &UserId,
+    form: &FeverForm,
+    groups: &Groups,
+) -> Result<(), MyError> {
+    let now = Utc::now().timestamp();
+    let id: i64 = form
+        .id
+        .as_deref()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| MyError::BadParam("id".into(), "Needs a numeric id".into()))?;
+    let mark_as = form.mark_as.as_deref().unwrap_or_default();
+    let before = form.before.unwrap_or(now);
+    match form.mark.as_deref().unwrap_or_default() {
+        "item" => {
+            if dto::Item::fetch(user_id, id, &clients.pool).await?.is_none() {
+                return Err(MyError::Missing("Item".to_string()));
+            }
+            let user_item = dto::UserItem::new(user_id, id);
+            match mark_as {
+                "read" => user_item.mark_read(now, &clients.pool).await?,
+                "unread" => user_item.mark_unread(&clients.pool).await?,
+                "saved" => user_item.star(now, &clients.pool).await?,
+                "unsaved" => user_item.unstar(&clients.pool).await?,
+                _ => return Err(MyError::BadParam("as".into(), mark_as.to_string())),
+            }
+        }
+        "feed" if mark_as == "read" => {
+            dto::UserItem::mark_all_read(user_id, before, now, Some(id), None, false, &clients.pool)
+                .await?
+        }
+        // Group 0 is every feed, negative ids are the sparks which we do not have
+        "group" if mark_as == "read" && id == 0 => {
+            dto::UserItem::mark_all_read(user_id, before, now, None, None, false, &clients.pool)
+                .await?
+        }
+        "group" if mark_as == "read" && id > 0 => {
+            let category = groups
+                .category(id)
+                .ok_or_else(|| MyError::Missing("Group".to_string()))?;
+            dto::UserItem::mark_all_read(
+                user_id,
+                before,
+                now,
+                None,
+                Some(category),
+                false,
+                &clients.pool,
+            )
+            .await?
+        }
+        "group" if mark_as == "read" => (),
+        mark => {
+            return Err(MyError::BadParam(
+                "mark".into(),
+                format!("Cannot mark {} as {}", mark, mark_as),
+            ))
+        }
+    }
+    Ok(())
+}
+
+fn join_ids(ids: &[i64]) -> String {
+    ids.iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_ids(ids: &str) -> Vec<i64> {
+    ids.split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_favicon() {
+        let icon = dto::SubscriptionIcon {
+            subscription_id: 3,
+            content_type: Some("image/png".to_string()),
+            data: Some(b"png".to_vec()),
+            fetched_on: 0,
+        };
+        assert_eq!(
+            favicon(&icon),
+            Some(json!({ "id": 3, "data": "image/png;base64,cG5n" }))
+        );
+        assert_eq!(
+            favicon(&dto::SubscriptionIcon {
+                data: None,
+                ..icon
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut subscriptions = vec![
+            dto::UserSubscription::for_tests(1, "Comics"),
+            dto::UserSubscription::for_tests(2, "Blogs"),
+            dto::UserSubscription::for_tests(3, "Comics"),
+        ];
+        let groups = Groups::new(&subscriptions);
+        let blogs = groups.id("Blogs").unwrap();
+        let comics = groups.id("Comics").unwrap();
+        assert_ne!(blogs, comics);
+        assert!(blogs > 0 && blogs <= MAX_GROUP_ID);
+        assert_eq!(groups.category(comics), Some("Comics"));
+        assert_eq!(groups.category(0), None);
+        assert_eq!(
+            groups.feeds_groups(&subscriptions),
+            json!([
+                { "group_id": blogs, "feed_ids": "2" },
+                { "group_id": comics, "feed_ids": "1,3" },
+            ])
+        );
+
+        // A new category sorting first leaves the ids of the others alone
+        subscriptions.push(dto::UserSubscription::for_tests(4, "Art"));
+        let groups = Groups::new(&subscriptions);
+        assert_eq!(groups.id("Blogs"), Some(blogs));
+        assert_eq!(groups.id("Comics"), Some(comics));
+        assert_eq!(groups.category(comics), Some("Comics"));
+        let art = groups.id("Art").unwrap();
+        assert!(art != blogs && art != comics);
+        subscriptions.remove(1);
+        assert_eq!(Groups::new(&subscriptions).id("Comics"), Some(comics));
+    }
+
+    #[test]
+    fn test_ids() {
+        assert_eq!(parse_ids("1, 2,x,3"), vec![1, 2, 3]);
+        assert_eq!(join_ids(&[1, 2, 3]), "1,2,3");
+        assert_eq!(join_ids(&[]), "");
+    }
+}
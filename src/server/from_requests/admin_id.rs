@@ -0,0 +1,32 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use color_eyre::eyre::eyre;
+use futures::{future::LocalBoxFuture, FutureExt};
+
+use crate::{clients::Clients, dto, server::MyError};
+
+use super::user_id::UserIdPart;
+
+/// A logged in user that is also an admin
+#[derive(Debug, Clone)]
+pub struct AdminIdPart(pub dto::UserId);
+
+impl FromRequest for AdminIdPart {
+    type Error = MyError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[inline]
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let clients = req.app_data::<web::Data<Clients>>().cloned();
+        let user_id = UserIdPart::from_request(req, payload);
+        let value = user_id.then(|user_id| async move {
+            let UserIdPart(user_id) = user_id?;
+            let clients = clients.ok_or_else(|| eyre!("Could not extract clients"))?;
+            if !user_id.is_admin(&clients.pool).await? {
+                return Err(MyError::Forbidden(eyre!("{:?} is not an admin", user_id)));
+            }
+            Ok(Self(user_id))
+        });
+        Box::pin(value)
+    }
+}
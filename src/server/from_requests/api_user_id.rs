@@ -0,0 +1,78 @@
+use actix_web::{dev::Payload, http::header::AUTHORIZATION, web, FromRequest, HttpRequest};
+use chrono::Utc;
+use color_eyre::{eyre::eyre, Report};
+use futures::{future::LocalBoxFuture, FutureExt};
+
+use crate::{
+    clients::Clients,
+    dto::{ApiScope, ApiToken, User, UserId},
+    server::MyError,
+    session::Sessions,
+};
+
+/// Don't write `last_used` on every request of a busy client
+const LAST_USED_RESOLUTION_S: i64 = 60;
+
+/// The user behind an `Authorization: Bearer` api token, the api counterpart of `UserIdPart`.
+/// `Authorization: GoogleLogin auth=` works as well.
+#[derive(Debug, Clone)]
+pub struct ApiUserIdPart {
+    pub user_id: UserId,
+    pub scope: ApiScope,
+}
+
+impl ApiUserIdPart {
+    pub fn require(&self, needed: ApiScope) -> Result<(), MyError> {
+        if self.scope.allows(needed) {
+            Ok(())
+        } else {
+            Err(MyError::Forbidden(eyre!(
+                "Token of {:?} needs the {} scope",
+                self.user_id,
+                needed.name()
+            )))
+        }
+    }
+}
+
+impl FromRequest for ApiUserIdPart {
+    type Error = MyError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| {
+                // Google Reader clients send the token from ClientLogin this way
+                x.strip_prefix("Bearer ")
+                    .or_else(|| x.strip_prefix("GoogleLogin auth="))
+            })
+            .map(|x| x.trim().to_string());
+        let clients = req.app_data::<web::Data<Clients>>().cloned();
+        let value = async move {
+            let token = token.ok_or_else(|| eyre!("Need a bearer token"))?;
+            let clients = clients.ok_or_else(|| eyre!("Could not extract clients"))?;
+            let api_token = ApiToken::fetch(&Sessions::hash_token(&token), &clients.pool)
+                .await?
+                .ok_or_else(|| eyre!("Unknown api token"))?;
+            let user = User::fetch_by_id(&clients.pool, &api_token.user_id).await?;
+            if user.disabled() {
+                return Err(eyre!("User {} is disabled", user.username()));
+            }
+            let now = Utc::now().timestamp();
+            if api_token.last_used.unwrap_or(0) + LAST_USED_RESOLUTION_S < now {
+                ApiToken::touch(api_token.id, now, &clients.pool).await?;
+            }
+            Ok::<_, Report>(Self {
+                user_id: api_token.user_id,
+                scope: api_token.scope,
+            })
+        }
+        .map(|x| x.map_err(MyError::Unauthorized));
+        Box::pin(value)
+    }
+}
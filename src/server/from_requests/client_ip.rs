@@ -0,0 +1,80 @@
+use std::net::IpAddr;
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+
+use crate::{clients::Clients, server::MyError};
+
+/// The address the request comes from. `X-Forwarded-For` is only read when the request comes
+/// from one of the `trusted_proxies`, anyone else could pick a new address on every request.
+#[derive(Debug, Clone)]
+pub struct ClientIp(pub String);
+
+impl FromRequest for ClientIp {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let trusted_proxies = req
+            .app_data::<web::Data<Clients>>()
+            .map(|clients| clients.settings.trusted_proxies.as_slice())
+            .unwrap_or_default();
+        let forwarded_for = req
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|x| x.to_str().ok());
+        let ip = client_ip(
+            req.peer_addr().map(|addr| addr.ip()),
+            forwarded_for,
+            trusted_proxies,
+        );
+        ready(Ok(Self(
+            ip.map(|ip| ip.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+        )))
+    }
+}
+
+/// Each proxy appends the address it got the request from, so the client is the first address
+/// from the right that is not one of our proxies
+fn client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let mut ip = peer?;
+    let hops = forwarded_for.unwrap_or_default().rsplit(',');
+    for hop in hops {
+        if !trusted_proxies.contains(&ip) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(hop) => ip = hop,
+            Err(_) => break,
+        }
+    }
+    Some(ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_ip() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let spoofed = Some("198.51.100.1, 203.0.113.7");
+        assert_eq!(client_ip(Some(client), spoofed, &[]), Some(client));
+        assert_eq!(client_ip(Some(client), spoofed, &[proxy]), Some(client));
+        assert_eq!(client_ip(Some(proxy), spoofed, &[proxy]), Some(client));
+        assert_eq!(client_ip(Some(proxy), None, &[proxy]), Some(proxy));
+        assert_eq!(
+            client_ip(Some(proxy), Some("garbage"), &[proxy]),
+            Some(proxy)
+        );
+        assert_eq!(client_ip(None, spoofed, &[proxy]), None);
+    }
+}
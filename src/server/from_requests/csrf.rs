@@ -0,0 +1,59 @@
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use color_eyre::eyre::eyre;
+use futures::{future::LocalBoxFuture, FutureExt};
+
+use crate::server::MyError;
+
+use super::session::SessionPart;
+
+/// Header htmx sends the token back in, see `templates::Home`
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// The CSRF token of the session, embedded in the pages so htmx can send it back
+#[derive(Debug, Clone, Default)]
+pub struct CsrfToken(pub String);
+
+impl FromRequest for CsrfToken {
+    type Error = MyError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[inline]
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let value = SessionPart::from_request(req, payload)
+            .map(|session| session.map(|SessionPart(session)| Self(session.csrf_token)));
+        Box::pin(value)
+    }
+}
+
+/// Guard for the state changing routes, the request has to carry the CSRF token of the session
+#[derive(Debug, Clone)]
+pub struct CsrfChecked;
+
+impl FromRequest for CsrfChecked {
+    type Error = MyError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[inline]
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let header = req
+            .headers()
+            .get(CSRF_HEADER)
+            .and_then(|x| x.to_str().ok())
+            .map(String::from);
+        let value = SessionPart::from_request(req, payload).map(move |session| {
+            let SessionPart(session) = session?;
+            match header {
+                Some(header) if !session.csrf_token.is_empty() && header == session.csrf_token => {
+                    Ok(Self)
+                }
+                _ => Err(MyError::Forbidden(eyre!(
+                    "Missing or invalid CSRF token for {:?}",
+                    session.user_id
+                ))),
+            }
+        });
+        Box::pin(value)
+    }
+}
@@ -0,0 +1,109 @@
+use actix_web::{dev::Payload, http::header::USER_AGENT, web, FromRequest, HttpRequest};
+use color_eyre::{eyre::eyre, Report, Result};
+use futures::{future::LocalBoxFuture, FutureExt};
+use sqlx::SqlitePool;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    clients::Clients,
+    dto::{self, User, UserId},
+    server::MyError,
+    session::{Sessions, SSID},
+    settings::ProxyAuth,
+};
+
+/// The session behind the `ssid` cookie, or the session of the user named by a trusted proxy
+#[derive(Debug, Clone)]
+pub struct SessionPart(pub dto::Session);
+
+impl FromRequest for SessionPart {
+    type Error = MyError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let ssid = req.cookie(SSID);
+        let sessions = req.app_data::<web::Data<Sessions>>().cloned();
+        let clients = req.app_data::<web::Data<Clients>>().cloned();
+        let proxy_user = clients
+            .as_ref()
+            .and_then(|clients| clients.settings.proxy_auth.clone())
+            .and_then(|proxy_auth| {
+                let username = proxy_username(req, &proxy_auth)?;
+                Some((username, proxy_auth.auto_provision))
+            });
+        let user_agent = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|x| x.to_str().ok())
+            .map(String::from);
+        let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+        let value = async move {
+            let sessions = sessions.ok_or_else(|| eyre!("Could not extract sessions"))?;
+            let clients = clients.ok_or_else(|| eyre!("Could not extract clients"))?;
+            if let Some((username, auto_provision)) = proxy_user {
+                let user_id = proxy_user_id(&username, auto_provision, &clients.pool).await?;
+                let session = sessions
+                    .fetch_for_proxy_user(
+                        &user_id,
+                        user_agent.as_deref(),
+                        ip.as_deref(),
+                        &clients.pool,
+                    )
+                    .await?;
+                return Ok(Self(session));
+            }
+            let session = ssid.ok_or_else(|| eyre!("Need to have a SSID cookie"))?;
+            let session = sessions
+                .fetch(session.value(), &clients.pool)
+                .await?
+                .ok_or_else(|| eyre!("No cookie in sessions"))?;
+
+            Ok::<_, Report>(Self(session))
+        }
+        .map(|x| x.map_err(MyError::NotLoggedIn));
+        Box::pin(value)
+    }
+}
+
+/// The username from the proxy header, only when the request comes directly from a trusted proxy.
+/// Uses the peer address, not the forwarded one, since the client can set that one.
+fn proxy_username(req: &HttpRequest, proxy_auth: &ProxyAuth) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let username = req
+        .headers()
+        .get(proxy_auth.header.as_str())?
+        .to_str()
+        .ok()?
+        .trim();
+    if username.is_empty() {
+        return None;
+    }
+    if !proxy_auth.trusted_proxies.contains(&peer) {
+        warn!(%peer, "Ignoring {} from an untrusted address", proxy_auth.header);
+        return None;
+    }
+    Some(username.to_string())
+}
+
+async fn proxy_user_id(username: &str, auto_provision: bool, pool: &SqlitePool) -> Result<UserId> {
+    if let Ok(user) = User::fetch(pool, username).await {
+        if user.disabled() {
+            return Err(eyre!("User {} is disabled", username));
+        }
+        return Ok(UserId::clone(&user));
+    }
+    if !auto_provision {
+        return Err(eyre!("Proxy user {} does not exist", username));
+    }
+    info!("Creating user {} for the proxy", username);
+    // Nobody knows the password, the user can only get in through the proxy
+    let password = Uuid::new_v4().to_string();
+    match User::insert(username, &password, false, pool).await {
+        Ok(user_id) => Ok(user_id),
+        // Another request could have created the user in the meantime
+        Err(_) => Ok(UserId::clone(&User::fetch(pool, username).await?)),
+    }
+}
@@ -1,8 +1,9 @@
-use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
-use color_eyre::eyre::eyre;
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
 use futures::{future::LocalBoxFuture, FutureExt};
 
-use crate::{dto, server::MyError, session::SessionMap};
+use crate::{dto, server::MyError};
+
+use super::session::SessionPart;
 
 #[derive(Debug, Clone)]
 pub struct UserIdPart(pub dto::UserId);
@@ -14,20 +15,8 @@ impl<'a> FromRequest for UserIdPart {
 
     #[inline]
     fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
-        let ssid = req.cookie("ssid");
-        let value = web::Data::<SessionMap>::from_request(req, payload)
-            .then(|session_map| async move {
-                let session = ssid.ok_or_else(|| eyre!("Need to have a SSID cookie"))?;
-                let session = session.value();
-                let sessions_data = session_map.map_err(|_| eyre!("Could not extract sessions"))?;
-                let mut sessions = sessions_data.lock().await;
-                let user_id = sessions
-                    .get(&session.to_string())
-                    .ok_or_else(|| eyre!("No cookie in sessions"))?;
-
-                Ok(Self(user_id.user_id().clone()))
-            })
-            .map(|x| x.map_err(MyError::NotLoggedIn));
+        let value = SessionPart::from_request(req, payload)
+            .map(|session| session.map(|SessionPart(session)| Self(session.user_id)));
         Box::pin(value)
     }
 }
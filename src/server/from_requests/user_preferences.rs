@@ -10,6 +10,7 @@ pub enum FilterItems {
     All,
     Id(i64),
     Title(String),
+    Tag(String),
 }
 impl FilterItems {
     pub fn as_items(&self) -> (Option<i64>, Option<String>) {
@@ -23,6 +24,12 @@ impl FilterItems {
         };
         (id, title)
     }
+    pub fn tag(&self) -> Option<String> {
+        match self {
+            FilterItems::Tag(tag) => Some(tag.clone()),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize)]
@@ -0,0 +1,704 @@
+use std::collections::HashMap;
+
+use actix_web::{get, http::header::USER_AGENT, post, web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    clients::Clients,
+    dto::{self, ApiScope, ItemStream, UserId},
+    login_throttle::LoginThrottle,
+    server::from_requests::user_preferences::{FilterItems, ShowUnreads},
+};
+
+use super::{
+    account::new_api_token,
+    from_requests::{api_user_id::ApiUserIdPart, client_ip::ClientIp},
+    login::authenticate,
+    subscriptions::subscribe,
+    MyError,
+};
+
+const READING_LIST: &str = "state/com.google/reading-list";
+const STARRED: &str = "state/com.google/starred";
+const READ: &str = "state/com.google/read";
+const LABEL: &str = "label/";
+const ITEM_PREFIX: &str = "tag:google.com,2005:reader/item/";
+const DEFAULT_COUNT: i64 = 20;
+const MAX_COUNT: i64 = 10_000;
+/// Category for feeds subscribed to without a label
+const NO_CATEGORY: &str = "Uncategorized";
+/// Name of the api tokens handed out by ClientLogin, followed by the app
+const CLIENT_TOKEN_NAME: &str = "Google Reader client";
+const MAX_CLIENT_NAME: usize = 100;
+
+/// Query strings and forms of the reader api repeat keys, like `i=1&i=2`
+type Params = Vec<(String, String)>;
+
+fn param<'a>(params: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.as_str())
+}
+
+fn params_all<'a>(params: &'a [(String, String)], key: &'a str) -> impl Iterator<Item = &'a str> {
+    params
+        .iter()
+        .filter(move |(k, _)| k == key)
+        .map(|(_, value)| value.as_str())
+}
+
+/// The part of a stream or tag id after `user/<id>/`, the user is always the one logged in
+fn user_tag(tag: &str) -> Option<&str> {
+    let rest = tag.strip_prefix("user/")?;
+    rest.split_once('/').map(|(_, tag)| tag)
+}
+
+fn label_id(category: &str) -> String {
+    format!("user/-/{}{}", LABEL, category)
+}
+
+fn feed_id(subscription_id: i64) -> String {
+    format!("feed/{}", subscription_id)
+}
+
+fn long_item_id(id: i64) -> String {
+    format!("{}{:016x}", ITEM_PREFIX, id)
+}
+
+/// Item ids come either long, in hex, or short, in decimal
+fn parse_item_id(id: &str) -> Option<i64> {
+    match id.strip_prefix(ITEM_PREFIX) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => id.parse().ok(),
+    }
+}
+
+/// Feeds are `feed/<subscription id>`, the ones we hand out, or `feed/<url>` when subscribing
+fn find_subscription<'a>(
+    subscriptions: &'a [dto::UserSubscription],
+    stream: &str,
+) -> Option<&'a dto::UserSubscription> {
+    let feed = stream.strip_prefix("feed/")?;
+    match feed.parse::<i64>() {
+        Ok(id) => subscriptions.iter().find(|x| x.id == id),
+        Err(_) => subscriptions.iter().find(|x| x.rss_feed == feed),
+    }
+}
+
+fn parse_stream(
+    subscriptions: &[dto::UserSubscription],
+    stream: &str,
+) -> Result<ItemStream, MyError> {
+    let mut item_stream = ItemStream::default();
+    if stream.starts_with("feed/") {
+        let subscription = find_subscription(subscriptions, stream)
+            .ok_or_else(|| MyError::Missing(stream.to_string()))?;
+        item_stream.subscription_id = Some(subscription.id);
+        return Ok(item_stream);
+    }
+    match user_tag(stream) {
+        Some(READING_LIST) => (),
+        Some(STARRED) => item_stream.starred_only = true,
+        Some(tag) if tag.starts_with(LABEL) => {
+            item_stream.category = Some(tag[LABEL.len()..].to_string())
+        }
+        _ => return Err(MyError::BadParam("s".into(), stream.to_string())),
+    }
+    Ok(item_stream)
+}
+
+/// Everything the item queries take from `s`, `xt`, `it`, `ot`, `nt`, `r`, `n` and `c`
+fn item_query(
+    subscriptions: &[dto::UserSubscription],
+    stream: &str,
+    params: &[(String, String)],
+) -> Result<(ItemStream, i64, i64), MyError> {
+    let mut item_stream = parse_stream(subscriptions, stream)?;
+    if params_all(params, "xt").any(|tag| user_tag(tag) == Some(READ)) {
+        item_stream.unread_only = true;
+    }
+    if params_all(params, "it").any(|tag| user_tag(tag) == Some(STARRED)) {
+        item_stream.starred_only = true;
+    }
+    item_stream.newer_than = param(params, "ot").and_then(|x| x.parse().ok());
+    item_stream.older_than = param(params, "nt").and_then(|x| x.parse().ok());
+    item_stream.oldest_first = param(params, "r") == Some("o");
+    let count = param(params, "n")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_COUNT)
+        .clamp(1, MAX_COUNT);
+    let offset = param(params, "c")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(0i64)
+        .max(0);
+    Ok((item_stream, count, offset))
+}
+
+fn item_json(item: &dto::Item, subscription: Option<&dto::UserSubscription>) -> Value {
+    let mut categories = vec![format!("user/-/{}", READING_LIST)];
+    if item.is_read {
+        categories.push(format!("user/-/{}", READ));
+    }
+    if item.is_starred {
+        categories.push(format!("user/-/{}", STARRED));
+    }
+    if let Some(subscription) = subscription {
+        categories.push(label_id(&subscription.category));
+    }
+    json!({
+        "id": long_item_id(item.id),
+        "crawlTimeMsec": (item.pub_date * 1000).to_string(),
+        "timestampUsec": (item.pub_date * 1_000_000).to_string(),
+        "published": item.pub_date,
+        "updated": item.pub_date,
+        "title": item.title,
+        "author": item.author.clone().unwrap_or_default(),
+        "canonical": [{ "href": item.link }],
+        "alternate": [{ "href": item.link, "type": "text/html" }],
+        "summary": {
+            "direction": "ltr",
+            "content": item.contents.clone().or_else(|| item.description.clone()).unwrap_or_default(),
+        },
+        "categories": categories,
+        "origin": {
+            "streamId": feed_id(item.subscription_id),
+            "title": subscription.map(|x| x.title.as_str()).unwrap_or_default(),
+            "htmlUrl": subscription.map(|x| x.rss_feed.as_str()).unwrap_or_default(),
+        },
+    })
+}
+
+fn items_json(
+    stream: &str,
+    items: &[dto::Item],
+    subscriptions: &[dto::UserSubscription],
+    continuation: Option<i64>,
+) -> Value {
+    let subscription_map: HashMap<_, _> = subscriptions.iter().map(|x| (x.id, x)).collect();
+    let mut response = json!({
+        "id": stream,
+        "updated": Utc::now().timestamp(),
+        "items": items
+            .iter()
+            .map(|item| item_json(item, subscription_map.get(&item.subscription_id).copied()))
+            .collect::<Vec<_>>(),
+    });
+    if let Some(continuation) = continuation {
+        response["continuation"] = json!(continuation.to_string());
+    }
+    response
+}
+
+fn ok() -> HttpResponse {
+    HttpResponse::Ok().content_type("text/plain").body("OK")
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ClientLoginForm {
+    #[serde(rename = "Email")]
+    email: String,
+    #[serde(rename = "Passwd")]
+    passwd: String,
+}
+
+impl std::fmt::Debug for ClientLoginForm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientLoginForm")
+            .field("Email", &self.email)
+            .field("Passwd", &"redacted")
+            .finish()
+    }
+}
+
+/// Logs in with the account password and hands out a new api token, which shows up on
+/// `/account/tokens` where it can be revoked. Every app gets its own token, only the token of an
+/// earlier login of the same app is replaced, clients log in again whenever they lose the token.
+#[post("/accounts/ClientLogin")]
+#[instrument(skip(clients, login_throttle, request))]
+pub async fn client_login(
+    clients: web::Data<Clients>,
+    login_throttle: web::Data<LoginThrottle>,
+    form: web::Form<ClientLoginForm>,
+    request: HttpRequest,
+    ClientIp(ip): ClientIp,
+) -> Result<HttpResponse, MyError> {
+    let user = authenticate(&clients, &login_throttle, &form.email, &form.passwd, &ip).await?;
+    let name = client_token_name(
+        request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|x| x.to_str().ok()),
+    );
+    dto::ApiToken::delete_named(&user, &name, &clients.pool).await?;
+    let token = new_api_token(&clients, &user, &name, ApiScope::Write).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body(format!("SID={}\nLSID={}\nAuth={}\n", token, token, token)))
+}
+
+/// Apps are told apart by their user agent, cut short to keep the tokens page readable
+fn client_token_name(user_agent: Option<&str>) -> String {
+    match user_agent.map(str::trim).filter(|x| !x.is_empty()) {
+        Some(user_agent) => format!(
+            "{} ({})",
+            CLIENT_TOKEN_NAME,
+            user_agent.chars().take(MAX_CLIENT_NAME).collect::<String>()
+        ),
+        None => CLIENT_TOKEN_NAME.to_string(),
+    }
+}
+
+/// Requests are authenticated by header so there is nothing to forge, any token will do
+#[get("/reader/api/0/token")]
+#[instrument]
+pub async fn token(_user: ApiUserIdPart) -> Result<HttpResponse, MyError> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain")
+        .body(Uuid::new_v4().to_simple().to_string()))
+}
+
+#[get("/reader/api/0/user-info")]
+#[instrument(skip(clients))]
+pub async fn user_info(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+) -> Result<HttpResponse, MyError> {
+    let account = dto::User::fetch_by_id(&clients.pool, &user.user_id).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "userId": user.user_id.0.to_string(),
+        "userName": account.username(),
+        "userProfileId": user.user_id.0.to_string(),
+        "userEmail": "",
+    })))
+}
+
+#[get("/reader/api/0/subscription/list")]
+#[instrument(skip(clients))]
+pub async fn subscription_list(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+) -> Result<HttpResponse, MyError> {
+    let subscriptions = dto::UserSubscription::fetch_all(&user.user_id, &clients.pool).await?;
+    let subscriptions: Vec<Value> = subscriptions
+        .iter()
+        .map(|subscription| {
+            json!({
+                "id": feed_id(subscription.id),
+                "title": subscription.title,
+                "categories": [{
+                    "id": label_id(&subscription.category),
+                    "label": subscription.category,
+                }],
+                "url": subscription.rss_feed,
+                "htmlUrl": subscription.rss_feed,
+                "iconUrl": "",
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({ "subscriptions": subscriptions })))
+}
+
+#[get("/reader/api/0/tag/list")]
+#[instrument(skip(clients))]
+pub async fn tag_list(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+) -> Result<HttpResponse, MyError> {
+    let subscriptions = dto::UserSubscription::fetch_all(&user.user_id, &clients.pool).await?;
+    let mut tags = vec![json!({ "id": format!("user/-/{}", STARRED) })];
+    let mut categories: Vec<&String> = subscriptions.iter().map(|x| &x.category).collect();
+    categories.sort();
+    categories.dedup();
+    tags.extend(
+        categories
+            .into_iter()
+            .map(|category| json!({ "id": label_id(category), "type": "folder" })),
+    );
+    Ok(HttpResponse::Ok().json(json!({ "tags": tags })))
+}
+
+/// `ac=subscribe|unsubscribe|edit`, `s=feed/..` (repeatable), `t` for the title and `a`/`r` to
+/// add and remove the label, our category
+#[post("/reader/api/0/subscription/edit")]
+#[instrument(skip(clients))]
+pub async fn subscription_edit(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    form: web::Form<Params>,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Write)?;
+    let subscriptions = dto::UserSubscription::fetch_all(&user.user_id, &clients.pool).await?;
+    let title = param(&form, "t");
+    let add_label = param(&form, "a")
+        .and_then(user_tag)
+        .and_then(|tag| tag.strip_prefix(LABEL));
+    let remove_label = param(&form, "r")
+        .and_then(user_tag)
+        .and_then(|tag| tag.strip_prefix(LABEL));
+    for stream in params_all(&form, "s") {
+        match param(&form, "ac") {
+            Some("subscribe") => {
+                let url = stream
+                    .strip_prefix("feed/")
+                    .ok_or_else(|| MyError::BadParam("s".into(), stream.to_string()))?;
+                subscribe(
+                    &clients,
+                    &user.user_id,
+                    add_label.unwrap_or(NO_CATEGORY),
+                    title.unwrap_or(url),
+                    url,
+                )
+                .await?;
+            }
+            Some("unsubscribe") => {
+                let subscription = find_subscription(&subscriptions, stream)
+                    .ok_or_else(|| MyError::Missing(stream.to_string()))?;
+                dto::UserSubscription::delete(&user.user_id, subscription.id, &clients.pool)
+                    .await?;
+            }
+            Some("edit") => {
+                let subscription = find_subscription(&subscriptions, stream)
+                    .ok_or_else(|| MyError::Missing(stream.to_string()))?;
+                let category = match (add_label, remove_label) {
+                    (Some(label), _) => label,
+                    (None, Some(label)) if label == subscription.category => NO_CATEGORY,
+                    _ => subscription.category.as_str(),
+                };
+                dto::UserSubscription::update(
+                    &user.user_id,
+                    subscription.id,
+                    title.unwrap_or(&subscription.title),
+                    category,
+                    &clients.pool,
+                )
+                .await?;
+            }
+            action => {
+                return Err(MyError::BadParam(
+                    "ac".into(),
+                    action.unwrap_or_default().to_string(),
+                ))
+            }
+        }
+    }
+    Ok(ok())
+}
+
+#[get("/reader/api/0/unread-count")]
+#[instrument(skip(clients))]
+pub async fn unread_count(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+) -> Result<HttpResponse, MyError> {
+    let subscriptions = dto::UserSubscription::fetch_all(&user.user_id, &clients.pool).await?;
+    let items = dto::Item::fetch_all_not_read(
+        &user.user_id,
+        &FilterItems::All,
+        &ShowUnreads::ShowUnreads,
+        &clients.pool,
+    )
+    .await?;
+    // Count and newest publish date, per stream
+    let mut counts: HashMap<String, (i64, i64)> = HashMap::new();
+    let categories: HashMap<_, _> = subscriptions.iter().map(|x| (x.id, &x.category)).collect();
+    for item in items.iter() {
+        let mut streams = vec![
+            format!("user/-/{}", READING_LIST),
+            feed_id(item.subscription_id),
+        ];
+        if let Some(category) = categories.get(&item.subscription_id) {
+            streams.push(label_id(category));
+        }
+        for stream in streams {
+            let count = counts.entry(stream).or_insert((0, 0));
+            count.0 += 1;
+            count.1 = count.1.max(item.pub_date);
+        }
+    }
+    let unread_counts: Vec<Value> = counts
+        .into_iter()
+        .map(|(id, (count, newest))| {
+            json!({
+                "id": id,
+                "count": count,
+                "newestItemTimestampUsec": (newest * 1_000_000).to_string(),
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({
+        "max": items.len(),
+        "unreadcounts": unread_counts,
+    })))
+}
+
+#[get("/reader/api/0/stream/items/ids")]
+#[instrument(skip(clients))]
+pub async fn stream_item_ids(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    query: web::Query<Params>,
+) -> Result<HttpResponse, MyError> {
+    let subscriptions = dto::UserSubscription::fetch_all(&user.user_id, &clients.pool).await?;
+    let stream = param(&query, "s").unwrap_or("user/-/state/com.google/reading-list");
+    let (item_stream, count, offset) = item_query(&subscriptions, stream, &query)?;
+    let items =
+        dto::Item::fetch_stream(&user.user_id, &item_stream, count, offset, &clients.pool).await?;
+    let item_refs: Vec<Value> = items
+        .iter()
+        .map(|item| {
+            json!({
+                "id": item.id.to_string(),
+                "directStreamIds": [feed_id(item.subscription_id)],
+                "timestampUsec": (item.pub_date * 1_000_000).to_string(),
+            })
+        })
+        .collect();
+    let mut response = json!({ "itemRefs": item_refs });
+    if items.len() as i64 == count {
+        response["continuation"] = json!((offset + count).to_string());
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[get("/reader/api/0/stream/contents/{stream:.*}")]
+#[instrument(skip(clients))]
+pub async fn stream_contents(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    stream: web::Path<String>,
+    query: web::Query<Params>,
+) -> Result<HttpResponse, MyError> {
+    let subscriptions = dto::UserSubscription::fetch_all(&user.user_id, &clients.pool).await?;
+    let stream = match stream.as_str() {
+        "" => param(&query, "s").unwrap_or("user/-/state/com.google/reading-list"),
+        stream => stream,
+    };
+    let (item_stream, count, offset) = item_query(&subscriptions, stream, &query)?;
+    let items =
+        dto::Item::fetch_stream(&user.user_id, &item_stream, count, offset, &clients.pool).await?;
+    let continuation = if items.len() as i64 == count {
+        Some(offset + count)
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(items_json(stream, &items, &subscriptions, continuation)))
+}
+
+/// The contents of the items given as `i`
+#[post("/reader/api/0/stream/items/contents")]
+#[instrument(skip(clients))]
+pub async fn stream_items_contents(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    form: web::Form<Params>,
+) -> Result<HttpResponse, MyError> {
+    let subscriptions = dto::UserSubscription::fetch_all(&user.user_id, &clients.pool).await?;
+    let mut items = Vec::new();
+    for id in params_all(&form, "i").filter_map(parse_item_id) {
+        if let Some(item) = dto::Item::fetch(&user.user_id, id, &clients.pool).await? {
+            items.push(item);
+        }
+    }
+    Ok(HttpResponse::Ok().json(items_json(
+        "user/-/state/com.google/reading-list",
+        &items,
+        &subscriptions,
+        None,
+    )))
+}
+
+/// Adds `a` and removes `r` from the items `i`, only read and starred are kept
+#[post("/reader/api/0/edit-tag")]
+#[instrument(skip(clients))]
+pub async fn edit_tag(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    form: web::Form<Params>,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Write)?;
+    let now = Utc::now().timestamp();
+    let add: Vec<&str> = params_all(&form, "a").filter_map(user_tag).collect();
+    let remove: Vec<&str> = params_all(&form, "r").filter_map(user_tag).collect();
+    for id in params_all(&form, "i").filter_map(parse_item_id) {
+        if dto::Item::fetch(&user.user_id, id, &clients.pool)
+            .await?
+            .is_none()
+        {
+            continue;
+        }
+        let user_item = dto::UserItem::new(&user.user_id, id);
+        for tag in add.iter() {
+            match *tag {
+                READ => user_item.mark_read(now, &clients.pool).await?,
+                STARRED => user_item.star(now, &clients.pool).await?,
+                _ => (),
+            }
+        }
+        for tag in remove.iter() {
+            match *tag {
+                READ => user_item.mark_unread(&clients.pool).await?,
+                STARRED => user_item.unstar(&clients.pool).await?,
+                _ => (),
+            }
+        }
+    }
+    Ok(ok())
+}
+
+/// Marks the stream `s` read up to `ts`, in microseconds
+#[post("/reader/api/0/mark-all-as-read")]
+#[instrument(skip(clients))]
+pub async fn mark_all_as_read(
+    clients: web::Data<Clients>,
+    user: ApiUserIdPart,
+    form: web::Form<Params>,
+) -> Result<HttpResponse, MyError> {
+    user.require(ApiScope::Write)?;
+    let subscriptions = dto::UserSubscription::fetch_all(&user.user_id, &clients.pool).await?;
+    let stream = param(&form, "s").unwrap_or("user/-/state/com.google/reading-list");
+    let item_stream = parse_stream(&subscriptions, stream)?;
+    let now = Utc::now().timestamp();
+    let before = param(&form, "ts")
+        .and_then(|x| x.parse::<i64>().ok())
+        .map(|ts| ts / 1_000_000)
+        .unwrap_or(now);
+    mark_stream_read(&clients, &user.user_id, &item_stream, before, now).await?;
+    Ok(ok())
+}
+
+async fn mark_stream_read(
+    clients: &Clients,
+    user_id: &UserId,
+    item_stream: &ItemStream,
+    before: i64,
+    now: i64,
+) -> Result<(), MyError> {
+    dto::UserItem::mark_all_read(
+        user_id,
+        before,
+        now,
+        item_stream.subscription_id,
+        item_stream.category.as_deref(),
+        item_stream.starred_only,
+        &clients.pool,
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+
+    use super::*;
+    use crate::settings::Settings;
+
+    async fn login(clients: &Clients, user_agent: &str) -> String {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(clients.clone()))
+                .app_data(web::Data::new(LoginThrottle::default()))
+                .service(client_login),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/accounts/ClientLogin")
+            .insert_header((USER_AGENT, user_agent))
+            .set_form(&[("Email", "test"), ("Passwd", "password123")])
+            .to_request();
+        let body = String::from_utf8(test::read_response(&app, request).await.to_vec()).unwrap();
+        body.lines()
+            .find_map(|x| x.strip_prefix("Auth="))
+            .unwrap()
+            .to_string()
+    }
+
+    async fn user_info_status(clients: &Clients, token: &str) -> StatusCode {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(clients.clone()))
+                .service(user_info),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri("/reader/api/0/user-info")
+            .insert_header(("Authorization", format!("GoogleLogin auth={}", token)))
+            .to_request();
+        test::call_service(&app, request).await.status()
+    }
+
+    #[actix_web::rt::test]
+    async fn test_client_login() {
+        let clients = Clients::in_memory(Settings::for_tests()).await.unwrap();
+        dto::User::set_password(&UserId(1), "password123", &clients.pool)
+            .await
+            .unwrap();
+
+        let phone = login(&clients, "Reeder/5.0").await;
+        let desktop = login(&clients, "FeedReader/2.11").await;
+        assert_eq!(user_info_status(&clients, &phone).await, StatusCode::OK);
+        assert_eq!(user_info_status(&clients, &desktop).await, StatusCode::OK);
+
+        // Logging in again from the same app replaces only its own token
+        let phone_again = login(&clients, "Reeder/5.0").await;
+        assert_eq!(
+            user_info_status(&clients, &phone).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            user_info_status(&clients, &phone_again).await,
+            StatusCode::OK
+        );
+        assert_eq!(user_info_status(&clients, &desktop).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_item_ids() {
+        assert_eq!(long_item_id(26), "tag:google.com,2005:reader/item/000000000000001a");
+        assert_eq!(parse_item_id(&long_item_id(26)), Some(26));
+        assert_eq!(parse_item_id("26"), Some(26));
+        assert_eq!(parse_item_id("tag:google.com,2005:reader/item/zz"), None);
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let subscriptions = vec![
+            dto::UserSubscription::for_tests(1, "Comics"),
+            dto::UserSubscription::for_tests(2, "Blogs"),
+        ];
+        let stream = parse_stream(&subscriptions, "feed/2").unwrap();
+        assert_eq!(stream.subscription_id, Some(2));
+        let stream = parse_stream(&subscriptions, "feed/https://example.com/1.xml").unwrap();
+        assert_eq!(stream.subscription_id, Some(1));
+        let stream = parse_stream(&subscriptions, "user/-/label/Comics").unwrap();
+        assert_eq!(stream.category.as_deref(), Some("Comics"));
+        let stream = parse_stream(&subscriptions, "user/1/state/com.google/starred").unwrap();
+        assert!(stream.starred_only);
+        assert!(parse_stream(&subscriptions, "feed/3").is_err());
+        assert!(parse_stream(&subscriptions, "user/-/state/com.google/broadcast").is_err());
+    }
+
+    #[test]
+    fn test_item_query() {
+        let subscriptions = vec![dto::UserSubscription::for_tests(1, "Comics")];
+        let params: Params = vec![
+            ("xt".into(), "user/-/state/com.google/read".into()),
+            ("n".into(), "1000".into()),
+            ("c".into(), "40".into()),
+            ("r".into(), "o".into()),
+            ("ot".into(), "1626912000".into()),
+        ];
+        let (stream, count, offset) =
+            item_query(&subscriptions, "user/-/state/com.google/reading-list", &params).unwrap();
+        assert!(stream.unread_only);
+        assert!(stream.oldest_first);
+        assert_eq!(stream.newer_than, Some(1626912000));
+        assert_eq!(count, 1000);
+        assert_eq!(offset, 40);
+    }
+}
@@ -0,0 +1,66 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use tracing::instrument;
+
+use crate::{clients::Clients, dto};
+
+use super::{from_requests::user_id::UserIdPart, MyError};
+
+/// Icons are looked up again weekly, a day old one is fine
+const ICON_MAX_AGE_S: u64 = 24 * 60 * 60;
+/// Shown until an icon is found, checked again sooner
+const BLANK_MAX_AGE_S: u64 = 60 * 60;
+/// A transparent 1x1 gif, so the pages do not show broken images
+const BLANK_GIF: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff!\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+#[get("/icons/{subscription_id}")]
+#[instrument(skip(clients, request))]
+pub async fn subscription_icon(
+    clients: web::Data<Clients>,
+    subscription_id: web::Path<i64>,
+    request: HttpRequest,
+    UserIdPart(user_id): UserIdPart,
+) -> Result<HttpResponse, MyError> {
+    // The icons would otherwise tell anyone which feeds the others subscribed to
+    dto::SubscriptionSettings::fetch(&user_id, *subscription_id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Subscription".to_string()))?;
+    let icon = dto::SubscriptionIcon::fetch(*subscription_id, &clients.pool).await?;
+    let (content_type, data, fetched_on) = match icon {
+        Some(dto::SubscriptionIcon {
+            content_type: Some(content_type),
+            data: Some(data),
+            fetched_on,
+            ..
+        }) => (content_type, data, fetched_on),
+        _ => {
+            return Ok(HttpResponse::Ok()
+                .content_type("image/gif")
+                .append_header((
+                    "Cache-Control",
+                    format!("private, max-age={}", BLANK_MAX_AGE_S),
+                ))
+                .body(BLANK_GIF))
+        }
+    };
+    let etag = format!("\"{}\"", fetched_on);
+    let cache_control = format!("private, max-age={}", ICON_MAX_AGE_S);
+    let not_modified = request
+        .headers()
+        .get("If-None-Match")
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.split(',').any(|x| x.trim() == etag))
+        .unwrap_or_default();
+    if not_modified {
+        return Ok(HttpResponse::NotModified()
+            .append_header(("ETag", etag))
+            .append_header(("Cache-Control", cache_control))
+            .finish());
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .append_header(("ETag", etag))
+        .append_header(("Cache-Control", cache_control))
+        .append_header(("Content-Security-Policy", "default-src 'none'"))
+        .append_header(("X-Content-Type-Options", "nosniff"))
+        .body(data))
+}
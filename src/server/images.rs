@@ -0,0 +1,101 @@
+use actix_web::{
+    error::ErrorBadGateway,
+    get,
+    http::{header::RANGE, StatusCode},
+    web, HttpRequest, HttpResponse,
+};
+use color_eyre::eyre::eyre;
+use futures::stream;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::clients::Clients;
+
+use super::{from_requests::user_id::UserIdPart, MyError};
+
+/// Proxied images do not change, the browser can keep them for a week
+const IMAGE_MAX_AGE_S: u64 = 7 * 24 * 60 * 60;
+/// What the players need to know of the media to seek in it
+const MEDIA_HEADERS: &[&str] = &["content-type", "content-range", "accept-ranges"];
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProxyImageQuery {
+    url: String,
+    sig: String,
+}
+
+#[get("/proxy/image")]
+#[instrument(skip(clients))]
+pub async fn proxy_image(
+    clients: web::Data<Clients>,
+    query: web::Query<ProxyImageQuery>,
+    UserIdPart(_user_id): UserIdPart,
+) -> Result<HttpResponse, MyError> {
+    if !clients.image_proxy.verify(&query.url, &query.sig) {
+        return Err(MyError::Forbidden(eyre!(
+            "Bad signature for the image {}",
+            query.url
+        )));
+    }
+    let (content_type, image) = clients
+        .image_proxy
+        .image(&query.url)
+        .await
+        .map_err(MyError::CannotFind)?;
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .append_header((
+            "Cache-Control",
+            format!("private, max-age={}", IMAGE_MAX_AGE_S),
+        ))
+        .append_header(("Content-Security-Policy", "default-src 'none'"))
+        .append_header(("X-Content-Type-Options", "nosniff"))
+        .body(image))
+}
+
+/// Streams the audio and video of the articles, signed like the images
+#[get("/proxy/media")]
+#[instrument(skip(clients, request))]
+pub async fn proxy_media(
+    clients: web::Data<Clients>,
+    query: web::Query<ProxyImageQuery>,
+    request: HttpRequest,
+    UserIdPart(_user_id): UserIdPart,
+) -> Result<HttpResponse, MyError> {
+    if !clients.image_proxy.verify(&query.url, &query.sig) {
+        return Err(MyError::Forbidden(eyre!(
+            "Bad signature for the media {}",
+            query.url
+        )));
+    }
+    let range = request.headers().get(RANGE).and_then(|x| x.to_str().ok());
+    let media = clients
+        .image_proxy
+        .media(&query.url, range)
+        .await
+        .map_err(MyError::CannotFind)?;
+    let mut response = HttpResponse::build(
+        StatusCode::from_u16(media.status().as_u16()).unwrap_or(StatusCode::OK),
+    );
+    for header in MEDIA_HEADERS {
+        if let Some(value) = media.headers().get(*header).and_then(|x| x.to_str().ok()) {
+            response.append_header((*header, value.to_string()));
+        }
+    }
+    let body = stream::unfold(Some(media), |media| async move {
+        let mut media = media?;
+        match media.chunk().await {
+            Ok(Some(chunk)) => Some((Ok(chunk), Some(media))),
+            Ok(None) => None,
+            Err(e) => Some((Err(ErrorBadGateway(e)), None)),
+        }
+    });
+    Ok(response
+        .append_header((
+            "Cache-Control",
+            format!("private, max-age={}", IMAGE_MAX_AGE_S),
+        ))
+        .append_header(("Content-Security-Policy", "default-src 'none'"))
+        .append_header(("X-Content-Type-Options", "nosniff"))
+        .streaming(Box::pin(body)))
+}
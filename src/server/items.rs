@@ -1,23 +1,44 @@
 use std::time::SystemTime;
 
-use actix_web::{get, web, HttpResponse};
+use actix_web::{get, post, web, HttpResponse};
+use chrono::Utc;
 use color_eyre::eyre::eyre;
-use color_eyre::Report;
+use serde::Deserialize;
+use tokio::task::spawn_blocking;
 use tracing::instrument;
 
 use crate::server::templates;
-use crate::{clients::Clients, dto};
+use crate::{archive, clients::Clients, dto, highlight, readability};
 
-use super::{from_requests::user_id::UserIdPart, wrap_body, MyError};
+use super::{
+    from_requests::{
+        csrf::{CsrfChecked, CsrfToken},
+        user_id::UserIdPart,
+    },
+    wrap_body, MyError,
+};
 
-#[get("/items/partial/{id}")]
+/// Keeps a single note from filling the database
+const MAX_NOTE_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ItemFragmentQuery {
+    #[serde(default)]
+    expanded: bool,
+    /// The full article is on screen, see `load_full_content`
+    #[serde(default)]
+    full_content: bool,
+}
+
+#[post("/items/partial/{id}")]
 #[instrument(skip(clients))]
 pub async fn get_full_item_part(
     clients: web::Data<Clients>,
     id: web::Path<i64>,
     UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
 ) -> Result<HttpResponse, MyError> {
-    let item = dto::Item::fetch(*id, &clients.pool)
+    let mut item = dto::Item::fetch(&user_id, *id, &clients.pool)
         .await?
         .ok_or_else(|| MyError::Missing("Item".to_string()))?;
 
@@ -26,43 +47,427 @@ pub async fn get_full_item_part(
         .map_err(|error| MyError::Internal(eyre!("Could not get now time: {:?}", error)))?
         .as_secs() as i64;
 
-    sqlx::query!(
-        "INSERT OR IGNORE INTO user_item_reads (item_id, user_id, read_on) VALUES ($1, $2, $3)",
+    dto::UserItem::new(&user_id, item.id)
+        .mark_read(now, &clients.pool)
+        .await?;
+    item.is_read = true;
+    item_fragment(&clients, &user_id, &item, true, false).await
+}
+
+#[post("/items/{id}/toggle_read")]
+#[instrument(skip(clients))]
+pub async fn toggle_item_read(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    query: web::Query<ItemFragmentQuery>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let mut item = dto::Item::fetch(&user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    let user_item = dto::UserItem::new(&user_id, item.id);
+    if item.is_read {
+        user_item.mark_unread(&clients.pool).await?;
+    } else {
+        user_item
+            .mark_read(Utc::now().timestamp(), &clients.pool)
+            .await?;
+    }
+    item.is_read = !item.is_read;
+    item_fragment(&clients, &user_id, &item, query.expanded, false).await
+}
+
+#[post("/items/{id}/toggle_star")]
+#[instrument(skip(clients))]
+pub async fn toggle_item_star(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    query: web::Query<ItemFragmentQuery>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let mut item = dto::Item::fetch(&user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    let user_item = dto::UserItem::new(&user_id, item.id);
+    if item.is_starred {
+        user_item.unstar(&clients.pool).await?;
+    } else {
+        user_item.star(Utc::now().timestamp(), &clients.pool).await?;
+    }
+    item.is_starred = !item.is_starred;
+    item_fragment(&clients, &user_id, &item, query.expanded, false).await
+}
+
+/// Shows the article of the linked page, downloaded the first time someone asks for it
+#[post("/items/{id}/full_content")]
+#[instrument(skip(clients))]
+pub async fn load_full_content(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let item = dto::Item::fetch(&user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    if dto::Item::fetch_full_contents(item.id, &clients.pool)
+        .await?
+        .is_none()
+    {
+        let contents = readability::fetch_full_content(&item.link)
+            .await
+            .map_err(|e| MyError::BadParam("link".into(), format!("{:?}", e)))?
+            .ok_or_else(|| MyError::Missing("Article".to_string()))?;
+        dto::Item::update_full_contents(item.id, &contents, &clients.pool).await?;
+    }
+    item_fragment(&clients, &user_id, &item, true, true).await
+}
+
+/// Keeps a copy of the linked page with its images, for when the page is gone
+#[post("/items/{id}/archive")]
+#[instrument(skip(clients))]
+pub async fn archive_item(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let item = dto::Item::fetch(&user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    let html = archive::archive_page(&item.link)
+        .await
+        .map_err(|e| MyError::BadParam("link".into(), format!("{:?}", e)))?;
+    dto::ItemArchive::save(
+        &user_id,
         item.id,
-        user_id,
-        now
+        &html,
+        Utc::now().timestamp(),
+        &clients.pool,
     )
-    .execute(&clients.pool)
-    .await
-    .map_err(Report::from)
-    .map_err(MyError::Internal)?;
-    let subscription =
-        dto::UserSubscription::fetch(&user_id, item.subscription_id, &clients.pool).await?;
-    let index = templates::Item {
-        show_expanded: true,
-        subscription: &&subscription,
+    .await?;
+    item_fragment(&clients, &user_id, &item, true, false).await
+}
+
+/// The archive is sanitized on the way in, the policy keeps it from running anything or loading
+/// images from anywhere but us anyway
+#[get("/item/{id}/archive")]
+#[instrument(skip(clients))]
+pub async fn get_item_archive(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    UserIdPart(user_id): UserIdPart,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, MyError> {
+    let item = dto::Item::fetch(&user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    let archive = dto::ItemArchive::fetch(&user_id, item.id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Archive".to_string()))?;
+    let timezone = user_id.timezone(&clients.pool).await?;
+    // Archives get big, they are rewritten off the workers serving the requests
+    let (archive_html, image_proxy) = (archive.html.clone(), clients.image_proxy.clone());
+    let html = spawn_blocking(move || archive::proxy_images(&archive_html, &image_proxy))
+        .await
+        .map_err(|e| MyError::Internal(eyre!("Could not rewrite the archive: {:?}", e)))?;
+    let body = wrap_body(&csrf, templates::ItemArchive {
         item: &item,
-    };
+        archive: &archive,
+        html: &html,
+        timezone: &timezone,
+    });
     Ok(HttpResponse::Ok()
         .content_type("text/html")
-        .body(index.to_string()))
+        .append_header((
+            "Content-Security-Policy",
+            "script-src 'self'; object-src 'none'; img-src 'self' data:",
+        ))
+        .body(body))
 }
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ItemTagForm {
+    tag: String,
+}
+
+#[post("/items/{id}/tags")]
+#[instrument(skip(clients))]
+pub async fn add_item_tag(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    query: web::Query<ItemFragmentQuery>,
+    form: web::Form<ItemTagForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let item = dto::Item::fetch(&user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    let tag = dto::ItemTag::normalize(&form.tag)
+        .ok_or_else(|| MyError::BadParam("tag".into(), "The tag is required".into()))?;
+    dto::UserItem::new(&user_id, item.id)
+        .add_tag(&tag, Utc::now().timestamp(), &clients.pool)
+        .await?;
+    item_fragment(&clients, &user_id, &item, query.expanded, false).await
+}
+
+#[post("/items/{id}/tags/delete")]
+#[instrument(skip(clients))]
+pub async fn remove_item_tag(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    query: web::Query<ItemFragmentQuery>,
+    form: web::Form<ItemTagForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let item = dto::Item::fetch(&user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    dto::UserItem::new(&user_id, item.id)
+        .remove_tag(&form.tag, &clients.pool)
+        .await?;
+    item_fragment(&clients, &user_id, &item, query.expanded, false).await
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ItemNoteForm {
+    note: String,
+}
+
+#[post("/items/{id}/note")]
+#[instrument(skip(clients, form))]
+pub async fn save_item_note(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    query: web::Query<ItemFragmentQuery>,
+    form: web::Form<ItemNoteForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let item = dto::Item::fetch(&user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    if form.note.len() > MAX_NOTE_LENGTH {
+        return Err(MyError::BadParam(
+            "note".into(),
+            format!("At most {} bytes", MAX_NOTE_LENGTH),
+        ));
+    }
+    dto::ItemNote::save(
+        &user_id,
+        item.id,
+        &form.note,
+        Utc::now().timestamp(),
+        &clients.pool,
+    )
+    .await?;
+    item_fragment(&clients, &user_id, &item, query.expanded, false).await
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ItemHighlightForm {
+    quote: String,
+    start: usize,
+    end: usize,
+}
+
+/// The offsets are checked against the sanitized content, so highlights always mark what the
+/// reader selected
+#[post("/items/{id}/highlights")]
+#[instrument(skip(clients, form))]
+pub async fn add_item_highlight(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    query: web::Query<ItemFragmentQuery>,
+    form: web::Form<ItemHighlightForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let item = dto::Item::fetch(&user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    // Against what the user sees, which may be the full article
+    let (item, full_content) =
+        with_full_contents(&clients, &user_id, item, query.full_content).await?;
+    let content = item
+        .contents
+        .as_deref()
+        .or_else(|| item.description.as_deref())
+        .ok_or_else(|| MyError::Missing("Content".to_string()))?;
+    let profile = dto::SubscriptionSettings::fetch_sanitizer_profile(
+        &user_id,
+        item.subscription_id,
+        &clients.pool,
+    )
+    .await?;
+    let text = highlight::text_content(&clients.sanitizer.clean(content, profile));
+    let (start, end) = highlight::resolve_highlight(&text, &form.quote, form.start, form.end)
+        .ok_or_else(|| {
+            MyError::BadParam("quote".into(), "The quote is not in the item".into())
+        })?;
+    dto::ItemHighlight::insert(
+        &user_id,
+        item.id,
+        &form.quote,
+        start as i64,
+        end as i64,
+        full_content,
+        Utc::now().timestamp(),
+        &clients.pool,
+    )
+    .await?;
+    item_fragment(&clients, &user_id, &item, query.expanded, full_content).await
+}
+
+#[post("/items/{id}/highlights/{highlight_id}/delete")]
+#[instrument(skip(clients))]
+pub async fn delete_item_highlight(
+    clients: web::Data<Clients>,
+    path: web::Path<(i64, i64)>,
+    query: web::Query<ItemFragmentQuery>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let (id, highlight_id) = path.into_inner();
+    let item = dto::Item::fetch(&user_id, id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    if !dto::ItemHighlight::delete(&user_id, item.id, highlight_id, &clients.pool).await? {
+        return Err(MyError::Missing("Highlight".to_string()));
+    }
+    item_fragment(
+        &clients,
+        &user_id,
+        &item,
+        query.expanded,
+        query.full_content,
+    )
+    .await
+}
+
 #[get("/item/{id}")]
 #[instrument(skip(clients))]
 pub async fn get_full_item(
     clients: web::Data<Clients>,
     id: web::Path<i64>,
     UserIdPart(user_id): UserIdPart,
+    csrf: CsrfToken,
 ) -> Result<HttpResponse, MyError> {
-    let item = dto::Item::fetch(*id, &clients.pool)
+    let item = dto::Item::fetch(&user_id, *id, &clients.pool)
         .await?
         .ok_or_else(|| MyError::Missing("Item".to_string()))?;
+    let (item, full_content) = with_full_contents(&clients, &user_id, item, false).await?;
     let subscription =
         dto::UserSubscription::fetch(&user_id, item.subscription_id, &clients.pool).await?;
-    let body = wrap_body(templates::Item {
+    let timezone = user_id.timezone(&clients.pool).await?;
+    let annotations = Annotations::fetch(&clients, &user_id, item.id).await?;
+    let sanitizer_profile = dto::SubscriptionSettings::fetch_sanitizer_profile(
+        &user_id,
+        item.subscription_id,
+        &clients.pool,
+    )
+    .await?;
+    let body = wrap_body(&csrf, templates::Item {
         show_expanded: true,
         subscription: &&subscription,
+        tags: &annotations.tags,
+        note: annotations.note.as_ref(),
+        highlights: &annotations.highlights,
+        full_content,
+        archived: annotations.archived,
+        sanitizer: &clients.sanitizer,
+        sanitizer_profile,
         item: &item,
+        timezone: &timezone,
     });
     Ok(HttpResponse::Ok().content_type("text/html").body(body))
 }
+
+/// What the user added to an item, shown when the item is expanded
+struct Annotations {
+    tags: Vec<String>,
+    note: Option<dto::ItemNote>,
+    highlights: Vec<dto::ItemHighlight>,
+    archived: bool,
+}
+
+impl Annotations {
+    async fn fetch(
+        clients: &Clients,
+        user_id: &dto::UserId,
+        item_id: i64,
+    ) -> Result<Self, MyError> {
+        Ok(Self {
+            tags: dto::ItemTag::fetch_for_item(user_id, item_id, &clients.pool).await?,
+            note: dto::ItemNote::fetch(user_id, item_id, &clients.pool).await?,
+            highlights: dto::ItemHighlight::fetch_for_item(user_id, item_id, &clients.pool)
+                .await?,
+            archived: dto::ItemArchive::exists(user_id, item_id, &clients.pool).await?,
+        })
+    }
+}
+
+/// The article from the link takes the place of the contents of the feed for the users who want
+/// the full articles of the subscription, or when it was `loaded` by hand. Tells whether it did.
+async fn with_full_contents(
+    clients: &Clients,
+    user_id: &dto::UserId,
+    mut item: dto::Item,
+    loaded: bool,
+) -> Result<(dto::Item, bool), MyError> {
+    let wanted = loaded
+        || dto::SubscriptionSettings::fetch(user_id, item.subscription_id, &clients.pool)
+            .await?
+            .map(|settings| settings.fetch_full_content)
+            .unwrap_or(false);
+    if wanted {
+        if let Some(contents) = dto::Item::fetch_full_contents(item.id, &clients.pool).await? {
+            item.contents = Some(contents);
+            return Ok((item, true));
+        }
+    }
+    Ok((item, false))
+}
+
+/// The htmx fragment for a single article, used to swap the article in place
+async fn item_fragment(
+    clients: &Clients,
+    user_id: &dto::UserId,
+    item: &dto::Item,
+    show_expanded: bool,
+    full_content: bool,
+) -> Result<HttpResponse, MyError> {
+    let (item, full_content) =
+        with_full_contents(clients, user_id, item.clone(), full_content).await?;
+    let item = &item;
+    let subscription =
+        dto::UserSubscription::fetch(user_id, item.subscription_id, &clients.pool).await?;
+    let timezone = user_id.timezone(&clients.pool).await?;
+    let annotations = Annotations::fetch(clients, user_id, item.id).await?;
+    let sanitizer_profile = dto::SubscriptionSettings::fetch_sanitizer_profile(
+        user_id,
+        item.subscription_id,
+        &clients.pool,
+    )
+    .await?;
+    let index = templates::Item {
+        show_expanded,
+        subscription: &&subscription,
+        tags: &annotations.tags,
+        note: annotations.note.as_ref(),
+        highlights: &annotations.highlights,
+        full_content,
+        archived: annotations.archived,
+        sanitizer: &clients.sanitizer,
+        sanitizer_profile,
+        item,
+        timezone: &timezone,
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(index.to_string()))
+}
@@ -5,17 +5,32 @@ use std::{
 
 use crate::{
     clients::Clients,
-    dto::User,
-    session::{Session, SessionMap},
+    dto::{Invite, PasswordCheck, User},
+    login_throttle::LoginThrottle,
+    session::{Sessions, SSID},
 };
-use actix_web::{cookie::Cookie, get, post, web, HttpResponse};
-use color_eyre::eyre::eyre;
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    get,
+    http::header::USER_AGENT,
+    post,
+    rt::time,
+    web, HttpRequest, HttpResponse,
+};
+use chrono::Utc;
+use color_eyre::{eyre::eyre, Report};
 use serde::Deserialize;
-use tracing::instrument;
+use tracing::{instrument, warn};
 
-use uuid::Uuid;
+use super::{
+    from_requests::{
+        client_ip::ClientIp,
+        csrf::{CsrfChecked, CsrfToken},
+    },
+    templates, wrap_body, MyError,
+};
 
-use super::{templates, MyError};
+pub const MIN_PASSWORD_LENGTH: usize = 8;
 
 #[derive(Clone, Deserialize)]
 pub struct LoginForm {
@@ -33,46 +48,213 @@ impl std::fmt::Debug for LoginForm {
 }
 
 #[get("/login")]
-#[instrument]
-pub async fn page_login() -> Result<HttpResponse, MyError> {
-    Ok(HttpResponse::Ok().content_type("text/html").body(
-        templates::Home {
-            body: &templates::Login {}.to_string(),
-        }
-        .to_string(),
-    ))
+#[instrument(skip(clients))]
+pub async fn page_login(clients: web::Data<Clients>) -> Result<HttpResponse, MyError> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(wrap_body(&CsrfToken::default(), templates::Login {
+            oidc: clients.settings.oidc.is_some(),
+        })))
 }
 #[post("/login")]
-#[instrument(skip())]
+#[instrument(skip(sessions, login_throttle, clients, request))]
 pub async fn login_post(
-    session_maps: web::Data<SessionMap>,
+    sessions: web::Data<Sessions>,
+    login_throttle: web::Data<LoginThrottle>,
     login_form: web::Form<LoginForm>,
     clients: web::Data<Clients>,
+    request: HttpRequest,
+    ClientIp(ip): ClientIp,
 ) -> Result<HttpResponse, MyError> {
-    let mut session_map = session_maps.lock().await;
-    let user = User::fetch(&clients.pool, &login_form.username)
-        .await
-        .map_err(MyError::CannotFind)?;
+    let user = authenticate(
+        &clients,
+        &login_throttle,
+        &login_form.username,
+        &login_form.password,
+        &ip,
+    )
+    .await?;
+
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|x| x.to_str().ok());
+    let ssid = sessions
+        .create(&user, user_agent, Some(&ip), &clients.pool)
+        .await?;
+    Ok(HttpResponse::Found()
+        .cookie(session_cookie(&clients, ssid))
+        .append_header(("Location", "/"))
+        .finish())
+}
 
-    if !user.passwords_match(&login_form.password) {
-        return Err(MyError::CannotFind(eyre!("Cannot find")));
+/// Checks a username and password, throttled per ip and username. The error is the same whether
+/// or not the user exists.
+pub(super) async fn authenticate(
+    clients: &Clients,
+    login_throttle: &LoginThrottle,
+    username: &str,
+    password: &str,
+    ip: &str,
+) -> Result<User, MyError> {
+    let username_key = LoginThrottle::username_key(username);
+    let throttle_keys = [LoginThrottle::ip_key(ip), username_key.clone()];
+    if let Some(locked_for) = login_throttle.locked_for(&throttle_keys) {
+        warn!(%username, %ip, "Login attempt while locked out");
+        return Err(MyError::TooManyAttempts(locked_for.as_secs() + 1));
     }
 
-    let ssid = Uuid::new_v4().to_string();
-    session_map.insert(ssid.clone(), Session::new((*user).clone()));
+    let user = User::fetch(&clients.pool, username).await.ok();
+    let password_check = match &user {
+        Some(user) => user.verify_password(password),
+        None => {
+            User::simulate_password_check(password);
+            PasswordCheck::Invalid
+        }
+    };
+    let user = match user {
+        Some(user) if !user.disabled() && password_check.is_valid() => user,
+        _ => {
+            let delay = login_throttle.record_failure(&throttle_keys);
+            warn!(%username, %ip, "Failed login attempt");
+            time::sleep(delay).await;
+            return Err(MyError::LoginFailed);
+        }
+    };
+    login_throttle.record_success(&username_key);
+    if password_check == PasswordCheck::NeedsRehash {
+        if let Err(e) = User::set_password(&user, password, &clients.pool).await {
+            warn!("Could not upgrade password hash for {}: {:?}", user.username(), e);
+        }
+    }
+    Ok(user)
+}
+
+/// The cookie holding the token of a freshly created session
+pub fn session_cookie(clients: &Clients, ssid: String) -> Cookie<'static> {
+    Cookie::build(SSID, ssid)
+        .path("/")
+        .secure(clients.settings.secure)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .expires(Some(
+            SystemTime::now()
+                .add(Duration::from_secs(clients.settings.time_of_cookies_s))
+                .into(),
+        ))
+        .finish()
+}
+
+#[post("/logout")]
+#[instrument(skip(sessions, clients, request))]
+pub async fn logout_post(
+    sessions: web::Data<Sessions>,
+    clients: web::Data<Clients>,
+    request: HttpRequest,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    if let Some(ssid) = request.cookie(SSID) {
+        sessions.remove(ssid.value(), &clients.pool).await?;
+    }
     Ok(HttpResponse::Found()
-        .cookie(
-            Cookie::build("ssid", ssid)
-                .path("/")
-                .secure(clients.settings.secure)
-                .http_only(true)
-                .expires(Some(
-                    SystemTime::now()
-                        .add(Duration::from_secs(clients.settings.time_of_cookies_s))
-                        .into(),
-                ))
-                .finish(),
-        )
-        .append_header(("Location", "/"))
+        .del_cookie(&Cookie::build(SSID, "").path("/").finish())
+        .append_header(("Location", "/login"))
+        .finish())
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SignupForm {
+    username: String,
+    password: String,
+    #[serde(default)]
+    invite: String,
+}
+
+impl std::fmt::Debug for SignupForm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SignupForm")
+            .field("username", &self.username)
+            .field("password", &"redacted")
+            .field("invite", &self.invite)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignupQuery {
+    #[serde(default)]
+    invite: String,
+}
+
+#[get("/signup")]
+#[instrument(skip(clients))]
+pub async fn page_signup(
+    clients: web::Data<Clients>,
+    query: web::Query<SignupQuery>,
+) -> Result<HttpResponse, MyError> {
+    Ok(HttpResponse::Ok().content_type("text/html").body(wrap_body(
+        &CsrfToken::default(),
+        templates::Signup {
+            invite: &query.invite,
+            invite_required: !clients.settings.allow_signup,
+        },
+    )))
+}
+
+#[post("/signup")]
+#[instrument(skip(clients, login_throttle))]
+pub async fn signup_post(
+    signup_form: web::Form<SignupForm>,
+    clients: web::Data<Clients>,
+    login_throttle: web::Data<LoginThrottle>,
+    ClientIp(ip): ClientIp,
+) -> Result<HttpResponse, MyError> {
+    // Every attempt counts, the successful ones too, so accounts and invites can not be
+    // churned through from one address
+    let throttle_keys = [LoginThrottle::signup_key(&ip)];
+    if let Some(locked_for) = login_throttle.locked_for(&throttle_keys) {
+        warn!(%ip, "Signup attempt while locked out");
+        return Err(MyError::TooManyAttempts(locked_for.as_secs() + 1));
+    }
+    time::sleep(login_throttle.record_failure(&throttle_keys)).await;
+
+    let username = signup_form.username.trim();
+    let invite = signup_form.invite.trim();
+    if username.is_empty() {
+        return Err(MyError::BadParam(
+            "username".into(),
+            "Username is required".into(),
+        ));
+    }
+    if signup_form.password.len() < MIN_PASSWORD_LENGTH {
+        return Err(MyError::BadParam(
+            "password".into(),
+            format!("Needs at least {} characters", MIN_PASSWORD_LENGTH),
+        ));
+    }
+    if !clients.settings.allow_signup && invite.is_empty() {
+        return Err(MyError::Forbidden(eyre!("Signup without an invite")));
+    }
+    if User::fetch(&clients.pool, username).await.is_ok() {
+        return Err(MyError::BadParam(
+            "username".into(),
+            "Username is taken".into(),
+        ));
+    }
+
+    let mut transaction = clients.pool.begin().await.map_err(Report::from)?;
+    let user_id = User::insert(username, &signup_form.password, false, &mut transaction).await?;
+    if !invite.is_empty()
+        && !Invite::redeem(invite, &user_id, Utc::now().timestamp(), &mut transaction).await?
+    {
+        return Err(MyError::BadParam(
+            "invite".into(),
+            "Invite is invalid or already used".into(),
+        ));
+    }
+    transaction.commit().await.map_err(Report::from)?;
+
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/login"))
         .finish())
 }
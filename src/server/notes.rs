@@ -0,0 +1,191 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap},
+};
+
+use actix_web::{get, web, HttpResponse};
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
+use tracing::instrument;
+
+use crate::{
+    clients::Clients,
+    dto::{self, ItemHighlight, ItemNote},
+};
+
+use super::{
+    from_requests::{csrf::CsrfToken, user_id::UserIdPart},
+    templates, wrap_body, MyError,
+};
+
+/// An item with what the user wrote and marked on it
+pub struct AnnotatedItem {
+    pub item: dto::Item,
+    pub subscription_title: String,
+    pub note: Option<ItemNote>,
+    pub highlights: Vec<ItemHighlight>,
+}
+
+/// Every item with a note or a highlight, newest first
+async fn annotated_items(
+    clients: &Clients,
+    user_id: &dto::UserId,
+) -> Result<Vec<AnnotatedItem>, MyError> {
+    let mut notes: HashMap<i64, ItemNote> = ItemNote::fetch_all(user_id, &clients.pool)
+        .await?
+        .into_iter()
+        .map(|note| (note.item_id, note))
+        .collect();
+    let mut highlights: HashMap<i64, Vec<ItemHighlight>> = HashMap::new();
+    for highlight in ItemHighlight::fetch_all(user_id, &clients.pool).await? {
+        highlights
+            .entry(highlight.item_id)
+            .or_default()
+            .push(highlight);
+    }
+    let item_ids: BTreeSet<i64> = notes.keys().chain(highlights.keys()).copied().collect();
+    let subscriptions: HashMap<i64, String> =
+        dto::UserSubscription::fetch_all(user_id, &clients.pool)
+            .await?
+            .into_iter()
+            .map(|x| (x.id, x.title))
+            .collect();
+    let mut annotated = Vec::new();
+    for item_id in item_ids {
+        // Items of feeds the user left are not shown any more
+        let item = match dto::Item::fetch(user_id, item_id, &clients.pool).await? {
+            Some(item) => item,
+            None => continue,
+        };
+        annotated.push(AnnotatedItem {
+            subscription_title: subscriptions
+                .get(&item.subscription_id)
+                .cloned()
+                .unwrap_or_default(),
+            note: notes.remove(&item_id),
+            highlights: highlights.remove(&item_id).unwrap_or_default(),
+            item,
+        });
+    }
+    annotated.sort_by_key(|x| Reverse(x.item.pub_date));
+    Ok(annotated)
+}
+
+#[get("/notes")]
+#[instrument(skip(clients))]
+pub async fn page_notes(
+    clients: web::Data<Clients>,
+    UserIdPart(user_id): UserIdPart,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, MyError> {
+    let annotated = annotated_items(&clients, &user_id).await?;
+    let timezone = user_id.timezone(&clients.pool).await?;
+    let body = wrap_body(
+        &csrf,
+        templates::Notes {
+            annotated: annotated.iter().collect(),
+            timezone: &timezone,
+        },
+    );
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[get("/notes/export.md")]
+#[instrument(skip(clients))]
+pub async fn export_notes(
+    clients: web::Data<Clients>,
+    UserIdPart(user_id): UserIdPart,
+) -> Result<HttpResponse, MyError> {
+    let annotated = annotated_items(&clients, &user_id).await?;
+    let timezone = user_id.timezone(&clients.pool).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/markdown; charset=utf-8")
+        .append_header(("Content-Disposition", r#"attachment; filename="notes.md""#))
+        .body(notes_markdown(&annotated, &timezone)))
+}
+
+fn notes_markdown(annotated: &[AnnotatedItem], timezone: &Tz) -> String {
+    let mut markdown = String::from("# Notes\n");
+    for entry in annotated {
+        markdown.push_str(&format!(
+            "\n## [{}]({})\n\n{}, {}\n",
+            markdown_escape(&entry.item.title),
+            entry.item.link.replace(')', "%29"),
+            markdown_escape(&entry.subscription_title),
+            timezone
+                .timestamp(entry.item.pub_date, 0)
+                .format("%Y-%m-%d")
+        ));
+        for highlight in entry.highlights.iter() {
+            markdown.push('\n');
+            for line in highlight.quote.lines() {
+                markdown.push_str(&format!("> {}\n", line));
+            }
+        }
+        if let Some(note) = &entry.note {
+            markdown.push_str(&format!("\n{}\n", note.note.trim()));
+        }
+    }
+    markdown.push_str(&format!(
+        "\n_Exported {}_\n",
+        Utc::now()
+            .with_timezone(timezone)
+            .format("%Y-%m-%d %H:%M %Z")
+    ));
+    markdown
+}
+
+/// Keeps titles from being read as markdown
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]#<>".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notes_markdown() {
+        let annotated = vec![AnnotatedItem {
+            item: dto::Item {
+                id: 1,
+                subscription_id: 2,
+                title: "Rust *really* fast".to_string(),
+                link: "https://example.com/rust_(lang)".to_string(),
+                pub_date: 1_626_912_000,
+                author: None,
+                description: None,
+                contents: None,
+                comments: None,
+                is_read: true,
+                is_starred: false,
+            },
+            subscription_title: "Blog".to_string(),
+            note: Some(ItemNote {
+                item_id: 1,
+                note: "Read again\n".to_string(),
+                updated_on: 0,
+            }),
+            highlights: vec![ItemHighlight {
+                id: 1,
+                item_id: 1,
+                quote: "first\nsecond".to_string(),
+                start_offset: 0,
+                end_offset: 12,
+                created_on: 0,
+                full_content: false,
+            }],
+        }];
+        let markdown = notes_markdown(&annotated, &chrono_tz::UTC);
+        assert!(markdown.starts_with(
+            "# Notes\n\n## [Rust \\*really\\* fast](https://example.com/rust_(lang%29)\n\nBlog, 2021-07-22\n\n> first\n> second\n\nRead again\n"
+        ));
+    }
+}
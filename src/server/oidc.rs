@@ -0,0 +1,396 @@
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    get,
+    http::header::USER_AGENT,
+    web, HttpRequest, HttpResponse,
+};
+use chrono::Utc;
+use color_eyre::{eyre::eyre, Report};
+use serde::Deserialize;
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use crate::{
+    clients::Clients,
+    dto::{User, UserId, UserIdentity},
+    oidc::{Identity, Oidc},
+    session::Sessions,
+};
+
+use super::{
+    from_requests::{client_ip::ClientIp, session::SessionPart},
+    login::session_cookie,
+    MyError,
+};
+
+/// Ties the callback to the browser that started the login
+const OIDC_STATE: &str = "oidc_state";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CallbackQuery {
+    code: String,
+    state: String,
+}
+
+/// Sends the user to the provider, when already logged in the identity gets linked on the callback
+#[get("/login/oidc")]
+#[instrument(skip(oidc))]
+pub async fn oidc_login(oidc: web::Data<Option<Oidc>>) -> Result<HttpResponse, MyError> {
+    let oidc = oidc
+        .get_ref()
+        .as_ref()
+        .ok_or_else(|| MyError::Missing("OpenID Connect".to_string()))?;
+    let (url, state) = oidc.start_login();
+    Ok(HttpResponse::Found()
+        .cookie(
+            Cookie::build(OIDC_STATE, state)
+                .path("/login/oidc")
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .finish(),
+        )
+        .append_header(("Location", url))
+        .finish())
+}
+
+#[get("/login/oidc/callback")]
+#[instrument(skip(oidc, sessions, clients, request, session))]
+pub async fn oidc_callback(
+    oidc: web::Data<Option<Oidc>>,
+    sessions: web::Data<Sessions>,
+    clients: web::Data<Clients>,
+    query: web::Query<CallbackQuery>,
+    request: HttpRequest,
+    ClientIp(ip): ClientIp,
+    session: Option<SessionPart>,
+) -> Result<HttpResponse, MyError> {
+    let oidc = oidc
+        .get_ref()
+        .as_ref()
+        .ok_or_else(|| MyError::Missing("OpenID Connect".to_string()))?;
+    match request.cookie(OIDC_STATE) {
+        Some(state) if state.value() == query.state => (),
+        _ => {
+            return Err(MyError::Forbidden(eyre!(
+                "Login state does not match the browser"
+            )))
+        }
+    }
+    let identity = oidc
+        .finish_login(&query.state, &query.code)
+        .await
+        .map_err(MyError::Forbidden)?;
+    let linked_to = session.map(|SessionPart(session)| session.user_id);
+    let user_id = identity_user(&clients, &identity, linked_to).await?;
+
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|x| x.to_str().ok());
+    let ssid = sessions
+        .create(&user_id, user_agent, Some(&ip), &clients.pool)
+        .await?;
+    Ok(HttpResponse::Found()
+        .cookie(session_cookie(&clients, ssid))
+        .del_cookie(&Cookie::build(OIDC_STATE, "").path("/login/oidc").finish())
+        .append_header(("Location", "/"))
+        .finish())
+}
+
+/// The user the identity belongs to. An unknown identity is linked to the logged in user, or
+/// gets a new user when auto provisioning is on.
+async fn identity_user(
+    clients: &Clients,
+    identity: &Identity,
+    logged_in: Option<UserId>,
+) -> Result<UserId, MyError> {
+    let now = Utc::now().timestamp();
+    if let Some(linked) =
+        UserIdentity::fetch(&identity.issuer, &identity.subject, &clients.pool).await?
+    {
+        let user = User::fetch_by_id(&clients.pool, &linked.user_id).await?;
+        if user.disabled() {
+            return Err(MyError::Forbidden(eyre!("User {} is disabled", user.username())));
+        }
+        return Ok(linked.user_id);
+    }
+    if let Some(user_id) = logged_in {
+        info!(?user_id, issuer = %identity.issuer, "Linking identity");
+        UserIdentity::insert(&identity.issuer, &identity.subject, &user_id, now, &clients.pool)
+            .await?;
+        return Ok(user_id);
+    }
+    let auto_provision = clients
+        .settings
+        .oidc
+        .as_ref()
+        .map(|oidc| oidc.auto_provision)
+        .unwrap_or(false);
+    if !auto_provision {
+        return Err(MyError::Forbidden(eyre!(
+            "No user is linked to {} at {}",
+            identity.subject,
+            identity.issuer
+        )));
+    }
+    let username = identity
+        .username
+        .clone()
+        .unwrap_or_else(|| identity.subject.clone());
+    if User::fetch(&clients.pool, &username).await.is_ok() {
+        return Err(MyError::BadParam(
+            "username".into(),
+            format!("{} is taken, log in and link the account instead", username),
+        ));
+    }
+    info!(%username, issuer = %identity.issuer, "Creating user for identity");
+    let mut transaction = clients.pool.begin().await.map_err(Report::from)?;
+    // Nobody knows the password, the user logs in through the provider
+    let user_id = User::insert(
+        &username,
+        &Uuid::new_v4().to_string(),
+        false,
+        &mut transaction,
+    )
+    .await?;
+    UserIdentity::insert(
+        &identity.issuer,
+        &identity.subject,
+        &user_id,
+        now,
+        &mut transaction,
+    )
+    .await?;
+    transaction.commit().await.map_err(Report::from)?;
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use actix_web::{dev::ServiceResponse, http::StatusCode, test, App, HttpServer};
+    use openidconnect::{
+        core::{
+            CoreIdToken, CoreIdTokenClaims, CoreIdTokenFields, CoreJsonWebKeySet,
+            CoreJwsSigningAlgorithm, CoreProviderMetadata, CoreResponseType,
+            CoreRsaPrivateSigningKey, CoreSubjectIdentifierType, CoreTokenResponse, CoreTokenType,
+        },
+        AccessToken, Audience, AuthUrl, EmptyAdditionalClaims, EmptyAdditionalProviderMetadata,
+        EmptyExtraTokenFields, EndUserUsername, IssuerUrl, JsonWebKeyId, JsonWebKeySetUrl, Nonce,
+        PrivateSigningKey, ResponseTypes, StandardClaims, SubjectIdentifier, TokenUrl,
+    };
+    use url::Url;
+
+    use super::*;
+    use crate::{
+        session::SSID,
+        settings::{OidcSettings, Settings},
+    };
+
+    const CLIENT_ID: &str = "rss";
+    const SUBJECT: &str = "248289761001";
+    /// Signs the id tokens of the provider
+    const PROVIDER_KEY: &str = include_str!("../../test/artifacts/oidc_key.pem");
+
+    /// A provider on a local port. `replay_mocker` can not set the json content type the client
+    /// insists on, nor sign the nonce of each login.
+    struct Provider {
+        issuer: String,
+    }
+
+    fn signing_key() -> CoreRsaPrivateSigningKey {
+        CoreRsaPrivateSigningKey::from_pem(
+            PROVIDER_KEY,
+            Some(JsonWebKeyId::new("test-key".to_string())),
+        )
+        .unwrap()
+    }
+
+    async fn discovery(provider: web::Data<Provider>) -> HttpResponse {
+        let issuer = &provider.issuer;
+        let metadata = CoreProviderMetadata::new(
+            IssuerUrl::new(issuer.clone()).unwrap(),
+            AuthUrl::new(format!("{}/authorize", issuer)).unwrap(),
+            JsonWebKeySetUrl::new(format!("{}/jwks", issuer)).unwrap(),
+            vec![ResponseTypes::new(vec![CoreResponseType::Code])],
+            vec![CoreSubjectIdentifierType::Public],
+            vec![CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256],
+            EmptyAdditionalProviderMetadata {},
+        )
+        .set_token_endpoint(Some(TokenUrl::new(format!("{}/token", issuer)).unwrap()));
+        HttpResponse::Ok().json(metadata)
+    }
+
+    async fn jwks() -> HttpResponse {
+        HttpResponse::Ok().json(CoreJsonWebKeySet::new(vec![
+            signing_key().as_verification_key()
+        ]))
+    }
+
+    #[derive(Deserialize)]
+    struct TokenRequest {
+        code: String,
+    }
+
+    /// The code is the nonce that gets signed, so the tests choose what comes back
+    async fn token(provider: web::Data<Provider>, form: web::Form<TokenRequest>) -> HttpResponse {
+        let now = Utc::now();
+        let claims = CoreIdTokenClaims::new(
+            IssuerUrl::new(provider.issuer.clone()).unwrap(),
+            vec![Audience::new(CLIENT_ID.to_string())],
+            now + chrono::Duration::minutes(5),
+            now,
+            StandardClaims::new(SubjectIdentifier::new(SUBJECT.to_string()))
+                .set_preferred_username(Some(EndUserUsername::new("jane".to_string()))),
+            EmptyAdditionalClaims {},
+        )
+        .set_nonce(Some(Nonce::new(form.code.clone())));
+        let id_token = CoreIdToken::new(
+            claims,
+            &signing_key(),
+            CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+            None,
+            None,
+        )
+        .unwrap();
+        HttpResponse::Ok().json(CoreTokenResponse::new(
+            AccessToken::new("access".to_string()),
+            CoreTokenType::Bearer,
+            CoreIdTokenFields::new(Some(id_token), EmptyExtraTokenFields {}),
+        ))
+    }
+
+    /// Starts the provider and discovers it, returns the issuer
+    async fn setup() -> (Clients, Oidc, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let provider = web::Data::new(Provider {
+            issuer: issuer.clone(),
+        });
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(provider.clone())
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(discovery),
+                )
+                .route("/jwks", web::get().to(jwks))
+                .route("/token", web::post().to(token))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        actix_web::rt::spawn(server);
+
+        let mut settings = Settings::for_tests();
+        settings.oidc = Some(OidcSettings {
+            issuer_url: issuer.clone(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            redirect_url: "http://localhost/login/oidc/callback".to_string(),
+            auto_provision: true,
+        });
+        let oidc = Oidc::discover(settings.oidc.as_ref().unwrap())
+            .await
+            .unwrap();
+        let clients = Clients::in_memory(settings).await.unwrap();
+        (clients, oidc, issuer)
+    }
+
+    fn nonce(url: &str) -> String {
+        Url::parse(url)
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "nonce")
+            .unwrap()
+            .1
+            .to_string()
+    }
+
+    async fn callback(
+        clients: &Clients,
+        oidc: &Oidc,
+        cookie_state: &str,
+        state: &str,
+        code: &str,
+    ) -> ServiceResponse {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Some(oidc.clone())))
+                .app_data(web::Data::new(Sessions::new(&clients.settings)))
+                .app_data(web::Data::new(clients.clone()))
+                .service(oidc_callback),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri(&format!(
+                "/login/oidc/callback?code={}&state={}",
+                code, state
+            ))
+            .cookie(Cookie::new(OIDC_STATE, cookie_state.to_string()))
+            .to_request();
+        test::call_service(&app, request).await
+    }
+
+    async fn linked_user(clients: &Clients, issuer: &str) -> Option<String> {
+        let linked = UserIdentity::fetch(issuer, SUBJECT, &clients.pool)
+            .await
+            .unwrap()?;
+        let user = User::fetch_by_id(&clients.pool, &linked.user_id)
+            .await
+            .unwrap();
+        Some(user.username().to_string())
+    }
+
+    #[actix_web::rt::test]
+    async fn test_oidc_login() {
+        let (clients, oidc, issuer) = setup().await;
+        let (url, state) = oidc.start_login();
+        assert!(url.starts_with(&format!("{}/authorize?", issuer)));
+
+        let response = callback(&clients, &oidc, &state, &state, &nonce(&url)).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert!(response
+            .response()
+            .cookies()
+            .any(|cookie| cookie.name() == SSID && !cookie.value().is_empty()));
+        assert_eq!(
+            linked_user(&clients, &issuer).await.as_deref(),
+            Some("jane")
+        );
+
+        // The state is gone once used
+        let response = callback(&clients, &oidc, &state, &state, &nonce(&url)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::rt::test]
+    async fn test_oidc_state_mismatch() {
+        let (clients, oidc, issuer) = setup().await;
+        let (url, state) = oidc.start_login();
+
+        let response = callback(&clients, &oidc, "other-browser", &state, &nonce(&url)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(linked_user(&clients, &issuer).await.is_none());
+
+        let response = callback(&clients, &oidc, "unknown", "unknown", &nonce(&url)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(linked_user(&clients, &issuer).await.is_none());
+
+        // The login is still open for the browser that started it
+        let response = callback(&clients, &oidc, &state, &state, &nonce(&url)).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+    }
+
+    #[actix_web::rt::test]
+    async fn test_oidc_nonce_mismatch() {
+        let (clients, oidc, issuer) = setup().await;
+        let (_, state) = oidc.start_login();
+
+        let response = callback(&clients, &oidc, &state, &state, "replayed-nonce").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(linked_user(&clients, &issuer).await.is_none());
+    }
+}
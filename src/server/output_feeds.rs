@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use atom_syndication as atom;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    clients::Clients,
+    dto::{self, FeedFormat, OutputFeed, OutputFeedKind},
+};
+
+use super::{
+    from_requests::{
+        csrf::{CsrfChecked, CsrfToken},
+        user_id::UserIdPart,
+    },
+    templates, wrap_body, MyError,
+};
+
+/// How many of the newest items a generated feed holds
+const FEED_SIZE: i64 = 50;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OutputFeedForm {
+    title: String,
+    kind: OutputFeedKind,
+    #[serde(default)]
+    value: String,
+    format: FeedFormat,
+}
+
+/// The generated feed, the token in the url is all the authentication there is
+#[get("/feeds/{token}")]
+#[instrument(skip(clients, token, request))]
+pub async fn output_feed(
+    clients: web::Data<Clients>,
+    token: web::Path<String>,
+    request: HttpRequest,
+) -> Result<HttpResponse, MyError> {
+    let feed = OutputFeed::fetch_by_token(&token, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Feed".to_string()))?;
+    let items = dto::Item::fetch_stream(
+        &feed.user_id,
+        &feed.item_stream(),
+        FEED_SIZE,
+        0,
+        &clients.pool,
+    )
+    .await?;
+    let subscriptions: HashMap<_, _> =
+        dto::UserSubscription::fetch_all(&feed.user_id, &clients.pool)
+            .await?
+            .into_iter()
+            .map(|x| (x.id, x))
+            .collect();
+    let connection_info = request.connection_info();
+    let self_link = format!(
+        "{}://{}{}",
+        connection_info.scheme(),
+        connection_info.host(),
+        request.path()
+    );
+    let (content_type, body) = match feed.format {
+        FeedFormat::Rss => ("application/rss+xml", rss_feed(&feed, &items, &self_link)?),
+        FeedFormat::Atom => (
+            "application/atom+xml",
+            atom_feed(&feed, &items, &subscriptions, &self_link)?,
+        ),
+    };
+    Ok(HttpResponse::Ok().content_type(content_type).body(body))
+}
+
+fn rss_feed(feed: &OutputFeed, items: &[dto::Item], self_link: &str) -> Result<Vec<u8>, MyError> {
+    let items = items
+        .iter()
+        .map(|item| rss::Item {
+            title: Some(item.title.clone()),
+            link: Some(item.link.clone()),
+            description: item.description.clone(),
+            author: item.author.clone(),
+            comments: item.comments.clone(),
+            content: item.contents.clone(),
+            pub_date: Some(timestamp(item.pub_date).to_rfc2822()),
+            guid: Some(rss::Guid {
+                value: item.link.clone(),
+                permalink: true,
+            }),
+            ..Default::default()
+        })
+        .collect();
+    let channel = rss::Channel {
+        title: feed.title.clone(),
+        link: self_link.to_string(),
+        description: feed.title.clone(),
+        items,
+        ..Default::default()
+    };
+    channel
+        .write_to(Vec::new())
+        .map_err(|e| MyError::Internal(eyre!("Could not write rss: {:?}", e)))
+}
+
+fn atom_feed(
+    feed: &OutputFeed,
+    items: &[dto::Item],
+    subscriptions: &HashMap<i64, dto::UserSubscription>,
+    self_link: &str,
+) -> Result<Vec<u8>, MyError> {
+    let entries = items
+        .iter()
+        .map(|item| atom::Entry {
+            title: item.title.clone().into(),
+            id: item.link.clone(),
+            updated: timestamp(item.pub_date),
+            published: Some(timestamp(item.pub_date)),
+            authors: item
+                .author
+                .iter()
+                .map(|author| atom::Person {
+                    name: author.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+            links: vec![atom::Link {
+                href: item.link.clone(),
+                ..Default::default()
+            }],
+            summary: item.description.clone().map(Into::into),
+            content: item.contents.clone().map(|contents| atom::Content {
+                value: Some(contents),
+                content_type: Some("html".to_string()),
+                ..Default::default()
+            }),
+            source: subscriptions.get(&item.subscription_id).map(|subscription| {
+                atom::Source {
+                    title: subscription.title.clone().into(),
+                    id: subscription.rss_feed.clone(),
+                    ..Default::default()
+                }
+            }),
+            ..Default::default()
+        })
+        .collect();
+    let atom_feed = atom::Feed {
+        title: feed.title.clone().into(),
+        id: self_link.to_string(),
+        updated: items
+            .iter()
+            .map(|item| timestamp(item.pub_date))
+            .max()
+            .unwrap_or_else(|| timestamp(feed.created_on)),
+        links: vec![atom::Link {
+            href: self_link.to_string(),
+            rel: "self".to_string(),
+            ..Default::default()
+        }],
+        entries,
+        ..Default::default()
+    };
+    atom_feed
+        .write_to(Vec::new())
+        .map_err(|e| MyError::Internal(eyre!("Could not write atom: {:?}", e)))
+}
+
+fn timestamp(seconds: i64) -> DateTime<FixedOffset> {
+    let utc = FixedOffset::east(0);
+    utc.from_utc_datetime(&NaiveDateTime::from_timestamp(seconds, 0))
+}
+
+#[get("/account/feeds")]
+#[instrument(skip(clients, request))]
+pub async fn page_output_feeds(
+    clients: web::Data<Clients>,
+    request: HttpRequest,
+    UserIdPart(user_id): UserIdPart,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, MyError> {
+    let feeds = OutputFeed::fetch_all(&user_id, &clients.pool).await?;
+    let connection_info = request.connection_info();
+    let base_url = format!("{}://{}", connection_info.scheme(), connection_info.host());
+    let body = wrap_body(&csrf, templates::AccountOutputFeeds {
+        feeds: feeds.iter().collect(),
+        base_url: &base_url,
+    });
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[post("/account/feeds")]
+#[instrument(skip(clients))]
+pub async fn create_output_feed(
+    clients: web::Data<Clients>,
+    form: web::Form<OutputFeedForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let title = form.title.trim();
+    if title.is_empty() {
+        return Err(MyError::BadParam("title".into(), "Title is required".into()));
+    }
+    let value = form.value.trim();
+    let value = match form.kind {
+        OutputFeedKind::Starred => None,
+        _ if value.is_empty() => {
+            return Err(MyError::BadParam(
+                "value".into(),
+                "Needs the category, the search or the tag".into(),
+            ))
+        }
+        _ => Some(value),
+    };
+    OutputFeed::insert(
+        &user_id,
+        title,
+        form.kind,
+        value,
+        form.format,
+        Utc::now().timestamp(),
+        &clients.pool,
+    )
+    .await?;
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/account/feeds"))
+        .finish())
+}
+
+#[post("/account/feeds/{id}/delete")]
+#[instrument(skip(clients))]
+pub async fn delete_output_feed(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    if !OutputFeed::delete(&user_id, *id, &clients.pool).await? {
+        return Err(MyError::Missing("Feed".to_string()));
+    }
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/account/feeds"))
+        .finish())
+}
@@ -0,0 +1,159 @@
+use actix_web::{get, post, web, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    clients::Clients,
+    dto::{self, FilterRule, RuleAction, RuleField, RuleMatch, RuleScope},
+    rules,
+};
+
+use super::{
+    from_requests::{
+        csrf::{CsrfChecked, CsrfToken},
+        user_id::UserIdPart,
+    },
+    templates, wrap_body, MyError,
+};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RuleForm {
+    field: RuleField,
+    match_kind: RuleMatch,
+    pattern: String,
+    scope: RuleScope,
+    #[serde(default)]
+    category: String,
+    #[serde(default)]
+    subscription_id: String,
+    action: RuleAction,
+    #[serde(default)]
+    tag: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RulesQuery {
+    applied: Option<usize>,
+}
+
+#[get("/rules")]
+#[instrument(skip(clients))]
+pub async fn page_rules(
+    clients: web::Data<Clients>,
+    query: web::Query<RulesQuery>,
+    UserIdPart(user_id): UserIdPart,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, MyError> {
+    let rules = FilterRule::fetch_all(&user_id, &clients.pool).await?;
+    let subscriptions = dto::UserSubscription::fetch_all(&user_id, &clients.pool).await?;
+    let body = wrap_body(
+        &csrf,
+        templates::Rules {
+            rules: rules.iter().collect(),
+            subscriptions: subscriptions.iter().collect(),
+            applied: query.applied,
+        },
+    );
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[post("/rules")]
+#[instrument(skip(clients))]
+pub async fn create_rule(
+    clients: web::Data<Clients>,
+    form: web::Form<RuleForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let pattern = form.pattern.trim();
+    if pattern.is_empty() {
+        return Err(MyError::BadParam(
+            "pattern".into(),
+            "Pattern is required".into(),
+        ));
+    }
+    if form.match_kind == RuleMatch::Regex {
+        rules::compile_regex(pattern)
+            .map_err(|e| MyError::BadParam("pattern".into(), e.to_string()))?;
+    }
+    let scope_value = match form.scope {
+        RuleScope::All => None,
+        RuleScope::Category => Some(required(&form.category, "category")?.to_string()),
+        RuleScope::Subscription => {
+            let subscription_id: i64 = form
+                .subscription_id
+                .parse()
+                .map_err(|_| MyError::BadParam("subscription_id".into(), "Pick a feed".into()))?;
+            // Only the feeds of the user can be used as a scope
+            dto::UserSubscription::fetch(&user_id, subscription_id, &clients.pool)
+                .await
+                .map_err(|_| MyError::Missing("Subscription".to_string()))?;
+            Some(subscription_id.to_string())
+        }
+    };
+    let action_value = match form.action {
+        RuleAction::Tag => Some(
+            dto::ItemTag::normalize(&form.tag)
+                .ok_or_else(|| MyError::BadParam("tag".into(), "The tag is required".into()))?,
+        ),
+        _ => None,
+    };
+    FilterRule::insert(
+        &user_id,
+        form.field,
+        form.match_kind,
+        pattern,
+        form.scope,
+        scope_value.as_deref(),
+        form.action,
+        action_value.as_deref(),
+        Utc::now().timestamp(),
+        &clients.pool,
+    )
+    .await?;
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/rules"))
+        .finish())
+}
+
+#[post("/rules/{id}/delete")]
+#[instrument(skip(clients))]
+pub async fn delete_rule(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    if !FilterRule::delete(&user_id, *id, &clients.pool).await? {
+        return Err(MyError::Missing("Rule".to_string()));
+    }
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/rules"))
+        .finish())
+}
+
+/// Runs the rules over the items already there, for rules added after the items came in
+#[post("/rules/apply")]
+#[instrument(skip(clients))]
+pub async fn apply_rules(
+    clients: web::Data<Clients>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let applied = rules::apply_to_existing_items(&user_id, &clients.pool).await?;
+    Ok(HttpResponse::Found()
+        .append_header(("Location", format!("/rules?applied={}", applied)))
+        .finish())
+}
+
+fn required<'a>(value: &'a str, name: &str) -> Result<&'a str, MyError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(MyError::BadParam(
+            name.to_string(),
+            format!("The {} is required", name),
+        ));
+    }
+    Ok(value)
+}
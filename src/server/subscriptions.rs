@@ -1,13 +1,23 @@
 use std::collections::HashMap;
 
-use crate::{clients::Clients, dto, server::MyError};
-use actix_web::{get, post, web, HttpResponse};
+use crate::{
+    clients::Clients,
+    dto, favicon,
+    poll::{self, Refreshes},
+    server::MyError,
+};
+use actix_web::{get, post, rt::spawn, web, HttpResponse};
+use color_eyre::eyre::eyre;
 use rss::Channel;
 use serde::Deserialize;
-use tracing::instrument;
+use tracing::{instrument, warn};
 
 use super::{
-    from_requests::{user_id::UserIdPart, user_preferences::UserPreferences},
+    from_requests::{
+        csrf::{CsrfChecked, CsrfToken},
+        user_id::UserIdPart,
+        user_preferences::UserPreferences,
+    },
     templates, wrap_body,
 };
 
@@ -15,13 +25,11 @@ use super::{
 #[instrument]
 pub async fn page_rss_subscription_form(
     UserIdPart(_user_id): UserIdPart,
+    csrf: CsrfToken,
 ) -> Result<HttpResponse, MyError> {
-    Ok(HttpResponse::Ok().content_type("text/html").body(
-        templates::Home {
-            body: &templates::Subscribe {}.to_string(),
-        }
-        .to_string(),
-    ))
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(wrap_body(&csrf, templates::Subscribe {})))
 }
 #[post("/rss/subscriptions")]
 #[instrument(skip(clients))]
@@ -29,30 +37,53 @@ pub async fn new_subscription(
     form: web::Form<SubscriptionForm>,
     UserIdPart(user_id): UserIdPart,
     clients: web::Data<Clients>,
+    _csrf: CsrfChecked,
 ) -> Result<HttpResponse, MyError> {
     let SubscriptionForm {
         category,
         title,
         url,
     } = form.into_inner();
+    let _user_subscription = subscribe(&clients, &user_id, &category, &title, &url).await?;
+
+    Ok(HttpResponse::Found()
+        .append_header(("Location", "/"))
+        .finish())
+}
 
-    let content = reqwest::get(url.clone())
+/// Checks that the url serves a feed before subscribing the user to it
+pub(super) async fn subscribe(
+    clients: &Clients,
+    user_id: &dto::UserId,
+    category: &str,
+    title: &str,
+    url: &str,
+) -> Result<Option<dto::UserSubscription>, MyError> {
+    let content = reqwest::get(url)
         .await
         .map_err(|e| MyError::BadParam("url".into(), format!("{:?}", e)))?
         .bytes()
         .await
         .map_err(|e| MyError::BadParam("url".into(), format!("{:?}", e)))?;
     let _channel = Channel::read_from(&content[..])
-        .map_err(|x| MyError::InvalidSubscription(url.clone(), x.to_string()))?;
-    let rss_feed = url.to_string();
-    let subscription = dto::Subscription::insert(&rss_feed, &clients.pool).await?;
-    let _user_subscription =
-        dto::UserSubscription::insert(&category, &title, &subscription, &user_id, &clients.pool)
+        .map_err(|x| MyError::InvalidSubscription(url.to_string(), x.to_string()))?;
+    let subscription = dto::Subscription::insert(url, &clients.pool).await?;
+    if dto::SubscriptionIcon::fetch(subscription.id, &clients.pool)
+        .await?
+        .is_none()
+    {
+        let pool = clients.pool.clone();
+        let subscription = subscription.clone();
+        spawn(async move {
+            if let Err(e) = favicon::refresh_icon(&subscription, &pool).await {
+                warn!("Could not save the icon of {}: {:?}", subscription.rss_feed, e);
+            }
+        });
+    }
+    let user_subscription =
+        dto::UserSubscription::insert(category, title, &subscription, user_id, &clients.pool)
             .await?;
-
-    Ok(HttpResponse::Found()
-        .append_header(("Location", "/"))
-        .finish())
+    Ok(user_subscription)
 }
 
 #[get("/")]
@@ -61,8 +92,10 @@ pub async fn page_all_subscriptions(
     clients: web::Data<Clients>,
     UserIdPart(user_id): UserIdPart,
     user_preference: UserPreferences,
+    csrf: CsrfToken,
 ) -> Result<HttpResponse, MyError> {
     let subscriptions = dto::UserSubscription::fetch_all(&user_id, &clients.pool).await?;
+    let timezone = user_id.timezone(&clients.pool).await?;
     let subscription_map: HashMap<_, _> = subscriptions.iter().map(|x| (x.id, x)).collect();
     let items = dto::Item::fetch_all_not_read(
         &user_id,
@@ -71,6 +104,20 @@ pub async fn page_all_subscriptions(
         &clients.pool,
     )
     .await?;
+    let tags = dto::TagCount::fetch_all(&user_id, &clients.pool).await?;
+    let mut item_tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for item_tag in dto::ItemTag::fetch_all(&user_id, &clients.pool).await? {
+        item_tags
+            .entry(item_tag.item_id)
+            .or_default()
+            .push(item_tag.tag);
+    }
+    let sanitizer_profiles: HashMap<i64, dto::SanitizerProfile> =
+        dto::SubscriptionSettings::fetch_all(&user_id, &clients.pool)
+            .await?
+            .into_iter()
+            .map(|x| (x.subscription_id, x.sanitizer_profile))
+            .collect();
     let subscriptions_read: HashMap<i64, String> = subscriptions
         .iter()
         .map(|subscription| {
@@ -84,7 +131,7 @@ pub async fn page_all_subscriptions(
             )
         })
         .collect();
-    let index = wrap_body(templates::AllSubscriptions {
+    let index = wrap_body(&csrf, templates::AllSubscriptions {
         latest_read: items
             .iter()
             .map(|x| x.pub_date as i64)
@@ -94,8 +141,13 @@ pub async fn page_all_subscriptions(
         subscription_map,
         subscriptions_read,
         items: items.iter().collect(),
+        tags: tags.iter().collect(),
+        item_tags,
         show_unreads: user_preference.show_unreads,
         sidebar_collapsed: user_preference.sidebar_collapsed,
+        sanitizer: &clients.sanitizer,
+        sanitizer_profiles,
+        timezone,
     });
     Ok(HttpResponse::Ok().content_type("text/html").body(index))
 }
@@ -106,3 +158,170 @@ pub struct SubscriptionForm {
     title: String,
     url: String,
 }
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SubscriptionSettingsForm {
+    fetch_full_content: Option<String>,
+    sanitizer_profile: dto::SanitizerProfile,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SubscriptionRetentionForm {
+    #[serde(default)]
+    max_age_days: String,
+    #[serde(default)]
+    max_items: String,
+}
+
+#[get("/subscriptions/{id}/settings")]
+#[instrument(skip(clients))]
+pub async fn page_subscription_settings(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    UserIdPart(user_id): UserIdPart,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, MyError> {
+    let settings = dto::SubscriptionSettings::fetch(&user_id, *id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Subscription".to_string()))?;
+    let subscription = dto::UserSubscription::fetch(&user_id, *id, &clients.pool).await?;
+    // Items are shared, only admins pick how long they are kept
+    let retention = if user_id.is_admin(&clients.pool).await? {
+        dto::SubscriptionRetention::fetch(*id, &clients.pool).await?
+    } else {
+        None
+    };
+    let body = wrap_body(&csrf, templates::SubscriptionSettings {
+        subscription: &subscription,
+        settings: &settings,
+        retention: retention.as_ref(),
+    });
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[post("/subscriptions/{id}/settings")]
+#[instrument(skip(clients))]
+pub async fn update_subscription_settings(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    form: web::Form<SubscriptionSettingsForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let settings = dto::SubscriptionSettings {
+        subscription_id: *id,
+        fetch_full_content: form.fetch_full_content.is_some(),
+        sanitizer_profile: form.sanitizer_profile,
+    };
+    if !settings.save(&user_id, &clients.pool).await? {
+        return Err(MyError::Missing("Subscription".to_string()));
+    }
+    Ok(HttpResponse::Found()
+        .append_header(("Location", format!("/subscriptions/{}/settings", *id)))
+        .finish())
+}
+
+#[post("/subscriptions/{id}/retention")]
+#[instrument(skip(clients))]
+pub async fn update_subscription_retention(
+    clients: web::Data<Clients>,
+    id: web::Path<i64>,
+    form: web::Form<SubscriptionRetentionForm>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    if !user_id.is_admin(&clients.pool).await? {
+        return Err(MyError::Forbidden(eyre!(
+            "Only admins can change the retention"
+        )));
+    }
+    dto::SubscriptionRetention::fetch(*id, &clients.pool)
+        .await?
+        .ok_or_else(|| MyError::Missing("Subscription".to_string()))?;
+    dto::SubscriptionRetention {
+        subscription_id: *id,
+        max_age_days: optional_number(&form.max_age_days, "max_age_days")?,
+        max_items: optional_number(&form.max_items, "max_items")?,
+    }
+    .save(&clients.pool)
+    .await?;
+    Ok(HttpResponse::Found()
+        .append_header(("Location", format!("/subscriptions/{}/settings", *id)))
+        .finish())
+}
+
+/// A blank field uses the global setting
+fn optional_number(value: &str, name: &str) -> Result<Option<i64>, MyError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| MyError::BadParam(name.to_string(), "Needs to be a number".into()))
+}
+
+#[post("/subscriptions/{id}/refresh")]
+#[instrument(skip(clients, refreshes))]
+pub async fn refresh_subscription(
+    clients: web::Data<Clients>,
+    refreshes: web::Data<Refreshes>,
+    id: web::Path<i64>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let subscriptions: Vec<_> = dto::UserSubscription::fetch_all(&user_id, &clients.pool)
+        .await?
+        .into_iter()
+        .filter(|x| x.id == *id)
+        .collect();
+    if subscriptions.is_empty() {
+        return Err(MyError::Missing("Subscription".to_string()));
+    }
+    refresh(&clients, &refreshes, subscriptions).await
+}
+
+#[post("/subscriptions/refresh")]
+#[instrument(skip(clients, refreshes))]
+pub async fn refresh_all_subscriptions(
+    clients: web::Data<Clients>,
+    refreshes: web::Data<Refreshes>,
+    UserIdPart(user_id): UserIdPart,
+    _csrf: CsrfChecked,
+) -> Result<HttpResponse, MyError> {
+    let subscriptions = dto::UserSubscription::fetch_all(&user_id, &clients.pool).await?;
+    refresh(&clients, &refreshes, subscriptions).await
+}
+
+/// Polls the subscriptions not refreshed recently, answering with the htmx fragment of the result
+async fn refresh(
+    clients: &Clients,
+    refreshes: &Refreshes,
+    subscriptions: Vec<dto::UserSubscription>,
+) -> Result<HttpResponse, MyError> {
+    let subscriptions: Vec<_> = subscriptions
+        .into_iter()
+        .map(|x| dto::Subscription {
+            id: x.id,
+            rss_feed: x.rss_feed,
+        })
+        .collect();
+    let total = subscriptions.len();
+    let (subscriptions, retry_in) = refreshes.start(subscriptions);
+    let skipped = total - subscriptions.len();
+    let report = if subscriptions.is_empty() {
+        poll::PollReport::default()
+    } else {
+        poll::poll(clients, subscriptions).await?
+    };
+    let body = templates::RefreshResult {
+        report: &report,
+        refreshed: total - skipped,
+        skipped,
+        retry_in_s: retry_in.map(|x| x.as_secs() + 1).unwrap_or_default(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(body.to_string()))
+}
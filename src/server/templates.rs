@@ -1,22 +1,59 @@
 use std::collections::HashMap;
 
-use ammonia::Builder;
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
 use markup::{define, raw};
 use voca_rs::case;
 
-use crate::dto;
+use crate::{dto, highlight::mark_highlights, poll::PollReport, sanitizer::Sanitizer};
 
 use super::from_requests::user_preferences::ShowUnreads;
 
-pub fn ammonia(s: &str) -> String {
-    Builder::default()
-        .set_tag_attribute_value("img", "loading", "lazy")
-        .clean(s)
+/// The sanitized html with the highlights the user made on it marked, `full_content` when the
+/// html is the full article
+pub fn highlighted(html: &str, highlights: &[dto::ItemHighlight], full_content: bool) -> String {
+    let ranges: Vec<(usize, usize)> = highlights
+        .iter()
+        .filter(|x| x.full_content == full_content)
+        .map(|x| (x.start_offset as usize, x.end_offset as usize))
+        .collect();
+    mark_highlights(html, &ranges)
+}
+
+/// Absolute date used for the tooltip of the relative date
+pub fn absolute_time(timestamp: i64, timezone: &Tz) -> String {
+    timezone
+        .timestamp(timestamp, 0)
+        .format("%Y-%m-%d %H:%M %Z")
+        .to_string()
+}
+
+/// Day an item falls on in the user's timezone, used to group the items
+pub fn local_day(timestamp: i64, timezone: &Tz) -> String {
+    timezone
+        .timestamp(timestamp, 0)
+        .format("%A, %B %-d %Y")
         .to_string()
 }
 
+/// Short "3h ago" style of a timestamp
+pub fn relative_time(timestamp: i64, now: i64) -> String {
+    let seconds = now - timestamp;
+    if seconds < 0 {
+        return "in the future".to_string();
+    }
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3_599 => format!("{}m ago", seconds / 60),
+        3_600..=86_399 => format!("{}h ago", seconds / 3_600),
+        86_400..=2_591_999 => format!("{}d ago", seconds / 86_400),
+        2_592_000..=31_535_999 => format!("{}mo ago", seconds / 2_592_000),
+        _ => format!("{}y ago", seconds / 31_536_000),
+    }
+}
+
 define! {
-    Home<'a>(body: &'a str) {
+    Home<'a>(body: &'a str, csrf_token: &'a str) {
         @markup::doctype()
         html {
             head {
@@ -26,16 +63,38 @@ define! {
                 meta[name="viewport",content="width=device-width, initial-scale=1.0"]{}
                 link[rel="icon",href="/favicon.ico"]{}
                 link[rel="stylesheet",href="/static/styles.css"]{}
+                meta[name="csrf-token",content=csrf_token]{}
                 script[async=true,src="/static/htmx.min.js"]{}
+                script[defer=true,src="/static/keyboard.js"]{}
             }
-            body[style="margin: 0"] {
+            body[style="margin: 0", "hx-headers"=format!(r#"{{"X-CSRF-Token": "{}"}}"#, csrf_token)] {
                 @raw(body)
+                @KeyboardHelp {}
             }
         }
     }
-    Item<'a>(item: &'a dto::Item, subscription: &'a dto::UserSubscription, show_expanded: bool) {
-        div[class="article margins-off shadowed padded", id=format!("article-{}", item.id),"hx-swap"="outerHTML","hx-get"=format!("/items/partial/{}",item.id)] {
+    Item<'a>(
+        item: &'a dto::Item,
+        subscription: &'a dto::UserSubscription,
+        tags: &'a [String],
+        note: Option<&'a dto::ItemNote>,
+        highlights: &'a [dto::ItemHighlight],
+        full_content: bool,
+        archived: bool,
+        show_expanded: bool,
+        sanitizer: &'a Sanitizer,
+        sanitizer_profile: dto::SanitizerProfile,
+        timezone: &'a Tz)
+    {
+        div[
+            class=format!("article margins-off shadowed padded{}{}", if item.is_read {" article--read"} else {""}, if *show_expanded {" article--expanded"} else {""}),
+            id=format!("article-{}", item.id),
+            "data-item-id"=item.id,
+            "hx-swap"="outerHTML",
+            "hx-post"=format!("/items/partial/{}",item.id)
+        ] {
             div[class="article__subscription ellipsis"] {
+                img[class="subscription__icon", src=format!("/icons/{}", subscription.id), alt="", loading="lazy"]{}
                 @case::capitalize(&subscription.title, false)
             }
             div[class="article__comments"]{
@@ -55,15 +114,130 @@ define! {
                     @case::capitalize(&item.title, true)
                 }
             }
+            div[class="article__actions"] {
+                button[
+                    class="article__star",
+                    title="Star (s)",
+                    onclick="event.stopPropagation()",
+                    "hx-post"=format!("/items/{}/toggle_star?expanded={}", item.id, show_expanded),
+                    "hx-target"=format!("#article-{}", item.id)
+                ] {
+                    @if item.is_starred { "★" } else { "☆" }
+                }
+                button[
+                    class="article__read",
+                    title="Toggle read (m)",
+                    onclick="event.stopPropagation()",
+                    "hx-post"=format!("/items/{}/toggle_read?expanded={}", item.id, show_expanded),
+                    "hx-target"=format!("#article-{}", item.id)
+                ] {
+                    @if item.is_read { "Unread" } else { "Read" }
+                }
+                @if *show_expanded {
+                    button[
+                        class="article__full-content",
+                        title="Load the full article from the link",
+                        onclick="event.stopPropagation()",
+                        "hx-post"=format!("/items/{}/full_content", item.id),
+                        "hx-target"=format!("#article-{}", item.id)
+                    ] {
+                        "Full article"
+                    }
+                    button[
+                        class="article__archive",
+                        title="Keep a copy of the page with its images",
+                        onclick="event.stopPropagation()",
+                        "hx-post"=format!("/items/{}/archive", item.id),
+                        "hx-target"=format!("#article-{}", item.id)
+                    ] {
+                        @if *archived { "Archive again" } else { "Archive" }
+                    }
+                    @if *archived {
+                        a[href=format!("/item/{}/archive", item.id), onclick="event.stopPropagation()"] {
+                            "View archive"
+                        }
+                    }
+                }
+            }
+            div[class="article__byline ellipsis"] {
+                time[class="article__date", datetime=Utc.timestamp(item.pub_date, 0).to_rfc3339(), title=absolute_time(item.pub_date, timezone)] {
+                    @relative_time(item.pub_date, Utc::now().timestamp())
+                }
+                @if let Some(author) = &item.author {
+                    " by "
+                    span[class="article__author"] {
+                        @author
+                    }
+                }
+                @if !*show_expanded {
+                    @for tag in tags.iter() {
+                        " "
+                        span[class="article__tag"] { @tag }
+                    }
+                }
+            }
             @if *show_expanded {
+                div[class="article__tags", onclick="event.stopPropagation()"] {
+                    @for tag in tags.iter() {
+                        span[class="article__tag"] {
+                            @tag
+                            button[
+                                class="link-button",
+                                title="Remove tag",
+                                "hx-post"=format!("/items/{}/tags/delete?expanded={}", item.id, show_expanded),
+                                "hx-vals"=serde_json::json!({ "tag": tag }).to_string(),
+                                "hx-target"=format!("#article-{}", item.id)
+                            ] { "×" }
+                        }
+                        " "
+                    }
+                    form[
+                        "hx-post"=format!("/items/{}/tags?expanded={}", item.id, show_expanded),
+                        "hx-target"=format!("#article-{}", item.id)
+                    ] {
+                        input[type="text", name="tag", placeholder="Add tag", autocomplete="off", required=true]{}
+                    }
+                }
                 @if let Some(description)= &item.description {
-                    div[class="article__description shadowed padded"]{
-                        @raw(ammonia(description))
+                    @if item.contents.is_some() {
+                        div[class="article__description shadowed padded"]{
+                            @raw(sanitizer.clean(description, *sanitizer_profile))
+                        }
+                    } else {
+                        div[class="article__description article__highlightable shadowed padded"]{
+                            @raw(highlighted(&sanitizer.clean(description, *sanitizer_profile), highlights, false))
+                        }
                     }
                 }
                 @if let Some(contents) = &item.contents {
-                    div[class="article__contents shadowed padded"] {
-                        @raw(ammonia(contents))
+                    div[
+                        class="article__contents article__highlightable shadowed padded",
+                        "data-full-content"=if *full_content {"true"} else {"false"}
+                    ] {
+                        @raw(highlighted(&sanitizer.clean(contents, *sanitizer_profile), highlights, *full_content))
+                    }
+                }
+                div[class="article__notes", onclick="event.stopPropagation()"] {
+                    @for highlight in highlights.iter() {
+                        blockquote[class="article__highlight"] {
+                            @highlight.quote
+                            " "
+                            button[
+                                class="link-button",
+                                title="Remove highlight",
+                                "hx-post"=format!("/items/{}/highlights/{}/delete?expanded={}&full_content={}", item.id, highlight.id, show_expanded, full_content),
+                                "hx-target"=format!("#article-{}", item.id)
+                            ] { "×" }
+                        }
+                    }
+                    form[
+                        "hx-post"=format!("/items/{}/note?expanded={}", item.id, show_expanded),
+                        "hx-target"=format!("#article-{}", item.id)
+                    ] {
+                        textarea[name="note", placeholder="Note", rows="3"] {
+                            @note.map(|x| x.note.as_str()).unwrap_or_default()
+                        }
+                        button[type="submit"] { "Save note" }
                     }
                 }
             }
@@ -76,8 +250,13 @@ define! {
         subscription_map: HashMap<i64, &'a dto::UserSubscription>,
         subscriptions_read: HashMap<i64, String>,
         items: Vec<&'a dto::Item>,
+        tags: Vec<&'a dto::TagCount>,
+        item_tags: HashMap<i64, Vec<String>>,
         sidebar_collapsed: bool,
-        show_unreads: ShowUnreads)
+        show_unreads: ShowUnreads,
+        sanitizer: &'a Sanitizer,
+        sanitizer_profiles: HashMap<i64, dto::SanitizerProfile>,
+        timezone: Tz)
         {
         div#"all-subscriptions"[class="margins-off",style=r#"display: grid;
         background-color: lightgrey;
@@ -88,8 +267,10 @@ define! {
                 color: #d7dde4;
                 overflow-y:auto;
                 overflow-x:hidden;"#, "hx-boost"="true"] {
-                    a[href="/actions/collapse_sidebar"] {
-                        "🍔"
+                    form[action="/actions/collapse_sidebar", method="post"] {
+                        button[type="submit", class="link-button"] {
+                            "🍔"
+                        }
                     }
                     div[class="header"] {
                         input[type="text", placeholder="Search", autocomplete="off"]{}
@@ -100,19 +281,49 @@ define! {
                             a[href="/forms/rss/subscription"]{
                                 "New"
                             }
-                            a[href="/actions/filter_all_subscriptions"]{
-                                "All"
+                            a[href="/account"]{
+                                "Account"
+                            }
+                            a[href="/notes"]{
+                                "Notes"
+                            }
+                            form[action="/actions/filter_all_subscriptions", method="post"] {
+                                button[type="submit", class="link-button"] {
+                                    "All"
+                                }
                             }
                             @items.len()
                             @for subscription in subscriptions {
                                 div[class="subscription_category"] {
 
-                                    a[href=format!("/actions/filter_by_category/{}", subscription.id)]{
-                                        @case::capitalize(&subscription.title, true)
+                                    form[action=format!("/actions/filter_by_category/{}", subscription.id), method="post"] {
+                                        button[type="submit", class="link-button"] {
+                                            img[class="subscription__icon", src=format!("/icons/{}", subscription.id), alt="", loading="lazy"]{}
+                                            @case::capitalize(&subscription.title, true)
+                                        }
+                                    }
+                                    a[href=format!("/subscriptions/{}/settings", subscription.id), title="Settings"] {
+                                        "⚙"
                                     }
                                     @case::capitalize(subscriptions_read.get(&subscription.id).unwrap_or(&"?".to_string()), true)
                                 }
                             }
+                            @if !tags.is_empty() {
+                                div[class="subscription_category"] {
+                                    b { "Tags" }
+                                }
+                                @for tag in tags.iter() {
+                                    div[class="subscription_category"] {
+                                        form[action="/actions/filter_by_tag", method="post"] {
+                                            input[type="hidden", name="tag", value=&tag.tag]{}
+                                            button[type="submit", class="link-button"] {
+                                                @tag.tag
+                                            }
+                                        }
+                                        @tag.count
+                                    }
+                                }
+                            }
                         }
                     }
                 }
@@ -125,44 +336,521 @@ define! {
             {}
             "#, if *sidebar_collapsed {"grid-column-start:1;"}else {""})]{
                 @if *sidebar_collapsed {
-                    a[href="/actions/expand_sidebar", "hx-boost"="true"]{
-                        "🍔"
+                    form[action="/actions/expand_sidebar", method="post", "hx-boost"="true"] {
+                        button[type="submit", class="link-button"] {
+                            "🍔"
+                        }
                     }
                 }
-                form[action=format!("/actions/mark_all_read/{}", latest_read), method="get", "hx-boost"="true", "hx-push-url"="true"] {
+                form#"mark-all-read"[action=format!("/actions/mark_all_read/{}", latest_read), method="post", "hx-boost"="true"] {
                     button[type="submit"] {
                         "Mark All as Read"
                     }
                 }
+                p {
+                    button[type="button", "hx-post"="/subscriptions/refresh", "hx-target"="#refresh-result"] {
+                        "Refresh Everything"
+                    }
+                    " "
+                    span#"refresh-result"{}
+                }
                 @match show_unreads {
                     ShowUnreads::ShowEverything => {
-                        form[action="/actions/show_unreads", method="get", "hx-boost"="true", "hx-push-url"="true"] {
+                        form[action="/actions/show_unreads", method="post", "hx-boost"="true"] {
                             button[type="submit"] {
                                 "Show Unreads"
                             }
                         }
                     }
                     ShowUnreads::ShowUnreads => {
-                        form[action="/actions/show_everything", method="get", "hx-boost"="true", "hx-push-url"="true"] {
+                        form[action="/actions/show_everything", method="post", "hx-boost"="true"] {
                             button[type="submit"] {
                                 "Show Everything"
                             }
                         }
                     }
                 }
-                @for item in items {
+                @for (index, item) in items.iter().enumerate() {
+                    @let day = local_day(item.pub_date, timezone);
+                    @if index == 0 || local_day(items[index - 1].pub_date, timezone) != day {
+                        h3[class="articles__day"] {
+                            @day
+                        }
+                    }
                     @let subscription = subscription_map.get(&item.subscription_id).unwrap_or_else(||panic!("{}",item.subscription_id));
                     @let show_expanded =false;
+                    @let tags = item_tags.get(&item.id).map(|x| x.as_slice()).unwrap_or_default();
+                    @let sanitizer_profile = sanitizer_profiles.get(&item.subscription_id).copied().unwrap_or_default();
                     @Item{
                         item,
                         subscription,
-                        show_expanded
+                        tags,
+                        note: None,
+                        highlights: &[],
+                        full_content: false,
+                        archived: false,
+                        show_expanded,
+                        sanitizer,
+                        sanitizer_profile,
+                        timezone
+                    }
+                }
+            }
+        }
+    }
+    KeyboardHelp() {
+        div#"keyboard-help"[class="keyboard-help shadowed padded", hidden=true] {
+            b { "Keyboard shortcuts" }
+            dl {
+                dt { "j / k" } dd { "Next / previous article" }
+                dt { "o / Enter" } dd { "Expand article" }
+                dt { "m" } dd { "Toggle read" }
+                dt { "s" } dd { "Toggle star" }
+                dt { "v" } dd { "Open link in a new tab" }
+                dt { "h" } dd { "Highlight the selected text" }
+                dt { "Shift + A" } dd { "Mark all as read" }
+                dt { "?" } dd { "Toggle this help" }
+            }
+        }
+    }
+    Account<'a>(timezone: &'a str, is_admin: bool, archive_starred: bool, oidc: bool) {
+        div[class="container padded"] {
+            a[href="/"] { "Back" }
+            @if *is_admin {
+                " "
+                a[href="/admin/users"] { "Manage users" }
+            }
+            h2 { "Account" }
+            form[action="/account/timezone",method="post","hx-boost"="true","hx-push-url"="true"] {
+                label[for="timezone"]{
+                    b{
+                        "Timezone"
+                    }
+                }
+                input[type="text",placeholder="America/New_York",name="timezone",value=timezone,required=true]{}
+                button[type="submit"]{
+                    "Save"
+                }
+            }
+            h3 { "Change Password" }
+            form[action="/account/password",method="post","hx-boost"="true","hx-push-url"="true"] {
+                input[type="password",placeholder="Old Password",name="old_password",required=true]{}
+                input[type="password",placeholder="New Password",name="new_password",required=true]{}
+                input[type="password",placeholder="Confirm New Password",name="confirm_password",required=true]{}
+                button[type="submit"]{
+                    "Change Password"
+                }
+            }
+            p {
+                "Changing your password logs out all of your other "
+                a[href="/account/sessions"] { "sessions" }
+                "."
+            }
+            p {
+                a[href="/account/tokens"] { "API tokens" }
+                " let scripts and apps read and update your feeds."
+            }
+            p {
+                a[href="/account/feeds"] { "Published feeds" }
+                " share a category, your stars or a search as a feed."
+            }
+            p {
+                a[href="/rules"] { "Filter rules" }
+                " mark read, star, tag or hide new items as they come in."
+            }
+            form[action="/account/archive_starred",method="post","hx-boost"="true"] {
+                label {
+                    @if *archive_starred {
+                        input[type="checkbox",name="archive_starred",checked=true]{}
+                    } else {
+                        input[type="checkbox",name="archive_starred"]{}
+                    }
+                    " Archive the pages of starred items, with their images, in case they go away"
+                }
+                button[type="submit"] { "Save" }
+            }
+            h3 { "Fever API" }
+            p {
+                "Apps like Reeder can sync through "
+                code { "/fever/" }
+                " with your username and a separate API password."
+            }
+            form[action="/account/fever",method="post","hx-boost"="true","hx-push-url"="true"] {
+                input[type="password",placeholder="API Password",name="api_password",required=true]{}
+                button[type="submit"]{
+                    "Set API Password"
+                }
+            }
+            @if *oidc {
+                p {
+                    a[href="/login/oidc"] { "Link an SSO account" }
+                    " to log in without a password."
+                }
+            }
+            form[action="/logout",method="post","hx-boost"="true"] {
+                button[type="submit"]{
+                    "Logout"
+                }
+            }
+        }
+    }
+    AccountSessions<'a>(sessions: Vec<&'a dto::Session>, current: &'a str, timezone: &'a Tz) {
+        div[class="container padded"] {
+            a[href="/account"] { "Back" }
+            h2 { "Active Sessions" }
+            table {
+                tr {
+                    th { "Device" }
+                    th { "IP" }
+                    th { "Signed in" }
+                    th { "Last seen" }
+                    th {}
+                }
+                @for session in sessions {
+                    tr {
+                        td { @session.user_agent.as_deref().unwrap_or("Unknown") }
+                        td { @session.ip.as_deref().unwrap_or("Unknown") }
+                        td { @absolute_time(session.created_on, timezone) }
+                        td[title=absolute_time(session.last_seen, timezone)] {
+                            @relative_time(session.last_seen, Utc::now().timestamp())
+                        }
+                        td {
+                            @if session.token_hash == *current {
+                                "This session"
+                            } else {
+                                form[action=format!("/account/sessions/{}/revoke", session.id),method="post","hx-boost"="true"] {
+                                    button[type="submit"] { "Revoke" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    AccountApiTokens<'a>(tokens: Vec<&'a dto::ApiToken>, new_token: Option<&'a str>, timezone: &'a Tz) {
+        div[class="container padded"] {
+            a[href="/account"] { "Back" }
+            h2 { "API Tokens" }
+            @if let Some(new_token) = new_token {
+                p {
+                    "Copy the new token now, it will not be shown again: "
+                    code { @new_token }
+                }
+            }
+            form[action="/account/tokens",method="post","hx-boost"="true"] {
+                input[type="text",placeholder="Name",name="name",required=true]{}
+                select[name="scope"] {
+                    option[value="read"] { "Read" }
+                    option[value="write"] { "Read and write" }
+                }
+                button[type="submit"] { "Create Token" }
+            }
+            table {
+                tr {
+                    th { "Name" }
+                    th { "Scope" }
+                    th { "Created" }
+                    th { "Last used" }
+                    th {}
+                }
+                @for token in tokens {
+                    tr {
+                        td { @token.name }
+                        td { @token.scope.name() }
+                        td { @absolute_time(token.created_on, timezone) }
+                        td {
+                            @if let Some(last_used) = token.last_used {
+                                span[title=absolute_time(last_used, timezone)] {
+                                    @relative_time(last_used, Utc::now().timestamp())
+                                }
+                            } else {
+                                "Never"
+                            }
+                        }
+                        td {
+                            form[action=format!("/account/tokens/{}/revoke", token.id),method="post","hx-boost"="true"] {
+                                button[type="submit"] { "Revoke" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    AccountOutputFeeds<'a>(feeds: Vec<&'a dto::OutputFeed>, base_url: &'a str) {
+        div[class="container padded"] {
+            a[href="/account"] { "Back" }
+            h2 { "Published Feeds" }
+            p { "Anyone with the link can read the feed." }
+            form[action="/account/feeds",method="post","hx-boost"="true"] {
+                input[type="text",placeholder="Title",name="title",required=true]{}
+                select[name="kind"] {
+                    option[value="category"] { "Category" }
+                    option[value="starred"] { "Starred" }
+                    option[value="search"] { "Search" }
+                    option[value="tag"] { "Tag" }
+                }
+                input[type="text",placeholder="Category, search or tag",name="value"]{}
+                select[name="format"] {
+                    option[value="rss"] { "RSS" }
+                    option[value="atom"] { "Atom" }
+                }
+                button[type="submit"] { "Publish" }
+            }
+            table {
+                tr {
+                    th { "Title" }
+                    th { "Items" }
+                    th { "Link" }
+                    th {}
+                }
+                @for feed in feeds {
+                    tr {
+                        td { @feed.title }
+                        td {
+                            @match feed.kind {
+                                dto::OutputFeedKind::Category => { "Category " @feed.value.as_deref().unwrap_or_default() }
+                                dto::OutputFeedKind::Starred => { "Starred" }
+                                dto::OutputFeedKind::Search => { "Search " @feed.value.as_deref().unwrap_or_default() }
+                                dto::OutputFeedKind::Tag => { "Tag " @feed.value.as_deref().unwrap_or_default() }
+                            }
+                        }
+                        td {
+                            a[href=format!("{}/feeds/{}", base_url, feed.token)] {
+                                @match feed.format {
+                                    dto::FeedFormat::Rss => { "RSS" }
+                                    dto::FeedFormat::Atom => { "Atom" }
+                                }
+                            }
+                        }
+                        td {
+                            form[action=format!("/account/feeds/{}/delete", feed.id),method="post","hx-boost"="true"] {
+                                button[type="submit"] { "Delete" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    SubscriptionSettings<'a>(
+        subscription: &'a dto::UserSubscription,
+        settings: &'a dto::SubscriptionSettings,
+        retention: Option<&'a dto::SubscriptionRetention>)
+    {
+        div[class="container padded"] {
+            a[href="/"] { "Back" }
+            h2 { @subscription.title }
+            p { @subscription.rss_feed }
+            p {
+                button[type="button",
+                    "hx-post"=format!("/subscriptions/{}/refresh", subscription.id),
+                    "hx-target"="#refresh-result"
+                ] { "Refresh now" }
+                " "
+                span#"refresh-result"{}
+            }
+            form[action=format!("/subscriptions/{}/settings", subscription.id),method="post","hx-boost"="true"] {
+                label {
+                    @if settings.fetch_full_content {
+                        input[type="checkbox",name="fetch_full_content",checked=true]{}
+                    } else {
+                        input[type="checkbox",name="fetch_full_content"]{}
+                    }
+                    " Fetch the full article of new items, for feeds that only carry a link"
+                }
+                label[for="sanitizer_profile"] {
+                    b { "Allowed content" }
+                }
+                select[name="sanitizer_profile", id="sanitizer_profile"] {
+                    @for (value, profile, label) in [
+                        ("strict", dto::SanitizerProfile::Strict, "Text and images"),
+                        ("media", dto::SanitizerProfile::Media, "Also video and audio"),
+                        ("embeds", dto::SanitizerProfile::Embeds, "Also embedded players, like YouTube"),
+                    ].iter() {
+                        @if *profile == settings.sanitizer_profile {
+                            option[value=value, selected=true] { @label }
+                        } else {
+                            option[value=value] { @label }
+                        }
+                    }
+                }
+                button[type="submit"] { "Save" }
+            }
+            @if let Some(retention) = retention {
+                h3 { "Retention" }
+                p { "Shared by every subscriber. Blank uses the global setting, 0 keeps the items forever. Starred, tagged, annotated and archived items are always kept." }
+                form[action=format!("/subscriptions/{}/retention", subscription.id),method="post","hx-boost"="true"] {
+                    input[type="number",min="0",name="max_age_days",placeholder="Days to keep",value=retention.max_age_days.map(|x| x.to_string()).unwrap_or_default()]{}
+                    input[type="number",min="0",name="max_items",placeholder="Items to keep",value=retention.max_items.map(|x| x.to_string()).unwrap_or_default()]{}
+                    button[type="submit"] { "Save" }
+                }
+            }
+        }
+    }
+    RefreshResult<'a>(report: &'a PollReport, refreshed: usize, skipped: usize, retry_in_s: u64) {
+        span[class="refresh-result"] {
+            @if *refreshed == 0 && *skipped > 0 {
+                @format!("Refreshed recently, try again in {} seconds", retry_in_s)
+            } else {
+                @if report.new_items == 1 {
+                    "1 new item"
+                } else {
+                    @format!("{} new items", report.new_items)
+                }
+                @if *skipped > 0 {
+                    @format!(", {} feeds refreshed recently were skipped", skipped)
+                }
+                @for (subscription, error) in report.errors.iter() {
+                    br{}
+                    @format!("Could not refresh {}: {:#}", subscription.rss_feed, error)
+                }
+            }
+        }
+    }
+    ItemArchive<'a>(item: &'a dto::Item, archive: &'a dto::ItemArchive, html: &'a str, timezone: &'a Tz) {
+        div[class="container padded"] {
+            a[href=format!("/item/{}", item.id)] { "Back" }
+            h2 {
+                a[href=item.link.clone()] { @item.title }
+            }
+            p[class="article__byline"] {
+                "Archived "
+                time[datetime=Utc.timestamp(archive.archived_on, 0).to_rfc3339()] {
+                    @absolute_time(archive.archived_on, timezone)
+                }
+            }
+            div[class="article__contents"] {
+                @raw(html)
+            }
+        }
+    }
+    Notes<'a>(annotated: Vec<&'a crate::server::notes::AnnotatedItem>, timezone: &'a Tz) {
+        div[class="container padded"] {
+            a[href="/"] { "Back" }
+            " "
+            a[href="/notes/export.md"] { "Export as Markdown" }
+            h2 { "Notes" }
+            @if annotated.is_empty() {
+                p { "Expand an article to write a note, select text and press h to highlight it." }
+            }
+            @for entry in annotated.iter() {
+                div[class="shadowed padded"] {
+                    div {
+                        a[href=format!("/item/{}", entry.item.id)] { @entry.item.title }
+                    }
+                    div[class="article__byline"] {
+                        @entry.subscription_title
+                        ", "
+                        time[datetime=Utc.timestamp(entry.item.pub_date, 0).to_rfc3339()] {
+                            @absolute_time(entry.item.pub_date, timezone)
+                        }
+                    }
+                    @for highlight in entry.highlights.iter() {
+                        blockquote[class="article__highlight"] { @highlight.quote }
+                    }
+                    @if let Some(note) = &entry.note {
+                        p[style="white-space: pre-wrap"] { @note.note }
                     }
                 }
             }
         }
     }
-    Login() {
+    Rules<'a>(rules: Vec<&'a dto::FilterRule>, subscriptions: Vec<&'a dto::UserSubscription>, applied: Option<usize>) {
+        div[class="container padded"] {
+            a[href="/account"] { "Back" }
+            h2 { "Filter Rules" }
+            p { "Rules run on every new item. Keywords ignore case, regexes do not unless they start with (?i)." }
+            form[action="/rules",method="post","hx-boost"="true"] {
+                select[name="field"] {
+                    option[value="title"] { "Title" }
+                    option[value="author"] { "Author" }
+                    option[value="description"] { "Description" }
+                    option[value="link"] { "Link" }
+                }
+                select[name="match_kind"] {
+                    option[value="keyword"] { "contains" }
+                    option[value="regex"] { "matches regex" }
+                }
+                input[type="text",placeholder="Pattern",name="pattern",required=true]{}
+                select[name="scope"] {
+                    option[value="all"] { "In all feeds" }
+                    option[value="category"] { "In the category" }
+                    option[value="subscription"] { "In the feed" }
+                }
+                input[type="text",placeholder="Category",name="category"]{}
+                select[name="subscription_id"] {
+                    option[value=""] { "Feed" }
+                    @for subscription in subscriptions.iter() {
+                        option[value=subscription.id] { @subscription.title }
+                    }
+                }
+                select[name="action"] {
+                    option[value="read"] { "Mark read" }
+                    option[value="star"] { "Star" }
+                    option[value="tag"] { "Tag" }
+                    option[value="hide"] { "Hide" }
+                }
+                input[type="text",placeholder="Tag",name="tag"]{}
+                button[type="submit"] { "Add rule" }
+            }
+            form[action="/rules/apply",method="post","hx-boost"="true"] {
+                button[type="submit"] { "Apply to existing items" }
+                @if let Some(applied) = applied {
+                    " " @applied " items matched."
+                }
+            }
+            table {
+                tr {
+                    th { "When" }
+                    th { "Where" }
+                    th { "Then" }
+                    th {}
+                }
+                @for rule in rules {
+                    tr {
+                        td {
+                            @match rule.field {
+                                dto::RuleField::Title => { "Title" }
+                                dto::RuleField::Author => { "Author" }
+                                dto::RuleField::Description => { "Description" }
+                                dto::RuleField::Link => { "Link" }
+                            }
+                            @match rule.match_kind {
+                                dto::RuleMatch::Keyword => { " contains " }
+                                dto::RuleMatch::Regex => { " matches " }
+                            }
+                            code { @rule.pattern }
+                        }
+                        td {
+                            @match rule.scope {
+                                dto::RuleScope::All => { "All feeds" }
+                                dto::RuleScope::Category => { "Category " @rule.scope_value.as_deref().unwrap_or_default() }
+                                dto::RuleScope::Subscription => {
+                                    @subscriptions.iter().find(|x| Some(x.id.to_string()) == rule.scope_value).map(|x| x.title.as_str()).unwrap_or("Unsubscribed feed")
+                                }
+                            }
+                        }
+                        td {
+                            @match rule.action {
+                                dto::RuleAction::Read => { "Mark read" }
+                                dto::RuleAction::Star => { "Star" }
+                                dto::RuleAction::Tag => { "Tag " @rule.action_value.as_deref().unwrap_or_default() }
+                                dto::RuleAction::Hide => { "Hide" }
+                            }
+                        }
+                        td {
+                            form[action=format!("/rules/{}/delete", rule.id),method="post","hx-boost"="true"] {
+                                button[type="submit"] { "Delete" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Login(oidc: bool) {
         form[action="login",method="post","hx-boost"="true","hx-push-url"="true"] {
             div[class="container"] {
                 label[for="username"]{
@@ -181,6 +869,103 @@ define! {
                 button[type="submit"]{
                     "Login"
                 }
+                a[href="/signup"] {
+                    "Sign up"
+                }
+                @if *oidc {
+                    " "
+                    a[href="/login/oidc"] {
+                        "Login with SSO"
+                    }
+                }
+            }
+        }
+    }
+    Signup<'a>(invite: &'a str, invite_required: bool) {
+        form[action="/signup",method="post","hx-boost"="true","hx-push-url"="true"] {
+            div[class="container"] {
+                label[for="username"]{
+                    b{
+                        "Username"
+                    }
+                }
+                input[type="text",placeholder="Enter Username",name="username",required=true]{}
+                label[for="password"]{
+                    b{
+                        "Password"
+                    }
+                }
+                input[type="password",placeholder="Enter Password",name="password",required=true]{}
+                label[for="invite"]{
+                    b{
+                        "Invite Code"
+                    }
+                }
+                input[type="text",placeholder="Enter Invite Code",name="invite",value=invite,required=*invite_required]{}
+
+                button[type="submit"]{
+                    "Sign up"
+                }
+            }
+        }
+    }
+    AdminUsers<'a>(users: Vec<&'a dto::User>, invites: Vec<&'a dto::Invite>, current: &'a dto::UserId) {
+        div[class="container padded"] {
+            a[href="/account"] { "Back" }
+            h2 { "Users" }
+            table {
+                tr {
+                    th { "Username" }
+                    th { "Admin" }
+                    th { "Status" }
+                    th {}
+                }
+                @for user in users {
+                    tr {
+                        td { @user.username() }
+                        td { @if user.is_admin() { "Yes" } else { "No" } }
+                        td { @if user.disabled() { "Disabled" } else { "Active" } }
+                        td {
+                            @if user.0 != current.0 {
+                                @if user.disabled() {
+                                    form[action=format!("/admin/users/{}/enable", user.0),method="post","hx-boost"="true"] {
+                                        button[type="submit"] { "Enable" }
+                                    }
+                                } else {
+                                    form[action=format!("/admin/users/{}/disable", user.0),method="post","hx-boost"="true"] {
+                                        button[type="submit"] { "Disable" }
+                                    }
+                                }
+                                form[action=format!("/admin/users/{}/delete", user.0),method="post","hx-boost"="true","hx-confirm"="Delete this user and all of their data?"] {
+                                    button[type="submit"] { "Delete" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            h3 { "New User" }
+            form[action="/admin/users",method="post","hx-boost"="true"] {
+                input[type="text",placeholder="Enter Username",name="username",required=true]{}
+                input[type="password",placeholder="Enter Password",name="password",required=true]{}
+                label {
+                    input[type="checkbox",name="is_admin"]{}
+                    "Admin"
+                }
+                button[type="submit"] { "Create User" }
+            }
+            h3 { "Invites" }
+            form[action="/admin/invites",method="post","hx-boost"="true"] {
+                button[type="submit"] { "New Invite" }
+            }
+            ul {
+                @for invite in invites {
+                    li {
+                        a[href=format!("/signup?invite={}", invite.code)] {
+                            @invite.code
+                        }
+                    }
+                }
             }
         }
     }
@@ -215,3 +1000,28 @@ define! {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_time() {
+        let now = 1_626_309_745;
+        assert_eq!(relative_time(now, now), "just now");
+        assert_eq!(relative_time(now - 5 * 60, now), "5m ago");
+        assert_eq!(relative_time(now - 3 * 3_600, now), "3h ago");
+        assert_eq!(relative_time(now - 2 * 86_400, now), "2d ago");
+        assert_eq!(relative_time(now + 10, now), "in the future");
+    }
+
+    #[test]
+    fn test_local_day() {
+        let timestamp = 1_626_309_745; // 2021-07-15T00:42:25Z
+        assert_eq!(local_day(timestamp, &Tz::UTC), "Thursday, July 15 2021");
+        assert_eq!(
+            local_day(timestamp, &Tz::America__New_York),
+            "Wednesday, July 14 2021"
+        );
+    }
+}
@@ -1,33 +1,183 @@
-use std::{sync::Arc, time::SystemTime};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
 
-use futures::lock::Mutex;
+use chrono::Utc;
+use color_eyre::{eyre::eyre, Result};
 use lru_time_cache::LruCache;
+use sha3::{Digest, Sha3_256};
+use sqlx::SqlitePool;
+use tracing::instrument;
+use uuid::Uuid;
 
-use crate::dto::UserId;
+use crate::{
+    dto::{self, UserId},
+    settings::Settings,
+};
 
-pub type SessionMap = Arc<Mutex<LruCache<String, Session>>>;
+/// Name of the cookie holding the session id
+pub const SSID: &str = "ssid";
 
-#[derive(Debug, Clone)]
-pub struct Session {
-    user_id: UserId,
-    date: SystemTime,
+/// How long a session is trusted from the cache before going back to the database
+const CACHE_DURATION: Duration = Duration::from_secs(60);
+
+/// Sessions live in the database so they survive restarts, with a small read-through cache in
+/// front so that not every request has to go to the database.
+/// The cache is keyed by the hash of the token, the token itself is only known to the browser.
+#[derive(Clone)]
+pub struct Sessions {
+    cache: Arc<Mutex<LruCache<String, dto::Session>>>,
+    time_to_live_s: i64,
 }
 
-impl Session {
-    pub fn new(user_id: UserId) -> Self {
+impl Sessions {
+    pub fn new(settings: &Settings) -> Self {
         Self {
+            cache: Arc::new(Mutex::new(LruCache::with_expiry_duration_and_capacity(
+                CACHE_DURATION,
+                settings.max_sessions as usize,
+            ))),
+            time_to_live_s: settings.time_of_cookies_s as i64,
+        }
+    }
+
+    pub fn hash_token(token: &str) -> String {
+        let mut hasher = Sha3_256::new();
+        hasher.update(token.as_bytes());
+        hex::encode(hasher.finalize().as_slice())
+    }
+
+    fn cache(&self) -> MutexGuard<'_, LruCache<String, dto::Session>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Creates the session, returning the token to put in the cookie
+    #[instrument(skip(self, pool))]
+    pub async fn create(
+        &self,
+        user_id: &UserId,
+        user_agent: Option<&str>,
+        ip: Option<&str>,
+        pool: &SqlitePool,
+    ) -> Result<String> {
+        let token = Uuid::new_v4().to_string();
+        let csrf_token = Uuid::new_v4().to_simple().to_string();
+        dto::Session::insert(
+            &Self::hash_token(&token),
             user_id,
-            date: SystemTime::now(),
+            user_agent,
+            ip,
+            &csrf_token,
+            Utc::now().timestamp(),
+            pool,
+        )
+        .await?;
+        Ok(token)
+    }
+
+    /// The session for the token, if it exists and has not expired
+    #[instrument(skip(self, token, pool))]
+    pub async fn fetch(&self, token: &str, pool: &SqlitePool) -> Result<Option<dto::Session>> {
+        let token_hash = Self::hash_token(token);
+        if let Some(session) = self.cache().get(&token_hash) {
+            return Ok(Some(session.clone()));
         }
+        let now = Utc::now().timestamp();
+        let mut session = match dto::Session::fetch(&token_hash, pool).await? {
+            Some(session) if session.last_seen + self.time_to_live_s > now => session,
+            _ => return Ok(None),
+        };
+        dto::Session::touch(session.id, now, pool).await?;
+        session.last_seen = now;
+        self.cache().insert(token_hash, session.clone());
+        Ok(Some(session))
     }
 
-    /// Get a reference to the session's user id.
-    pub fn user_id(&self) -> &UserId {
-        &self.user_id
+    /// The session of a user authenticated by the reverse proxy, there is no cookie so there is
+    /// one session per user, created on the first request. The key can never be the hash of a
+    /// cookie token since those are hex.
+    #[instrument(skip(self, pool))]
+    pub async fn fetch_for_proxy_user(
+        &self,
+        user_id: &UserId,
+        user_agent: Option<&str>,
+        ip: Option<&str>,
+        pool: &SqlitePool,
+    ) -> Result<dto::Session> {
+        let token_hash = format!("proxy:{}", user_id.0);
+        if let Some(session) = self.cache().get(&token_hash) {
+            return Ok(session.clone());
+        }
+        let now = Utc::now().timestamp();
+        let mut session = match dto::Session::fetch(&token_hash, pool).await? {
+            Some(session) if session.last_seen + self.time_to_live_s > now => session,
+            expired => {
+                if expired.is_some() {
+                    dto::Session::delete(&token_hash, pool).await?;
+                }
+                let csrf_token = Uuid::new_v4().to_simple().to_string();
+                dto::Session::insert(&token_hash, user_id, user_agent, ip, &csrf_token, now, pool)
+                    .await?;
+                dto::Session::fetch(&token_hash, pool)
+                    .await?
+                    .ok_or_else(|| eyre!("Proxy session was not created"))?
+            }
+        };
+        dto::Session::touch(session.id, now, pool).await?;
+        session.last_seen = now;
+        self.cache().insert(token_hash, session.clone());
+        Ok(session)
+    }
+
+    #[instrument(skip(self, token, pool))]
+    pub async fn remove(&self, token: &str, pool: &SqlitePool) -> Result<()> {
+        let token_hash = Self::hash_token(token);
+        self.cache().remove(&token_hash);
+        dto::Session::delete(&token_hash, pool).await
+    }
+
+    /// Revokes one of the sessions of the user, returns false if there was no such session
+    #[instrument(skip(self, pool))]
+    pub async fn revoke(&self, user_id: &UserId, id: i64, pool: &SqlitePool) -> Result<bool> {
+        let session = match dto::Session::fetch_by_id(user_id, id, pool).await? {
+            Some(session) => session,
+            None => return Ok(false),
+        };
+        self.cache().remove(&session.token_hash);
+        dto::Session::delete(&session.token_hash, pool).await?;
+        Ok(true)
+    }
+
+    /// Drops the sessions of a user, except for the session with the `keep` token if there is one
+    #[instrument(skip(self, keep, pool))]
+    pub async fn remove_user_sessions(
+        &self,
+        user_id: &UserId,
+        keep: Option<&str>,
+        pool: &SqlitePool,
+    ) -> Result<()> {
+        let keep = keep.map(Self::hash_token);
+        dto::Session::delete_for_user(user_id, keep.as_deref(), pool).await?;
+        let mut cache = self.cache();
+        let token_hashes: Vec<String> = cache
+            .peek_iter()
+            .filter(|(token_hash, session)| {
+                &session.user_id == user_id && Some(token_hash.as_str()) != keep.as_deref()
+            })
+            .map(|(token_hash, _)| token_hash.clone())
+            .collect();
+        for token_hash in token_hashes {
+            cache.remove(&token_hash);
+        }
+        Ok(())
     }
 
-    /// Get a reference to the session's date.
-    pub fn date(&self) -> &SystemTime {
-        &self.date
+    /// Deletes the sessions that have not been seen in `time_of_cookies_s`
+    #[instrument(skip(self, pool))]
+    pub async fn remove_expired(&self, pool: &SqlitePool) -> Result<u64> {
+        dto::Session::delete_expired(Utc::now().timestamp() - self.time_to_live_s, pool).await
     }
 }
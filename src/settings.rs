@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use config::{Config, ConfigError, Environment, File};
 use serde::{Deserialize, Serialize};
 
@@ -8,6 +10,114 @@ pub struct Settings {
     pub time_of_polling_items: u64,
     pub db_name: String,
     pub secure: bool,
+    /// Anyone can sign up when set, otherwise an invite code from an admin is needed
+    #[serde(default)]
+    pub allow_signup: bool,
+    /// Reverse proxies whose `X-Forwarded-For` gives the address of the clients, see `ClientIp`
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Trust a reverse proxy to do the authentication, see `ProxyAuth`
+    #[serde(default)]
+    pub proxy_auth: Option<ProxyAuth>,
+    /// Login through an OpenID Connect provider, next to the local passwords
+    #[serde(default)]
+    pub oidc: Option<OidcSettings>,
+    /// The images of the articles are served through us, see `ImageProxy`
+    #[serde(default)]
+    pub image_proxy: ImageProxySettings,
+    #[serde(default)]
+    pub sanitizer: SanitizerSettings,
+    /// How long items are kept, see `crate::retention`
+    #[serde(default)]
+    pub retention: RetentionSettings,
+}
+
+/// Limits for every subscription, a subscription can override them. Without any limit the items
+/// are kept forever.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetentionSettings {
+    pub max_age_days: Option<i64>,
+    pub max_items_per_feed: Option<i64>,
+    /// Seconds between two prunings
+    pub interval_s: u64,
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        Self {
+            max_age_days: None,
+            max_items_per_feed: None,
+            interval_s: 6 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SanitizerSettings {
+    /// Hosts iframes may load from, for the subscriptions using the embeds profile
+    pub embed_hosts: Vec<String>,
+}
+
+impl Default for SanitizerSettings {
+    fn default() -> Self {
+        Self {
+            embed_hosts: vec![
+                "www.youtube.com".to_string(),
+                "www.youtube-nocookie.com".to_string(),
+                "player.vimeo.com".to_string(),
+            ],
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ImageProxySettings {
+    /// Signs the proxied urls, a random one is made at startup when missing
+    pub secret: Option<String>,
+    pub cache_dir: String,
+    /// The least recently used images are removed past this many bytes
+    pub max_cache_size: u64,
+    /// Images bigger than this are refused
+    pub max_image_size: usize,
+}
+
+impl Default for ImageProxySettings {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            cache_dir: "./cache/images".to_string(),
+            max_cache_size: 512 * 1024 * 1024,
+            max_image_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OidcSettings {
+    /// Where the provider serves `.well-known/openid-configuration`
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// Our `/login/oidc/callback`, as registered with the provider
+    pub redirect_url: String,
+    /// Create a user the first time an unknown identity logs in
+    #[serde(default)]
+    pub auto_provision: bool,
+}
+
+/// Authentication done by an SSO proxy in front of us, which passes the username in a header
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ProxyAuth {
+    /// Header holding the username, like `X-Forwarded-User`
+    pub header: String,
+    /// The header is only trusted on requests coming straight from one of these addresses
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Create the user the first time the proxy sends an unknown username
+    #[serde(default)]
+    pub auto_provision: bool,
 }
 
 impl Settings {
@@ -23,3 +133,18 @@ impl Settings {
         s.try_into()
     }
 }
+
+#[cfg(test)]
+impl Settings {
+    /// The defaults, without reading the config of the machine running the tests
+    pub fn for_tests() -> Self {
+        serde_json::from_value(serde_json::json!({
+            "max_sessions": 100,
+            "time_of_cookies_s": 60,
+            "time_of_polling_items": 60,
+            "db_name": "sqlite::memory:",
+            "secure": false,
+        }))
+        .unwrap()
+    }
+}
@@ -0,0 +1,167 @@
+// Keyboard navigation for the articles list, press `?` for the list of shortcuts
+(function () {
+  "use strict";
+  var selectedId = null;
+
+  function articles() {
+    return Array.prototype.slice.call(
+      document.querySelectorAll("#articles .article")
+    );
+  }
+
+  function selected() {
+    return selectedId && document.getElementById(selectedId);
+  }
+
+  function select(article) {
+    var previous = selected();
+    if (previous) {
+      previous.classList.remove("article--selected");
+    }
+    if (!article) {
+      selectedId = null;
+      return;
+    }
+    selectedId = article.id;
+    article.classList.add("article--selected");
+    article.scrollIntoView({ block: "nearest" });
+  }
+
+  function move(delta) {
+    var all = articles();
+    if (all.length === 0) {
+      return;
+    }
+    var index = all.indexOf(selected());
+    var next = index === -1 ? 0 : index + delta;
+    next = Math.max(0, Math.min(all.length - 1, next));
+    select(all[next]);
+  }
+
+  function csrfHeaders() {
+    var meta = document.querySelector('meta[name="csrf-token"]');
+    return meta ? { "X-CSRF-Token": meta.content } : {};
+  }
+
+  function post(article, action) {
+    var expanded = article.classList.contains("article--expanded");
+    htmx.ajax(
+      "POST",
+      "/items/" + article.dataset.itemId + "/" + action + "?expanded=" + expanded,
+      { source: article, target: article, headers: csrfHeaders() }
+    );
+  }
+
+  // Offsets count the text before the selection in the highlightable part of the article
+  function highlightSelection() {
+    var selection = window.getSelection();
+    if (!selection || selection.isCollapsed || selection.rangeCount === 0) {
+      return;
+    }
+    var range = selection.getRangeAt(0);
+    var node = range.commonAncestorContainer;
+    var element = node.nodeType === Node.ELEMENT_NODE ? node : node.parentElement;
+    var container = element && element.closest(".article__highlightable");
+    var article = container && container.closest(".article");
+    if (!article) {
+      return;
+    }
+    var before = document.createRange();
+    before.selectNodeContents(container);
+    before.setEnd(range.startContainer, range.startOffset);
+    var quote = range.toString();
+    var start = before.toString().length;
+    selection.removeAllRanges();
+    htmx.ajax(
+      "POST",
+      "/items/" +
+        article.dataset.itemId +
+        "/highlights?expanded=true&full_content=" +
+        (container.dataset.fullContent === "true"),
+      {
+        source: article,
+        target: article,
+        headers: csrfHeaders(),
+        values: { quote: quote, start: start, end: start + quote.length },
+      }
+    );
+  }
+
+  function toggleHelp() {
+    var help = document.getElementById("keyboard-help");
+    if (help) {
+      help.hidden = !help.hidden;
+    }
+  }
+
+  var actions = {
+    j: function () {
+      move(1);
+    },
+    k: function () {
+      move(-1);
+    },
+    o: function (article) {
+      if (article) {
+        htmx.ajax("POST", "/items/partial/" + article.dataset.itemId, {
+          source: article,
+          target: article,
+          headers: csrfHeaders(),
+        });
+      }
+    },
+    m: function (article) {
+      if (article) {
+        post(article, "toggle_read");
+      }
+    },
+    s: function (article) {
+      if (article) {
+        post(article, "toggle_star");
+      }
+    },
+    v: function (article) {
+      var link = article && article.querySelector(".article__title a");
+      if (link) {
+        window.open(link.href, "_blank", "noopener");
+      }
+    },
+    A: function () {
+      var form = document.getElementById("mark-all-read");
+      if (form) {
+        form.requestSubmit ? form.requestSubmit() : form.submit();
+      }
+    },
+    h: highlightSelection,
+    "?": toggleHelp,
+  };
+  actions.Enter = actions.o;
+
+  document.addEventListener("keydown", function (event) {
+    if (event.ctrlKey || event.metaKey || event.altKey) {
+      return;
+    }
+    var target = event.target;
+    if (
+      target &&
+      (target.isContentEditable ||
+        ["INPUT", "TEXTAREA", "SELECT"].indexOf(target.tagName) !== -1)
+    ) {
+      return;
+    }
+    var action = actions[event.key];
+    if (!action || (event.key === "Enter" && target.tagName === "BUTTON")) {
+      return;
+    }
+    event.preventDefault();
+    action(selected());
+  });
+
+  // Swapped in articles are new elements, so carry the selection over to them
+  document.addEventListener("htmx:afterSettle", function () {
+    var article = selected();
+    if (article) {
+      article.classList.add("article--selected");
+    }
+  });
+})();